@@ -0,0 +1,76 @@
+// benches/concurrency.rs - 三种并发方式的扩展性对比
+//
+// 分别用 tokio::spawn、join_all、FuturesUnordered 运行 N 个极小的异步任务，
+// 测量全部完成所需时间，N = 100、1000、10000。
+//
+// 运行：cargo bench --bench concurrency
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use futures::future::join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::hint::black_box;
+use tokio::runtime::Runtime;
+
+/// 一个极小的异步任务：让出一次执行权再返回结果
+async fn tiny_task(n: u64) -> u64 {
+    tokio::task::yield_now().await;
+    n * 2
+}
+
+/// tokio::spawn 每个任务，再依次 await JoinHandle（任务可在多个线程上并行）
+async fn with_spawn(n: u64) -> u64 {
+    let handles: Vec<_> = (0..n).map(|i| tokio::spawn(tiny_task(i))).collect();
+    let mut sum = 0;
+    for handle in handles {
+        sum += handle.await.unwrap();
+    }
+    sum
+}
+
+/// join_all：所有 Future 在当前任务内被轮询，不产生新任务
+async fn with_join_all(n: u64) -> u64 {
+    join_all((0..n).map(tiny_task)).await.into_iter().sum()
+}
+
+/// FuturesUnordered：同样在当前任务内轮询，只轮询被唤醒的 Future
+async fn with_futures_unordered(n: u64) -> u64 {
+    let mut pending: FuturesUnordered<_> = (0..n).map(tiny_task).collect();
+    let mut sum = 0;
+    while let Some(v) = pending.next().await {
+        sum += v;
+    }
+    sum
+}
+
+fn concurrency(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("concurrency");
+    
+    for n in [100u64, 1_000, 10_000] {
+        bench(&mut group, &runtime, "spawn", n, with_spawn);
+        bench(&mut group, &runtime, "join_all", n, with_join_all);
+        bench(&mut group, &runtime, "futures_unordered", n, with_futures_unordered);
+    }
+    group.finish();
+}
+
+fn bench<F, Fut>(
+    group: &mut criterion::BenchmarkGroup<'_, criterion::measurement::WallTime>,
+    runtime: &Runtime,
+    name: &str,
+    n: u64,
+    f: F,
+) where
+    F: Fn(u64) -> Fut,
+    Fut: std::future::Future<Output = u64>,
+{
+    group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+        b.to_async(runtime).iter(|| async { black_box(f(n).await) });
+    });
+}
+
+criterion_group!(benches, concurrency);
+criterion_main!(benches);
@@ -6,7 +6,11 @@
 // 3. 任务的并发执行
 // 4. 任务之间的独立性
 
-use tokio::time::{sleep, Duration};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 /// 模拟一个耗时的异步任务
 async fn async_task(id: u32, duration: u64) -> String {
@@ -55,12 +59,36 @@ async fn multiple_spawns() {
     let (result1, result2, result3) = tokio::join!(handle1, handle2, handle3);
     
     println!("\n📊 结果汇总：");
-    println!("   {}", result1.unwrap());
-    println!("   {}", result2.unwrap());
-    println!("   {}", result3.unwrap());
+    for result in [result1, result2, result3] {
+        match result {
+            Ok(message) => println!("   {}", message),
+            Err(e) => println!("   ❌ {}", describe_join_error(e)),
+        }
+    }
     println!("   ⏱️  总耗时: {:.1} 秒（并发执行）\n", start.elapsed().as_secs_f64());
 }
 
+/// 把 JoinError 转成可读的说明：区分取消和 panic，并尽量取出 panic 信息
+fn describe_join_error(e: tokio::task::JoinError) -> String {
+    if e.is_cancelled() {
+        return "cancelled".to_string();
+    }
+    // panic 的载荷通常是 &str（字面量）或 String（格式化信息）
+    let payload = e.into_panic();
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        format!("panicked: {}", msg)
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        format!("panicked: {}", msg)
+    } else {
+        "panicked: <非字符串载荷>".to_string()
+    }
+}
+
+/// 等待任务完成；失败时返回说明是被取消还是 panic（含 panic 信息）
+async fn join_reporting<T>(h: JoinHandle<T>) -> Result<T, String> {
+    h.await.map_err(describe_join_error)
+}
+
 /// 演示任务中的错误处理
 async fn error_handling() {
     println!("=== 3. 任务错误处理 ===");
@@ -79,6 +107,25 @@ async fn error_handling() {
         Ok(Err(e)) => println!("⚠️  任务返回错误: {}", e),
         Err(e) => println!("❌ 任务 panic: {:?}", e),
     }
+    
+    // panic 和取消都会表现为 JoinError，join_reporting 把两者区分开
+    let panicking = tokio::spawn(async {
+        let user_id = 42;
+        if user_id > 0 {
+            panic!("用户 {} 的数据损坏", user_id);
+        }
+    });
+    match join_reporting(panicking).await {
+        Ok(()) => println!("✅ 任务成功"),
+        Err(e) => println!("💥 panic 的任务: {}", e),
+    }
+    
+    let sleeping = tokio::spawn(sleep(Duration::from_secs(10)));
+    sleeping.abort();
+    match join_reporting(sleeping).await {
+        Ok(()) => println!("✅ 任务成功"),
+        Err(e) => println!("🛑 被取消的任务: {}", e),
+    }
     println!();
 }
 
@@ -149,6 +196,448 @@ async fn blocking_task() {
     println!("✅ 计算完成，结果: {}\n", result);
 }
 
+/// CPU 密集循环的三种写法
+#[derive(Debug, Clone, Copy)]
+enum CpuLoopMode {
+    /// 直接在异步任务里跑，从不让出（错误示范）
+    Blocking,
+    /// 每算一小段就 yield_now().await，把执行权交还给调度器
+    Yielding,
+    /// 整段计算交给 spawn_blocking 线程池
+    SpawnBlocking,
+}
+
+/// 忙算大约 dur 时长
+fn busy_work(dur: Duration) -> u64 {
+    let start = std::time::Instant::now();
+    let mut sum = 0u64;
+    while start.elapsed() < dur {
+        sum = sum.wrapping_add(1);
+    }
+    sum
+}
+
+/// 在单线程运行时里，一边跑 200ms 的 CPU 循环，一边跑每 10ms 触发一次的计时器
+///
+/// 返回循环期间计时器实际触发的次数。
+async fn ticks_during_cpu_loop(mode: CpuLoopMode) -> u32 {
+    let ticks = Arc::new(AtomicU32::new(0));
+    let counter = ticks.clone();
+    let ticker = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(10));
+        loop {
+            interval.tick().await;
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    
+    match mode {
+        CpuLoopMode::Blocking => {
+            for _ in 0..200 {
+                busy_work(Duration::from_millis(1));
+            }
+        }
+        CpuLoopMode::Yielding => {
+            for _ in 0..200 {
+                busy_work(Duration::from_millis(1));
+                tokio::task::yield_now().await;
+            }
+        }
+        CpuLoopMode::SpawnBlocking => {
+            let _ = tokio::task::spawn_blocking(|| busy_work(Duration::from_millis(200))).await;
+        }
+    }
+    
+    let fired = ticks.load(Ordering::Relaxed);
+    ticker.abort();
+    fired
+}
+
+/// 演示阻塞执行器的后果：同一线程上的计时器被饿死
+async fn cooperative_yield_demo() {
+    println!("=== 7. 不要阻塞执行器 ===");
+    println!("📝 单线程运行时中，200ms 的 CPU 循环 + 每 10ms 一次的计时器\n");
+    
+    for mode in [CpuLoopMode::Blocking, CpuLoopMode::Yielding, CpuLoopMode::SpawnBlocking] {
+        // 每种写法都放到独立的单线程运行时里，排除其他工作线程的干扰
+        let fired = tokio::task::spawn_blocking(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .unwrap();
+            runtime.block_on(ticks_during_cpu_loop(mode))
+        })
+        .await
+        .unwrap();
+        
+        let verdict = if fired == 0 { "❌ 计时器被饿死" } else { "✅ 计时器正常触发" };
+        println!("   {:?}: 计时器触发 {} 次 {}", mode, fired, verdict);
+    }
+    println!();
+}
+
+// === 8. 两级超时：软取消 + 替换工作者 ===
+
+/// 一个任务最终触发到哪一级超时
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escalation {
+    /// 在软超时之前完成
+    None,
+    /// 软超时：取消令牌已触发，处理函数配合退出
+    Soft,
+    /// 硬超时：处理函数无视取消，工作者被放弃并由新工作者替换
+    Hard,
+}
+
+/// 每个任务的处理结果；硬超时的任务没有输出
+#[derive(Debug)]
+struct JobReport {
+    job: u32,
+    escalation: Escalation,
+    output: Option<String>,
+}
+
+/// 工作者当前正在处理的任务
+struct Busy {
+    job: u32,
+    since: Instant,
+    token: CancellationToken,
+    soft_fired: bool,
+}
+
+/// 工作者与监督者共享的状态；两者都在同一把锁下判断任务归谁上报
+#[derive(Default)]
+struct SlotState {
+    busy: Option<Busy>,
+    retired: bool,
+}
+
+type JobQueue = Arc<tokio::sync::Mutex<tokio::sync::mpsc::Receiver<u32>>>;
+type Reports = tokio::sync::mpsc::UnboundedSender<JobReport>;
+
+/// 启动一个工作者：取任务、登记到 slot、处理，完成后上报
+///
+/// 如果监督者已经因硬超时把它退役，结果直接丢弃并退出，因为替换者已经接手。
+fn spawn_supervised_worker<F, Fut>(
+    slot: Arc<Mutex<SlotState>>,
+    jobs: JobQueue,
+    handler: Arc<F>,
+    reports: Reports,
+) -> JoinHandle<()>
+where
+    F: Fn(u32, CancellationToken) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = String> + Send,
+{
+    tokio::spawn(async move {
+        loop {
+            let job = jobs.lock().await.recv().await;
+            let Some(job) = job else { break };
+            let token = CancellationToken::new();
+            slot.lock().unwrap().busy = Some(Busy { job, since: Instant::now(), token: token.clone(), soft_fired: false });
+            
+            let output = handler(job, token).await;
+            
+            let busy = {
+                let mut state = slot.lock().unwrap();
+                if state.retired {
+                    println!("   🧟 被替换的工作者终于完成任务 {}，结果丢弃", job);
+                    break;
+                }
+                state.busy.take()
+            };
+            let escalation = if busy.is_some_and(|b| b.soft_fired) { Escalation::Soft } else { Escalation::None };
+            let _ = reports.send(JobReport { job, escalation, output: Some(output) });
+        }
+    })
+}
+
+/// 带两级超时的工作者池
+///
+/// 监督线程定期检查每个工作者：任务超过 soft 就触发它的取消令牌（协作式取消）；
+/// 超过 hard 仍未结束，说明处理函数无视取消（例如卡在同步循环里），
+/// 这时 abort 也无济于事，只能放弃这个工作者，上报 Hard 并启动一个替换者。
+/// 返回任务发送端、结果接收端，以及累计替换的工作者数量。
+fn supervised_pool<F, Fut>(
+    num_workers: usize,
+    soft: Duration,
+    hard: Duration,
+    handler: F,
+) -> (tokio::sync::mpsc::Sender<u32>, tokio::sync::mpsc::UnboundedReceiver<JobReport>, Arc<AtomicU32>)
+where
+    F: Fn(u32, CancellationToken) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = String> + Send + 'static,
+{
+    let (job_tx, job_rx) = tokio::sync::mpsc::channel(16);
+    let (report_tx, report_rx) = tokio::sync::mpsc::unbounded_channel();
+    let jobs: JobQueue = Arc::new(tokio::sync::Mutex::new(job_rx));
+    let handler = Arc::new(handler);
+    let replaced = Arc::new(AtomicU32::new(0));
+    
+    let mut workers: Vec<_> = (0..num_workers)
+        .map(|_| {
+            let slot = Arc::new(Mutex::new(SlotState::default()));
+            let handle = spawn_supervised_worker(slot.clone(), jobs.clone(), handler.clone(), report_tx.clone());
+            (slot, handle)
+        })
+        .collect();
+    
+    // 监督者跑在独立的 OS 线程上：处理函数阻塞住运行时线程时，它依然能按时检查
+    let runtime = tokio::runtime::Handle::current();
+    let replaced_count = replaced.clone();
+    std::thread::spawn(move || {
+        let _guard = runtime.enter(); // 让替换者可以直接用 tokio::spawn 启动
+        // 所有工作者都正常退出（任务队列已关闭）后监督者结束
+        while workers.iter().any(|(_, handle)| !handle.is_finished()) {
+            std::thread::sleep(soft / 4);
+            for (slot, handle) in workers.iter_mut() {
+                let mut state = slot.lock().unwrap();
+                let Some(busy) = state.busy.as_mut() else { continue };
+                let elapsed = busy.since.elapsed();
+                if elapsed >= hard {
+                    let job = busy.job;
+                    state.retired = true;
+                    state.busy = None;
+                    drop(state);
+                    handle.abort(); // 若处理函数之后还会 await，可以尽早释放
+                    // 先计数再上报：收到 Hard 报告或替换者的结果时，计数一定已经更新
+                    replaced_count.fetch_add(1, Ordering::Relaxed);
+                    let _ = report_tx.send(JobReport { job, escalation: Escalation::Hard, output: None });
+                    println!("   🔁 任务 {} 超过硬超时，替换工作者", job);
+                    
+                    *slot = Arc::new(Mutex::new(SlotState::default()));
+                    *handle = spawn_supervised_worker(slot.clone(), jobs.clone(), handler.clone(), report_tx.clone());
+                } else if elapsed >= soft && !busy.soft_fired {
+                    busy.soft_fired = true;
+                    busy.token.cancel();
+                    println!("   ✋ 任务 {} 超过软超时，请求取消", busy.job);
+                }
+            }
+        }
+    });
+    
+    (job_tx, report_rx, replaced)
+}
+
+/// 演示两级超时：快任务正常完成，配合取消的任务在软超时退出，
+/// 无视取消的阻塞任务触发硬超时，之后的任务由替换者处理
+async fn escalating_timeout_demo() {
+    println!("=== 8. 两级超时：软取消 + 替换工作者 ===");
+    println!("📝 软超时 100ms，硬超时 300ms；任务 3 在同步循环里阻塞 600ms 且无视取消\n");
+    
+    // 阻塞的处理函数会占住一个运行时线程，所以放到多线程运行时里，保证监督者还能运行
+    let (reports, replaced) = tokio::task::spawn_blocking(|| {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .enable_time()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (jobs, mut reports, replaced) = supervised_pool(
+                2,
+                Duration::from_millis(100),
+                Duration::from_millis(300),
+                |job, token: CancellationToken| async move {
+                    match job {
+                        2 => tokio::select! {
+                            _ = token.cancelled() => "收到取消，提前退出".to_string(),
+                            _ = sleep(Duration::from_secs(1)) => "慢慢完成".to_string(),
+                        },
+                        3 => {
+                            std::thread::sleep(Duration::from_millis(600));
+                            "终于算完".to_string()
+                        }
+                        _ => {
+                            sleep(Duration::from_millis(20)).await;
+                            "完成".to_string()
+                        }
+                    }
+                },
+            );
+            
+            for job in 1..=3 {
+                jobs.send(job).await.unwrap();
+            }
+            // 等任务 3 触发硬超时后再提交任务 4
+            sleep(Duration::from_millis(400)).await;
+            jobs.send(4).await.unwrap();
+            drop(jobs);
+            
+            let mut collected = Vec::new();
+            while collected.len() < 4 {
+                match reports.recv().await {
+                    Some(report) => collected.push(report),
+                    None => break,
+                }
+            }
+            // 等被替换的工作者醒来，确认它的结果被丢弃
+            sleep(Duration::from_millis(300)).await;
+            (collected, replaced.load(Ordering::Relaxed))
+        })
+    })
+    .await
+    .unwrap();
+    
+    for report in &reports {
+        println!("   📋 任务 {}: {:?}，输出 {:?}", report.job, report.escalation, report.output);
+    }
+    let hard = reports.iter().any(|r| r.job == 3 && r.escalation == Escalation::Hard);
+    println!("   {} 任务 3 触发硬超时，替换了 {} 个工作者\n", if hard && replaced == 1 { "✅" } else { "❌" }, replaced);
+}
+
+// === 9. JoinSet：数量在运行时才确定的任务 ===
+
+/// 演示 JoinSet：动态数量的任务按完成顺序收集结果，以及用 abort_all 提前结束
+async fn join_set_demo() {
+    println!("=== 9. JoinSet：数量在运行时才确定的任务 ===");
+    
+    // 任务数量和耗时来自运行时数据，没法写成固定个数的 join!
+    let durations: Vec<u64> = vec![3, 1, 2, 1];
+    println!("📝 根据配置启动 {} 个任务，按完成顺序收集结果\n", durations.len());
+    
+    let start = std::time::Instant::now();
+    let mut set = tokio::task::JoinSet::new();
+    for (i, duration) in durations.iter().enumerate() {
+        set.spawn(async_task(i as u32 + 1, *duration));
+    }
+    
+    let mut order = Vec::new();
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(message) => order.push(message),
+            Err(e) => println!("   ❌ {}", describe_join_error(e)),
+        }
+    }
+    println!("\n📊 完成顺序: {:?}", order);
+    println!("   ⏱️  总耗时: {:.1} 秒（由最慢的任务决定）\n", start.elapsed().as_secs_f64());
+    
+    // 拿到第一个结果就够了：abort_all 取消其余任务
+    println!("📝 同时启动 3 个任务，第一个完成后 abort_all 取消其余\n");
+    let mut set = tokio::task::JoinSet::new();
+    for (id, duration) in [(11, 1), (12, 3), (13, 3)] {
+        set.spawn(async_task(id, duration));
+    }
+    if let Some(Ok(first)) = set.join_next().await {
+        println!("   🏁 第一个结果: {}", first);
+    }
+    set.abort_all();
+    
+    let mut cancelled = 0;
+    while let Some(res) = set.join_next().await {
+        if let Err(e) = res {
+            println!("   🛑 {}", describe_join_error(e));
+            cancelled += 1;
+        }
+    }
+    println!("   {} 其余 {} 个任务被取消，JoinSet 已清空: {}\n",
+             if cancelled == 2 && set.is_empty() { "✅" } else { "❌" }, cancelled, set.is_empty());
+}
+
+// === 10. task-local：跟随任务而不是线程的变量 ===
+
+tokio::task_local! {
+    /// 当前任务正在处理的请求 id，由 scope() 设置
+    static REQUEST_ID: u32;
+}
+
+/// 调用链最深处的日志函数：直接读取 task-local，调用者不需要把 id 一层层传下来
+async fn log_step(step: &str) -> String {
+    let id = REQUEST_ID.get();
+    println!("   [请求 {}] {}", id, step);
+    format!("{}:{}", id, step)
+}
+
+async fn load_user() -> Vec<String> {
+    let mut log = vec![log_step("查询用户").await];
+    sleep(Duration::from_millis(50)).await;
+    log.push(log_step("查询权限").await);
+    log
+}
+
+async fn handle_request() -> Vec<String> {
+    let mut log = vec![log_step("开始处理").await];
+    log.extend(load_user().await);
+    sleep(Duration::from_millis(30)).await;
+    log.push(log_step("返回响应").await);
+    log
+}
+
+/// 演示 task-local：两个并发任务各自设置请求 id，调用链深处读到的都是自己的值
+async fn task_local_demo() {
+    println!("=== 10. task-local：跟随任务而不是线程的变量 ===");
+    println!("📝 两个任务并发处理请求 101 和 202，helper 函数没有 id 参数\n");
+    
+    let a = tokio::spawn(REQUEST_ID.scope(101, handle_request()));
+    let b = tokio::spawn(REQUEST_ID.scope(202, handle_request()));
+    let (a, b) = (a.await.unwrap(), b.await.unwrap());
+    
+    let own = |log: &[String], id: &str| log.iter().all(|line| line.starts_with(id));
+    println!("\n   {} 交错执行的两个任务各自只看到自己的请求 id", if own(&a, "101:") && own(&b, "202:") { "✅" } else { "❌" });
+    println!("   📌 scope 之外读取会失败: try_with = {:?}", REQUEST_ID.try_with(|id| *id).ok());
+    
+    println!("\n💡 与 thread_local! 的区别：");
+    println!("   • 多线程运行时会在 .await 之后把任务挪到别的工作线程，thread_local 的值随之“换人”");
+    println!("   • 同一个线程上还会交替运行许多任务，thread_local 会被它们共享、互相覆盖");
+    println!("   • task_local 的值保存在任务的 Future 里，跟着任务走，只在 scope 期间有效\n");
+}
+
+// === 11. 容错的批量 spawn ===
+
+/// 把每个 Future 都 spawn 出去并全部等待，结果按输入顺序返回
+///
+/// 某个任务 panic 不会影响其他任务，也不会让调用者 panic：它对应的位置是一个 JoinError，
+/// 调用者可以用 is_panic() / is_cancelled() 判断原因。
+async fn spawn_collect<T, F>(tasks: Vec<F>) -> Vec<Result<T, tokio::task::JoinError>>
+where
+    F: std::future::Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    // 先全部 spawn，再依次等待：任务之间并发执行
+    let handles: Vec<JoinHandle<T>> = tasks.into_iter().map(tokio::spawn).collect();
+    let mut results = Vec::with_capacity(handles.len());
+    for handle in handles {
+        results.push(handle.await);
+    }
+    results
+}
+
+/// 模拟一批上传：第 2 个返回业务错误，第 3 个 panic
+async fn upload(id: u32) -> Result<String, String> {
+    sleep(Duration::from_millis(100 * id as u64)).await;
+    match id {
+        2 => Err(format!("文件 {} 校验失败", id)),
+        3 => panic!("文件 {} 的缓冲区越界", id),
+        _ => Ok(format!("文件 {} 上传完成", id)),
+    }
+}
+
+async fn spawn_collect_demo() {
+    println!("=== 11. 容错的批量 spawn ===");
+    println!("📝 4 个上传任务：一个返回 Err，一个 panic，其余成功\n");
+    
+    let results = spawn_collect((1..=4).map(upload).collect()).await;
+    
+    let (mut ok, mut failed, mut panicked) = (0, 0, 0);
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(Ok(msg)) => {
+                ok += 1;
+                println!("   ✅ 任务 {}: {}", i + 1, msg);
+            }
+            Ok(Err(e)) => {
+                failed += 1;
+                println!("   ⚠️  任务 {}: 业务错误 - {}", i + 1, e);
+            }
+            Err(e) if e.is_panic() => {
+                panicked += 1;
+                println!("   💥 任务 {}: panic - {}", i + 1, describe_join_error(e));
+            }
+            Err(e) => println!("   🛑 任务 {}: {}", i + 1, describe_join_error(e)),
+        }
+    }
+    println!("   {} 成功 {}、业务错误 {}、panic {}，一个任务 panic 没有影响其余任务\n",
+             if (ok, failed, panicked) == (2, 1, 1) { "✅" } else { "❌" }, ok, failed, panicked);
+}
+
 #[tokio::main]
 async fn main() {
     println!("🎓 Tokio Spawn 与并发任务教程\n");
@@ -160,6 +649,11 @@ async fn main() {
     spawn_vs_await().await;
     task_cancellation().await;
     blocking_task().await;
+    cooperative_yield_demo().await;
+    escalating_timeout_demo().await;
+    join_set_demo().await;
+    task_local_demo().await;
+    spawn_collect_demo().await;
     
     println!("🎉 教程完成！\n");
     println!("💡 关键要点：");
@@ -167,7 +661,78 @@ async fn main() {
     println!("   • spawn 的任务在后台并发执行");
     println!("   • 使用 JoinHandle.await 等待任务完成并获取结果");
     println!("   • JoinHandle.abort() 可以取消任务");
+    println!("   • JoinError 区分 panic 与取消，into_panic() 可取出 panic 信息");
     println!("   • spawn_blocking 用于执行阻塞的同步代码");
+    println!("   • 异步任务里的长循环要么 yield_now，要么交给 spawn_blocking");
+    println!("   • 无视取消的任务无法被 abort，只能放弃它的工作者并替换");
+    println!("   • JoinSet 管理数量不固定的任务，join_next 按完成顺序取结果");
+    println!("   • task_local! 让上下文跟着任务走，不必层层传参");
+    println!("   • 批量 spawn 时逐个保留 JoinError，单个任务 panic 不会拖垮整批");
     println!("   • spawn 的任务必须是 'static 生命周期");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // #[tokio::test] 默认使用单线程运行时，计时器和 CPU 循环共享同一个线程
+    #[tokio::test]
+    async fn ticker_keeps_firing_only_when_cpu_loop_yields() {
+        assert_eq!(ticks_during_cpu_loop(CpuLoopMode::Blocking).await, 0, "从不让出时计时器应被饿死");
+        
+        let yielding = ticks_during_cpu_loop(CpuLoopMode::Yielding).await;
+        assert!(yielding >= 5, "yield_now 版本中计时器只触发了 {} 次", yielding);
+        
+        let offloaded = ticks_during_cpu_loop(CpuLoopMode::SpawnBlocking).await;
+        assert!(offloaded >= 5, "spawn_blocking 版本中计时器只触发了 {} 次", offloaded);
+    }
+    
+    #[tokio::test]
+    async fn join_reporting_distinguishes_panic_from_cancellation() {
+        let ok = tokio::spawn(async { 42 });
+        assert_eq!(join_reporting(ok).await, Ok(42));
+        
+        let panicked = tokio::spawn(async {
+            let divisor = 0;
+            if divisor == 0 {
+                panic!("除数为 {}", divisor);
+            }
+        });
+        let err = join_reporting(panicked).await.unwrap_err();
+        assert!(err.contains("除数为 0"), "{}", err);
+        
+        let cancelled = tokio::spawn(sleep(Duration::from_secs(60)));
+        cancelled.abort();
+        assert_eq!(join_reporting(cancelled).await, Err("cancelled".to_string()));
+    }
+    
+    // 阻塞的处理函数会占住一个运行时线程，需要多线程运行时让替换者有线程可用
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn blocking_handler_escalates_to_hard_timeout_and_is_replaced() {
+        let (jobs, mut reports, replaced) = supervised_pool(
+            1,
+            Duration::from_millis(50),
+            Duration::from_millis(150),
+            |job, _token: CancellationToken| async move {
+                if job == 1 {
+                    // 无视取消，卡在同步代码里
+                    std::thread::sleep(Duration::from_millis(400));
+                }
+                format!("任务 {} 完成", job)
+            },
+        );
+        jobs.send(1).await.unwrap();
+        jobs.send(2).await.unwrap();
+        
+        let first = reports.recv().await.unwrap();
+        assert_eq!((first.job, first.escalation), (1, Escalation::Hard));
+        assert!(first.output.is_none());
+        
+        // 唯一的工作者还卡着，任务 2 只能由替换者完成
+        let second = reports.recv().await.unwrap();
+        assert_eq!((second.job, second.escalation), (2, Escalation::None));
+        assert_eq!(second.output.as_deref(), Some("任务 2 完成"));
+        assert_eq!(replaced.load(Ordering::Relaxed), 1);
+    }
+}
@@ -6,8 +6,15 @@
 // 3. 任务取消和清理
 // 4. 并发模式的实际应用
 
-use tokio::time::{sleep, Duration, timeout};
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+use futures::stream::{self, FuturesUnordered, StreamExt};
+use tokio::sync::{mpsc, oneshot, Semaphore, SemaphorePermit};
+use tokio::time::{sleep, Duration, Instant, timeout};
 use tokio::select;
+use tokio_util::sync::CancellationToken;
 
 /// 模拟不同速度的异步任务
 async fn fast_task() -> &'static str {
@@ -70,10 +77,12 @@ async fn select_multiple_branches() {
     println!("=== 3. select! 多分支处理 ===");
     
     let mut count = 0;
+    // 定时器放在循环外：每次循环都新建 sleep 的话，它永远赢不过 50ms 的分支
+    let mut ticker = tokio::time::interval(Duration::from_millis(100));
     
     loop {
         select! {
-            _ = sleep(Duration::from_millis(100)) => {
+            _ = ticker.tick() => {
                 count += 1;
                 println!("   ⏰ 定时器触发 (第 {} 次)", count);
                 if count >= 3 {
@@ -178,8 +187,6 @@ async fn cancellation_safety() {
 
 /// 演示 FuturesUnordered - 处理动态数量的任务
 async fn futures_unordered_demo() {
-    use futures::stream::{FuturesUnordered, StreamExt};
-    
     println!("=== 7. FuturesUnordered（动态任务集合）===");
     println!("📝 按完成顺序处理多个 Future\n");
     
@@ -204,6 +211,724 @@ async fn async_task_with_delay(name: &str, seconds: u64) -> String {
     format!("{} 完成！", name)
 }
 
+/// 带指数退避的条件重试
+///
+/// 只有 should_retry 判定为“可重试”的错误才会重试，
+/// 永久性错误立即返回，不浪费剩余的重试次数。
+async fn retry_if<T, E, F, Fut, P>(
+    attempts: u32,
+    base: Duration,
+    mut op: F,
+    should_retry: P,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    P: Fn(&E) -> bool,
+{
+    let mut delay = base;
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < attempts && should_retry(&e) => {
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum FetchError {
+    /// 临时错误（如超时），值得重试
+    Transient,
+    /// 永久错误（如 404），重试也没用
+    Fatal,
+}
+
+/// 演示只对临时错误重试
+async fn retry_if_demo() {
+    println!("=== 8. 条件重试（retry_if）===");
+    println!("📝 临时错误按 100ms、200ms... 退避重试，永久错误立即失败\n");
+    
+    let mut calls = 0;
+    let result = retry_if(
+        5,
+        Duration::from_millis(100),
+        || {
+            calls += 1;
+            let n = calls;
+            async move {
+                if n < 3 { Err(FetchError::Transient) } else { Ok("数据") }
+            }
+        },
+        |e| matches!(e, FetchError::Transient),
+    )
+    .await;
+    println!("🔁 临时错误场景: {:?}，共尝试 {} 次", result, calls);
+    if matches!(result, Ok("数据")) && calls == 3 {
+        println!("   ✅ 两次临时错误后第 3 次成功");
+    } else {
+        println!("   ❌ 预期第 3 次尝试成功");
+    }
+    
+    let mut calls = 0;
+    let result: Result<&str, _> = retry_if(
+        5,
+        Duration::from_millis(100),
+        || {
+            calls += 1;
+            async { Err(FetchError::Fatal) }
+        },
+        |e| matches!(e, FetchError::Transient),
+    )
+    .await;
+    println!("🛑 永久错误场景: {:?}，共尝试 {} 次", result, calls);
+    if matches!(result, Err(FetchError::Fatal)) && calls == 1 {
+        println!("   ✅ 永久错误只尝试 1 次就返回，没有浪费重试次数\n");
+    } else {
+        println!("   ❌ 永久错误应当只尝试 1 次\n");
+    }
+}
+
+/// 并发映射：最多同时运行 concurrency 个 f(item)，按完成顺序收集结果
+///
+/// token 被取消时立即返回已完成的结果；尚未完成的 Future 随
+/// FuturesUnordered 一起被 drop，不会再继续执行。
+async fn parallel_map<T, R, F, Fut>(
+    items: Vec<T>,
+    concurrency: usize,
+    f: F,
+    token: CancellationToken,
+) -> Vec<R>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let mut pending = items.into_iter();
+    let mut in_flight = FuturesUnordered::new();
+    let mut results = Vec::new();
+    
+    loop {
+        while in_flight.len() < concurrency.max(1) {
+            match pending.next() {
+                Some(item) => in_flight.push(f(item)),
+                None => break,
+            }
+        }
+        
+        if in_flight.is_empty() {
+            break;
+        }
+        
+        select! {
+            biased;
+            _ = token.cancelled() => break,
+            Some(result) = in_flight.next() => results.push(result),
+        }
+    }
+    
+    results
+}
+
+/// 演示可取消的 parallel_map
+async fn parallel_map_demo() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    
+    println!("=== 9. 可取消的 parallel_map ===");
+    println!("📝 10 个 300ms 的任务，并发 3，700ms 后取消\n");
+    
+    let token = CancellationToken::new();
+    let finished = Arc::new(AtomicUsize::new(0));
+    
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(700)).await;
+        println!("   🛑 取消 parallel_map");
+        canceller.cancel();
+    });
+    
+    let counter = finished.clone();
+    let results = parallel_map(
+        (1..=10).collect(),
+        3,
+        |n: u32| {
+            let counter = counter.clone();
+            async move {
+                sleep(Duration::from_millis(300)).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+                n * n
+            }
+        },
+        token,
+    )
+    .await;
+    
+    println!("   📦 取消前完成的结果: {:?}", results);
+    let at_cancel = finished.load(Ordering::SeqCst);
+    sleep(Duration::from_millis(500)).await;
+    let after_wait = finished.load(Ordering::SeqCst);
+    let ok = results.len() < 10 && at_cancel == after_wait;
+    println!(
+        "   {} 返回 {} 个结果；取消时完成 {} 个，再等 500ms 后是 {} 个（没有任务在后台继续跑）\n",
+        if ok { "✅" } else { "❌" },
+        results.len(),
+        at_cancel,
+        after_wait
+    );
+}
+
+/// 长连接主循环：处理消息、空闲时发送 keepalive ping、响应关闭信号
+///
+/// ping 定时器在循环外创建，收到任何消息都会把它重置，
+/// 所以只有连接真正空闲了 idle 时长才会发 ping。返回发送的 ping 数。
+async fn connection_loop(
+    mut incoming: mpsc::Receiver<String>,
+    outgoing: mpsc::Sender<String>,
+    idle: Duration,
+    mut shutdown: oneshot::Receiver<()>,
+) -> u32 {
+    let mut pings = 0;
+    let ping_timer = sleep(idle);
+    tokio::pin!(ping_timer);
+    
+    loop {
+        select! {
+            msg = incoming.recv() => match msg {
+                Some(msg) => {
+                    println!("   📨 收到: {}", msg);
+                    ping_timer.as_mut().reset(Instant::now() + idle);
+                }
+                None => break, // 对端关闭
+            },
+            _ = &mut ping_timer => {
+                pings += 1;
+                if outgoing.send("PING".to_string()).await.is_err() {
+                    break;
+                }
+                println!("   🏓 空闲 {}ms，发送 PING", idle.as_millis());
+                ping_timer.as_mut().reset(Instant::now() + idle);
+            }
+            _ = &mut shutdown => {
+                println!("   🛑 收到关闭信号");
+                break;
+            }
+        }
+    }
+    
+    pings
+}
+
+/// 演示 keepalive：空闲时发 ping，有流量时不发
+async fn keepalive_demo() {
+    println!("=== 10. 连接 keepalive 循环 ===");
+    println!("📝 空闲阈值 100ms，每个场景运行 350ms\n");
+    
+    for (label, traffic_every) in [("无流量", None), ("每 50ms 一条消息", Some(50))] {
+        println!("🔌 场景: {}", label);
+        let (in_tx, in_rx) = mpsc::channel(16);
+        let (out_tx, mut out_rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        
+        let conn = tokio::spawn(connection_loop(in_rx, out_tx, Duration::from_millis(100), shutdown_rx));
+        tokio::spawn(async move { while out_rx.recv().await.is_some() {} });
+        
+        let deadline = Instant::now() + Duration::from_millis(350);
+        if let Some(ms) = traffic_every {
+            let mut n = 0;
+            while Instant::now() < deadline {
+                n += 1;
+                let _ = in_tx.send(format!("消息{}", n)).await;
+                sleep(Duration::from_millis(ms)).await;
+            }
+        } else {
+            tokio::time::sleep_until(deadline).await;
+        }
+        
+        let _ = shutdown_tx.send(());
+        let pings = conn.await.unwrap();
+        println!("   📊 共发送 {} 次 PING\n", pings);
+    }
+}
+
+// === 11. 带截止时间的许可获取 ===
+
+/// 在 deadline 之前拿到许可就返回它，否则返回 None，不会无限期排队
+async fn acquire_by(sem: &Semaphore, deadline: Instant) -> Option<SemaphorePermit<'_>> {
+    select! {
+        permit = sem.acquire() => permit.ok(),
+        _ = tokio::time::sleep_until(deadline) => None,
+    }
+}
+
+/// 演示许可耗尽时按截止时间放弃
+async fn acquire_by_demo() {
+    println!("=== 11. 带截止时间的许可获取 ===");
+    println!("📝 信号量只有 1 个许可且已被占用，第二个请求最多等 200ms\n");
+    
+    let semaphore = Semaphore::new(1);
+    let held = acquire_by(&semaphore, Instant::now() + Duration::from_millis(200)).await;
+    println!("   🔑 第一个请求拿到许可: {}", held.is_some());
+    
+    let start = Instant::now();
+    let second = acquire_by(&semaphore, Instant::now() + Duration::from_millis(200)).await;
+    println!("   ⏱️  第二个请求等待 {}ms 后: {}", start.elapsed().as_millis(),
+             if second.is_some() { "拿到许可" } else { "超过截止时间，放弃（None）" });
+    
+    drop(held);
+    let third = acquire_by(&semaphore, Instant::now() + Duration::from_millis(200)).await;
+    println!("   🔑 许可释放后第三个请求拿到许可: {}\n", third.is_some());
+}
+
+// === 12. 取消安全的接收 vs 取消不安全的接收 ===
+
+/// 取消安全：recv() 不持有任何中间状态，在 select! 中被取消不会丢消息
+async fn recv_cancel_safe<T>(rx: &mut mpsc::Receiver<T>) -> Option<T> {
+    rx.recv().await
+}
+
+/// 取消不安全：一次读两条消息，第一条先放在局部缓冲里
+///
+/// 如果在等第二条时被 select! 取消，缓冲里的第一条会随 Future 一起被 drop 而丢失。
+async fn recv_pair_unsafe<T>(rx: &mut mpsc::Receiver<T>) -> Option<(T, T)> {
+    let first = rx.recv().await?;
+    let second = rx.recv().await?;
+    Some((first, second))
+}
+
+/// 每 70ms 发送一条消息，共 6 条
+fn spawn_ticker_producer() -> mpsc::Receiver<u32> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        for i in 1..=6 {
+            sleep(Duration::from_millis(70)).await;
+            let _ = tx.send(i).await;
+        }
+    });
+    rx
+}
+
+/// 在每 100ms 被 select! 超时打断一次的循环里收完 rx 的所有消息
+///
+/// pairs 为 true 时使用取消不安全的 recv_pair_unsafe，否则使用 recv_cancel_safe。
+async fn receive_with_interruptions(mut rx: mpsc::Receiver<u32>, pairs: bool) -> Vec<u32> {
+    let mut received = Vec::new();
+    loop {
+        if pairs {
+            select! {
+                pair = recv_pair_unsafe(&mut rx) => match pair {
+                    Some((a, b)) => received.extend([a, b]),
+                    None => break,
+                },
+                _ = sleep(Duration::from_millis(100)) => {}
+            }
+        } else {
+            select! {
+                msg = recv_cancel_safe(&mut rx) => match msg {
+                    Some(msg) => received.push(msg),
+                    None => break,
+                },
+                _ = sleep(Duration::from_millis(100)) => {}
+            }
+        }
+    }
+    received
+}
+
+async fn cancel_safe_recv_demo() {
+    println!("=== 12. 取消安全的接收 ===");
+    println!("📝 生产者每 70ms 发一条消息（共 6 条），接收方每 100ms 被 select! 超时打断一次\n");
+    
+    let expected: Vec<u32> = (1..=6).collect();
+    for (label, pairs) in [("取消安全版本", false), ("取消不安全版本", true)] {
+        let received = receive_with_interruptions(spawn_ticker_producer(), pairs).await;
+        if received == expected {
+            println!("   ✅ {}收到全部消息: {:?}", label, received);
+        } else {
+            let lost: Vec<_> = expected.iter().filter(|m| !received.contains(m)).collect();
+            println!("   ❌ {}收到 {:?}，丢失 {:?}", label, received, lost);
+        }
+    }
+    println!();
+}
+
+// === 13. 异步记忆化（single-flight）===
+
+/// 按 key 缓存异步计算的结果
+///
+/// 同一个 key 的并发调用共享同一次正在进行的计算（single-flight），
+/// 计算完成后结果留在表里，之后的调用直接返回缓存值。
+struct AsyncMemo<K, V> {
+    cells: std::sync::Mutex<HashMap<K, Arc<tokio::sync::OnceCell<V>>>>,
+}
+
+impl<K: Hash + Eq, V: Clone> AsyncMemo<K, V> {
+    fn new() -> Self {
+        AsyncMemo {
+            cells: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+    
+    async fn get_or_compute<F, Fut>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        // 只在取 cell 时持有同步锁，等待计算时不持有
+        let cell = self.cells.lock().unwrap().entry(key).or_default().clone();
+        cell.get_or_init(f).await.clone()
+    }
+}
+
+async fn async_memo_demo() {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    
+    println!("=== 13. 异步记忆化（single-flight）===");
+    println!("📝 5 个并发调用请求同一个 key，计算耗时 200ms\n");
+    
+    let memo = AsyncMemo::new();
+    let computations = AtomicU32::new(0);
+    let compute = || async {
+        computations.fetch_add(1, Ordering::SeqCst);
+        sleep(Duration::from_millis(200)).await;
+        "配置数据".to_string()
+    };
+    
+    let start = Instant::now();
+    let callers = (0..5).map(|_| memo.get_or_compute("config", compute));
+    let values = futures::future::join_all(callers).await;
+    println!("   📦 5 个调用者拿到: {:?}", values);
+    println!("   🔢 实际计算次数: {}（耗时 {}ms）", computations.load(Ordering::SeqCst), start.elapsed().as_millis());
+    
+    let cached = memo.get_or_compute("config", compute).await;
+    println!("   ⚡ 之后的调用直接命中缓存: {}，计算次数仍为 {}\n", cached, computations.load(Ordering::SeqCst));
+}
+
+// === 14. 不等待的 select!：取走当前已就绪的消息 ===
+
+/// 只收集 channel 里当前已缓冲的消息，一条都没有时立即返回空 Vec
+///
+/// biased 让 select! 按书写顺序轮询：先看 recv() 是否已就绪，
+/// 没有就落到一个立即就绪的分支，相当于其他语言 select 的 default 分支。
+/// （同样的效果也可以用 try_recv 循环实现。）
+async fn drain_available<T>(rx: &mut mpsc::Receiver<T>) -> Vec<T> {
+    let mut items = Vec::new();
+    loop {
+        select! {
+            biased;
+            msg = rx.recv() => match msg {
+                Some(msg) => items.push(msg),
+                None => break, // 发送端全部关闭
+            },
+            _ = std::future::ready(()) => break, // 没有就绪的消息
+        }
+    }
+    items
+}
+
+async fn drain_available_demo() {
+    println!("=== 14. 不等待的 select!（default 分支）===");
+    
+    let (tx, mut rx) = mpsc::channel(8);
+    for i in 1..=3 {
+        tx.send(i).await.unwrap();
+    }
+    
+    let start = Instant::now();
+    let items = drain_available(&mut rx).await;
+    println!("   📥 已缓冲的消息: {:?}（耗时 {}µs）", items, start.elapsed().as_micros());
+    
+    let start = Instant::now();
+    let items = drain_available(&mut rx).await;
+    println!("   📭 没有消息时: {:?}（耗时 {}µs，没有等待）\n", items, start.elapsed().as_micros());
+}
+
+// === 15. 断线重连循环 ===
+
+/// 0 到 max 之间的随机抖动（借用 RandomState 的随机种子，无需额外依赖）
+fn jitter(max: Duration) -> Duration {
+    use std::hash::{BuildHasher, Hasher};
+    let random = std::collections::hash_map::RandomState::new().build_hasher().finish();
+    max.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+/// 经典的弹性客户端循环：连接 → 使用连接直到断开 → 指数退避加抖动后重连
+///
+/// 连接成功后退避时间重置为 base；token 被取消时无论处于哪个阶段都立即返回。
+/// 返回成功建立的连接次数。
+async fn reconnect_loop<C, E, F, Fut, S, SFut>(
+    mut connect: F,
+    mut serve: S,
+    base: Duration,
+    max_backoff: Duration,
+    token: CancellationToken,
+) -> u32
+where
+    E: std::fmt::Debug,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<C, E>>,
+    S: FnMut(C) -> SFut,
+    SFut: Future<Output = ()>,
+{
+    let mut backoff = base;
+    let mut connections = 0;
+    
+    loop {
+        let attempt = select! {
+            _ = token.cancelled() => return connections,
+            result = connect() => result,
+        };
+        
+        match attempt {
+            Ok(conn) => {
+                connections += 1;
+                backoff = base;
+                println!("   🔗 连接已建立（第 {} 次）", connections);
+                select! {
+                    _ = token.cancelled() => return connections,
+                    _ = serve(conn) => println!("   💔 连接断开，准备重连"),
+                }
+            }
+            Err(e) => {
+                let wait = backoff + jitter(backoff / 2);
+                println!("   ❌ 连接失败: {:?}，{}ms 后重试", e, wait.as_millis());
+                select! {
+                    _ = token.cancelled() => return connections,
+                    _ = sleep(wait) => {}
+                }
+                backoff = (backoff * 2).min(max_backoff);
+            }
+        }
+    }
+}
+
+async fn reconnect_loop_demo() {
+    println!("=== 15. 断线重连循环 ===");
+    println!("📝 前两次连接失败，之后成功；每个连接使用 150ms 后断开，600ms 后停止\n");
+    
+    let token = CancellationToken::new();
+    let canceller = token.clone();
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(600)).await;
+        canceller.cancel();
+    });
+    
+    let mut attempts = 0;
+    let connections = reconnect_loop(
+        || {
+            attempts += 1;
+            let n = attempts;
+            async move {
+                sleep(Duration::from_millis(10)).await;
+                if n <= 2 { Err("连接被拒绝") } else { Ok(format!("conn-{}", n)) }
+            }
+        },
+        |conn| async move {
+            println!("   📡 使用 {}", conn);
+            sleep(Duration::from_millis(150)).await;
+        },
+        Duration::from_millis(50),
+        Duration::from_secs(2),
+        token,
+    )
+    .await;
+    
+    println!("   📊 共尝试连接 {} 次，成功建立 {} 次连接\n", attempts, connections);
+}
+
+// === 16. 超时返回默认值 ===
+
+/// 在期限内完成就返回 Future 的结果，否则返回 default
+///
+/// 相比直接用 timeout 处理 Result，适合"超时就用兜底值"的场景。
+async fn timeout_or<F: Future>(dur: Duration, fut: F, default: F::Output) -> F::Output {
+    timeout(dur, fut).await.unwrap_or(default)
+}
+
+async fn timeout_or_demo() {
+    println!("=== 16. 超时返回默认值 ===");
+    
+    let lookup = |ms, value: &'static str| async move {
+        sleep(Duration::from_millis(ms)).await;
+        value
+    };
+    
+    let fast = timeout_or(Duration::from_millis(100), lookup(20, "实时数据"), "缓存数据").await;
+    println!("   {} 20ms 完成，期限 100ms: {}", if fast == "实时数据" { "✅" } else { "❌" }, fast);
+    
+    let start = Instant::now();
+    let slow = timeout_or(Duration::from_millis(100), lookup(500, "实时数据"), "缓存数据").await;
+    let elapsed = start.elapsed();
+    // 超时路径：返回默认值，而且在期限附近就返回，不等慢操作完成
+    let ok = slow == "缓存数据" && elapsed < Duration::from_millis(200);
+    println!("   {} 500ms 完成，期限 100ms: {}（{}ms 后返回）\n",
+             if ok { "✅" } else { "❌" }, slow, elapsed.as_millis());
+}
+
+// === 17. 最短耗时（防闪烁）===
+
+/// 保证整个操作至少耗时 min：fut 提前完成时补足剩余时间，本身更慢则不额外等待
+///
+/// 用于加载提示之类的场景，避免操作完成得太快导致界面一闪而过。
+async fn at_least<F: Future>(min: Duration, fut: F) -> F::Output {
+    let deadline = Instant::now() + min;
+    let output = fut.await;
+    tokio::time::sleep_until(deadline).await;
+    output
+}
+
+async fn at_least_demo() {
+    println!("=== 17. 最短耗时（防闪烁）===");
+    
+    let min = Duration::from_millis(200);
+    for work in [Duration::from_millis(20), Duration::from_millis(400)] {
+        let start = Instant::now();
+        let value = at_least(min, async {
+            sleep(work).await;
+            "加载完成"
+        })
+        .await;
+        let elapsed = start.elapsed();
+        println!("   ⏳ 操作本身 {}ms，至少 {}ms: {}，实际耗时 {}ms",
+                 work.as_millis(), min.as_millis(), value, elapsed.as_millis());
+        
+        // 快操作被补足到 min；慢操作不额外等待（精确耗时见暂停时钟下的测试）
+        let expected = work.max(min);
+        if elapsed >= expected {
+            println!("   ✅ 耗时不少于 max(操作本身, min) = {}ms", expected.as_millis());
+        } else {
+            println!("   ❌ 预期耗时至少 {}ms", expected.as_millis());
+        }
+    }
+    println!();
+}
+
+// === 18. 协作式取消 vs abort ===
+
+/// 两个账户的余额，转账分两步完成：先扣款，等待一会儿，再入账
+type Ledger = Arc<std::sync::Mutex<(i64, i64)>>;
+
+/// 从账户 A 向 B 转 1：扣款和入账之间隔着一个 .await，被中止就会只做了一半
+async fn transfer(ledger: &Ledger) {
+    ledger.lock().unwrap().0 -= 1;
+    sleep(Duration::from_millis(40)).await;
+    ledger.lock().unwrap().1 += 1;
+}
+
+/// 协作式取消的工作者：只在两笔转账之间检查取消，收到信号后先做清理再退出
+async fn cooperative_worker(id: u32, ledger: Ledger, token: CancellationToken) -> u32 {
+    let mut done = 0;
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                println!("   🧹 工作者 {} 收到取消：已完成 {} 笔转账，释放资源后退出", id, done);
+                return done;
+            }
+            // 等待下一笔转账：这里被打断不会留下半完成的状态
+            _ = sleep(Duration::from_millis(10)) => {}
+        }
+        // 不放进 select!：一笔转账一旦开始就一定做完
+        transfer(&ledger).await;
+        done += 1;
+    }
+}
+
+async fn cancellation_token_demo() {
+    println!("=== 18. 协作式取消 vs abort ===");
+    println!("📝 3 个工作者不断从 A 向 B 转账（扣款 → 等待 40ms → 入账），200ms 后叫停\n");
+    
+    // 协作式：cancel() 只是发出信号，由工作者自己选择在安全点退出
+    let ledger: Ledger = Arc::new(std::sync::Mutex::new((1000, 0)));
+    let token = CancellationToken::new();
+    let workers: Vec<_> = (1..=3)
+        .map(|id| tokio::spawn(cooperative_worker(id, ledger.clone(), token.clone())))
+        .collect();
+    sleep(Duration::from_millis(200)).await;
+    token.cancel();
+    for worker in workers {
+        worker.await.unwrap();
+    }
+    let (a, b) = *ledger.lock().unwrap();
+    println!("   {} CancellationToken: A = {}，B = {}，总额 {}（应为 1000）\n",
+             if a + b == 1000 { "✅" } else { "❌" }, a, b, a + b);
+    
+    // abort：任务在下一个 .await 处被直接丢弃，不管做到了哪一步
+    let ledger: Ledger = Arc::new(std::sync::Mutex::new((1000, 0)));
+    let workers: Vec<_> = (1..=3)
+        .map(|_| {
+            let ledger = ledger.clone();
+            tokio::spawn(async move {
+                loop {
+                    sleep(Duration::from_millis(10)).await;
+                    transfer(&ledger).await;
+                }
+            })
+        })
+        .collect();
+    sleep(Duration::from_millis(200)).await;
+    for worker in &workers {
+        worker.abort();
+    }
+    for worker in workers {
+        let _ = worker.await;
+    }
+    let (a, b) = *ledger.lock().unwrap();
+    if a + b == 1000 {
+        println!("   ⚠️  abort(): A = {}，B = {}，这次恰好没有在转账中途被中止", a, b);
+    } else {
+        println!("   ⚠️  abort(): A = {}，B = {}，总额 {}：有 {} 笔转账扣了款却没入账", a, b, a + b, 1000 - (a + b));
+    }
+    
+    println!("\n   💡 abort() 在任意 .await 处终止任务，没有机会清理，多步更新可能只做了一半；");
+    println!("      CancellationToken 只发出请求，任务在自己选定的安全点退出并完成清理，");
+    println!("      所以对有状态的工作，协作式取消更安全\n");
+}
+
+// === 19. 用 buffer_unordered 限制并发 ===
+
+async fn buffered_concurrency_demo() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    
+    println!("=== 19. 用 buffer_unordered 限制并发 ===");
+    println!("📝 9 个各需 1 秒的任务，buffer_unordered(3) 同时最多运行 3 个\n");
+    
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+    let start = Instant::now();
+    
+    let results: Vec<u32> = stream::iter(1..=9u32)
+        .map(|id| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                sleep(Duration::from_secs(1)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                println!("   ✅ 任务 {} 完成 @ {:.1}s", id, start.elapsed().as_secs_f64());
+                id * 10
+            }
+        })
+        // 只有 3 个 Future 在运行；完成一个，才从上游再取一个
+        .buffer_unordered(3)
+        .collect()
+        .await;
+    
+    let elapsed = start.elapsed();
+    let peak = peak.load(Ordering::SeqCst);
+    let ok = results.len() == 9 && peak == 3 && elapsed < Duration::from_millis(3500);
+    println!("   {} 收集到 {} 个结果，最大并发 {}，总耗时 {:.1} 秒（串行需要 9 秒）",
+             if ok { "✅" } else { "❌" }, results.len(), peak, elapsed.as_secs_f64());
+    
+    println!("\n   💡 如何选择：");
+    println!("      • buffer_unordered: 工作项本来就是一个序列，要一个固定的并发窗口，最简洁");
+    println!("      • FuturesUnordered: 任务集合是动态的，处理过程中还要继续 push 新任务");
+    println!("      • Semaphore: 限制跨越多处代码、多个 spawn 出去的任务共享的资源\n");
+}
+
 #[tokio::main]
 async fn main() {
     println!("🎓 Rust 并发模型深入教程\n");
@@ -216,6 +941,18 @@ async fn main() {
     oneshot_channel_demo().await;
     cancellation_safety().await;
     futures_unordered_demo().await;
+    retry_if_demo().await;
+    parallel_map_demo().await;
+    keepalive_demo().await;
+    acquire_by_demo().await;
+    cancel_safe_recv_demo().await;
+    async_memo_demo().await;
+    drain_available_demo().await;
+    reconnect_loop_demo().await;
+    timeout_or_demo().await;
+    at_least_demo().await;
+    cancellation_token_demo().await;
+    buffered_concurrency_demo().await;
     
     println!("🎉 教程完成！\n");
     println!("💡 关键要点：");
@@ -225,5 +962,254 @@ async fn main() {
     println!("   • oneshot channel 用于一次性通信");
     println!("   • select! 中未完成的分支会被取消");
     println!("   • FuturesUnordered 按完成顺序处理动态任务集合");
+    println!("   • 重试时区分临时错误和永久错误");
+    println!("   • CancellationToken 让批量任务可以被提前叫停");
+    println!("   • 可重置的 sleep + select! 实现空闲检测");
+    println!("   • sleep_until + select! 给等待许可设置截止时间");
+    println!("   • 在 select! 循环里只使用取消安全的 Future，避免丢失中间状态");
+    println!("   • OnceCell 让同一个 key 的并发计算只执行一次");
+    println!("   • biased + 立即就绪的分支让 select! 不阻塞");
+    println!("   • 重连循环：指数退避 + 抖动，成功后重置退避");
+    println!("   • timeout_or 在超时时返回兜底值");
+    println!("   • at_least 用 sleep_until 补足最短耗时，防止界面闪烁");
+    println!("   • 有状态的工作用 CancellationToken 协作式取消，abort 可能留下半完成的更新");
+    println!("   • buffer_unordered(n) 把 Stream 里的 Future 以 n 为窗口并发执行");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test(start_paused = true)]
+    async fn retry_if_short_circuits_on_fatal_error() {
+        let start = Instant::now();
+        let mut calls = 0;
+        let result: Result<(), _> = retry_if(
+            5,
+            Duration::from_millis(100),
+            || {
+                calls += 1;
+                async { Err(FetchError::Fatal) }
+            },
+            |e| matches!(e, FetchError::Transient),
+        )
+        .await;
+        
+        assert!(matches!(result, Err(FetchError::Fatal)));
+        assert_eq!(calls, 1);
+        assert_eq!(start.elapsed(), Duration::ZERO, "永久错误不应等待退避");
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn retry_if_backs_off_on_transient_errors() {
+        let start = Instant::now();
+        let mut calls = 0;
+        let result = retry_if(
+            5,
+            Duration::from_millis(100),
+            || {
+                calls += 1;
+                let n = calls;
+                async move { if n < 3 { Err(FetchError::Transient) } else { Ok(n) } }
+            },
+            |e| matches!(e, FetchError::Transient),
+        )
+        .await;
+        
+        assert_eq!(result.unwrap(), 3);
+        // 两次退避：100ms + 200ms
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn parallel_map_stops_all_work_on_cancel() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(700)).await;
+            canceller.cancel();
+        });
+        
+        let finished = Arc::new(AtomicUsize::new(0));
+        let counter = finished.clone();
+        let results = parallel_map(
+            (1..=10).collect(),
+            3,
+            |n: u32| {
+                let counter = counter.clone();
+                async move {
+                    sleep(Duration::from_millis(300)).await;
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    n
+                }
+            },
+            token,
+        )
+        .await;
+        
+        // 并发 3、每个 300ms：700ms 时恰好完成两批共 6 个
+        assert_eq!(results.len(), 6);
+        let at_cancel = finished.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(500)).await;
+        assert_eq!(finished.load(Ordering::SeqCst), at_cancel, "取消之后不应再有任务完成");
+    }
+    
+    /// 以固定间隔发送 `messages` 条消息，运行 `run_for` 后关闭连接，返回发出的 ping 数
+    async fn run_connection(messages: u32, every: Duration, run_for: Duration) -> u32 {
+        let (in_tx, in_rx) = mpsc::channel(16);
+        let (out_tx, mut out_rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let conn = tokio::spawn(connection_loop(in_rx, out_tx, Duration::from_millis(100), shutdown_rx));
+        
+        let deadline = Instant::now() + run_for;
+        for n in 0..messages {
+            in_tx.send(format!("消息{}", n)).await.unwrap();
+            sleep(every).await;
+        }
+        tokio::time::sleep_until(deadline).await;
+        shutdown_tx.send(()).unwrap();
+        
+        let pings = conn.await.unwrap();
+        let mut sent = 0;
+        while out_rx.recv().await.is_some() {
+            sent += 1;
+        }
+        assert_eq!(sent, pings, "每次 ping 都应该发到对端");
+        pings
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_pings_only_when_idle() {
+        // 空闲 350ms：第 100、200、300ms 各发一次 ping
+        assert_eq!(run_connection(0, Duration::ZERO, Duration::from_millis(350)).await, 3);
+        // 每 50ms 一条消息，ping 计时器不断被重置，一直到 350ms 都不该发 ping
+        assert_eq!(run_connection(7, Duration::from_millis(50), Duration::from_millis(350)).await, 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn acquire_by_gives_up_at_the_deadline() {
+        let semaphore = Semaphore::new(1);
+        let held = acquire_by(&semaphore, Instant::now() + Duration::from_millis(200)).await;
+        assert!(held.is_some());
+        
+        let start = Instant::now();
+        assert!(acquire_by(&semaphore, start + Duration::from_millis(200)).await.is_none());
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+        
+        drop(held);
+        assert!(acquire_by(&semaphore, Instant::now() + Duration::from_millis(200)).await.is_some());
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn only_the_cancel_unsafe_receiver_loses_messages() {
+        let safe = receive_with_interruptions(spawn_ticker_producer(), false).await;
+        assert_eq!(safe, [1, 2, 3, 4, 5, 6]);
+        
+        // 读到一半被取消时，缓冲在 Future 里的第一条消息随之丢失
+        let unsafe_pairs = receive_with_interruptions(spawn_ticker_producer(), true).await;
+        assert!(unsafe_pairs.len() < 6, "取消不安全版本应该丢消息: {:?}", unsafe_pairs);
+        assert!(unsafe_pairs.windows(2).all(|w| w[0] < w[1]));
+    }
+    
+    #[tokio::test]
+    async fn memo_runs_the_computation_once_for_concurrent_callers() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        
+        let memo = AsyncMemo::new();
+        let computations = AtomicU32::new(0);
+        let compute = || async {
+            computations.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            "配置数据".to_string()
+        };
+        
+        let calls = (0..5).map(|_| memo.get_or_compute("config", compute));
+        let values = futures::future::join_all(calls).await;
+        assert!(values.iter().all(|v| v == "配置数据"));
+        assert_eq!(computations.load(Ordering::SeqCst), 1);
+        
+        // 之后的调用直接命中缓存，其他 key 单独计算
+        memo.get_or_compute("config", compute).await;
+        memo.get_or_compute("other", compute).await;
+        assert_eq!(computations.load(Ordering::SeqCst), 2);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn drain_available_returns_only_buffered_items_without_waiting() {
+        let (tx, mut rx) = mpsc::channel(8);
+        for i in 1..=3 {
+            tx.send(i).await.unwrap();
+        }
+        // 有一条消息要 1 秒后才到，不应等它
+        let late = tx.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let _ = late.send(4).await;
+        });
+        
+        let start = Instant::now();
+        assert_eq!(drain_available(&mut rx).await, [1, 2, 3]);
+        assert!(drain_available(&mut rx).await.is_empty());
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_loop_backs_off_until_connect_succeeds() {
+        let token = CancellationToken::new();
+        let mut attempts = 0;
+        let connect = || {
+            attempts += 1;
+            let attempt = attempts;
+            async move { if attempt <= 2 { Err("connection refused") } else { Ok(attempt) } }
+        };
+        // 连接建立后立即停止循环，只观察重连过程
+        let mut served = Vec::new();
+        let serve = |conn| {
+            served.push(conn);
+            token.cancel();
+            std::future::pending::<()>()
+        };
+        
+        let start = Instant::now();
+        let connections = reconnect_loop(connect, serve, Duration::from_millis(100), Duration::from_secs(1), token.clone()).await;
+        
+        assert_eq!(connections, 1);
+        assert_eq!(attempts, 3);
+        assert_eq!(served, [3]);
+        // 两次退避：100ms 和 200ms，各加上最多一半的抖动
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_millis(450), "{:?}", elapsed);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn timeout_or_returns_value_in_time_and_default_otherwise() {
+        let lookup = |ms, value: &'static str| async move {
+            sleep(Duration::from_millis(ms)).await;
+            value
+        };
+        
+        assert_eq!(timeout_or(Duration::from_millis(100), lookup(20, "实时数据"), "缓存数据").await, "实时数据");
+        
+        let start = Instant::now();
+        assert_eq!(timeout_or(Duration::from_millis(100), lookup(500, "实时数据"), "缓存数据").await, "缓存数据");
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn at_least_pads_fast_futures_but_never_delays_slow_ones() {
+        let min = Duration::from_millis(200);
+        for (work, expected) in [(20, 200), (400, 400)] {
+            let start = Instant::now();
+            let value = at_least(min, async {
+                sleep(Duration::from_millis(work)).await;
+                work
+            })
+            .await;
+            assert_eq!(value, work);
+            assert_eq!(start.elapsed(), Duration::from_millis(expected));
+        }
+    }
+}
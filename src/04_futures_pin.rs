@@ -7,30 +7,38 @@
 // 4. 自引用结构体的问题
 
 use std::future::Future;
+use std::marker::PhantomPinned;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
 use std::time::{Duration, Instant};
+use pin_project_lite::pin_project;
 use tokio::time::sleep;
 
-/// === 1. 理解 Future Trait ===
-/// 
-/// Future 的定义（简化版）：
-/// ```
-/// trait Future {
-///     type Output;
-///     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output>;
-/// }
-/// ```
+// === 1. 理解 Future Trait ===
+// 
+// Future 的定义（简化版）：
+// ```
+// trait Future {
+//     type Output;
+//     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output>;
+// }
+// ```
 
 /// 一个简单的自定义 Future - 延迟完成
+///
+/// 计时交给内部的 tokio Sleep：它在 Pending 时向运行时的定时器注册 waker，
+/// 到期才会唤醒，等待期间不占用 CPU。
 struct DelayFuture {
-    when: Instant,
+    // Sleep 是 !Unpin 的，装进 Box 后 DelayFuture 本身仍然是 Unpin
+    sleep: Pin<Box<tokio::time::Sleep>>,
 }
 
 impl DelayFuture {
     fn new(duration: Duration) -> Self {
         DelayFuture {
-            when: Instant::now() + duration,
+            sleep: Box::pin(sleep(duration)),
         }
     }
 }
@@ -38,15 +46,12 @@ impl DelayFuture {
 impl Future for DelayFuture {
     type Output = String;
     
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if Instant::now() >= self.when {
-            // 时间到了，Future 完成
-            Poll::Ready("⏰ 延迟完成！".to_string())
-        } else {
-            // 还没到时间，需要稍后重新 poll
-            // 在实际实现中，应该注册 waker 来通知运行时
-            cx.waker().wake_by_ref();
-            Poll::Pending
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 委托给 Sleep：还没到时间时它会保存 cx 里的 waker 并返回 Pending，
+        // 到期后由定时器调用 wake()，运行时才会再次 poll 我们
+        match self.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready("⏰ 延迟完成！".to_string()),
+            Poll::Pending => Poll::Pending,
         }
     }
 }
@@ -61,40 +66,154 @@ async fn custom_future_demo() {
     println!("{}\n", result);
 }
 
-/// === 2. 理解 Pin ===
-/// 
-/// Pin 的作用：保证被 pin 的值不会在内存中移动
-/// 这对于自引用结构体非常重要
+// === 2. 理解 Pin ===
+// 
+// Pin 的作用：保证被 pin 的值不会在内存中移动
+// 这对于自引用结构体非常重要
 
-/// 一个自引用结构体的例子（仅用于概念演示）
-#[allow(dead_code)]
-struct SelfReferential {
-    data: String,
-    // 注意：这是一个指向 data 的指针（实际中很危险！）
-    // 如果结构体移动，指针会失效
-    pointer: *const String,
+pin_project! {
+    /// 一个自引用的 Future：pointer 指向自己的 data 字段，并且跨越一次等待继续使用
+    ///
+    /// 第一次 poll 时（此时已经被 pin）记下 data 的地址，然后等待 delay；
+    /// delay 完成后的那次 poll 再通过 pointer 读取 data。这正是 async fn 状态机的样子：
+    /// 局部变量和指向它们的引用一起保存在 Future 里，跨越 .await 存活。
+    ///
+    /// 如果允许移动：两次 poll 之间把结构体 mem::swap 或 move 到别处，data 的地址变了，
+    /// pointer 却还指向旧位置——读到的是已经被覆盖或释放的内存。
+    /// _pin 字段让整个结构体成为 !Unpin，于是 safe 代码拿不到 &mut Self，也就无法移动它。
+    struct SelfReferential<F> {
+        data: String,
+        pointer: *const String,
+        #[pin]
+        delay: F,
+        #[pin]
+        _pin: PhantomPinned,
+    }
 }
 
-#[allow(dead_code)]
-impl SelfReferential {
-    fn new(text: String) -> Self {
+impl<F> SelfReferential<F> {
+    fn new(text: String, delay: F) -> Self {
         SelfReferential {
             data: text,
             pointer: std::ptr::null(),
+            delay,
+            _pin: PhantomPinned,
+        }
+    }
+}
+
+impl<F: Future> Future for SelfReferential<F> {
+    type Output = String;
+    
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<String> {
+        // project() 由 pin_project! 生成：#[pin] 字段得到 Pin<&mut _>，其余字段得到 &mut _，
+        // 投影本身不需要 unsafe
+        let this = self.project();
+        if this.pointer.is_null() {
+            // 已经被 pin，data 的地址从此固定
+            *this.pointer = &*this.data;
+        }
+        
+        std::task::ready!(this.delay.poll(cx));
+        
+        // SAFETY: pointer 在 pin 之后才设置，Self 是 !Unpin，之后不会再移动，data 的地址没变
+        let data = unsafe { &**this.pointer };
+        Poll::Ready(format!("通过自引用读到: {}", data))
+    }
+}
+
+/// 演示自引用 Future 跨越等待后仍然有效
+async fn self_referential_demo() {
+    println!("=== 2.2 跨越等待的自引用 ===");
+    println!("📝 SelfReferential 首次 poll 时记下 data 的地址，等待 200ms 后再从这个地址读取\n");
+    
+    let future = SelfReferential::new("pinned data".to_string(), sleep(Duration::from_millis(200)));
+    // let moved = future; // 在 poll 之前移动没问题：pointer 还没有建立
+    let result = future.await;
+    println!("✅ {}", result);
+    println!("   📌 .await 把 future 固定在 async 块的状态里，两次 poll 之间它不会移动\n");
+}
+
+pin_project! {
+    /// 逐词解析自己拥有的 String 的 Future，每次 poll 解析一个词
+    ///
+    /// rest 是一个真正指向同一个结构体里 text 的借用：记录尚未解析的那一段，
+    /// 每次 poll 向后推进。text 的字节在堆上，移动结构体本身不会让它们失效；
+    /// 危险在于有人拿到 &mut WordParser 之后给 text 重新赋值或把它 take 走——
+    /// 旧的堆内存被释放，rest 就悬垂了。_pin 让 WordParser 成为 !Unpin，
+    /// 一旦被 pin，safe 代码就再也拿不到 &mut Self，text 也就不会在 rest 存活期间被替换。
+    struct WordParser {
+        text: String,
+        // 尚未解析的部分，指向 text 的堆内存；None 表示还没有 poll 过
+        rest: Option<*const str>,
+        words: Vec<String>,
+        #[pin]
+        _pin: PhantomPinned,
+    }
+}
+
+impl WordParser {
+    fn new(text: impl Into<String>) -> Self {
+        WordParser {
+            text: text.into(),
+            rest: None,
+            words: Vec::new(),
+            _pin: PhantomPinned,
         }
     }
+}
+
+impl Future for WordParser {
+    type Output = Vec<String>;
     
-    fn init(self: Pin<&mut Self>) {
-        let self_ptr: *const String = &self.data;
-        // 安全地设置自引用指针
-        unsafe {
-            let mut_ref = Pin::get_unchecked_mut(self);
-            mut_ref.pointer = self_ptr;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        // 第一次 poll 时已经被 pin，从这里开始借用 text
+        let rest = *this.rest.get_or_insert(this.text.as_str() as *const str);
+        // SAFETY: rest 指向 text 的堆内存，在 pin 之后才建立。poll 从不修改 text，
+        // Self 又是 !Unpin 的，外部拿不到 &mut Self 去替换或释放 text，所以这段内存一直有效
+        let rest = unsafe { &*rest };
+        
+        let trimmed = rest.trim_start();
+        if trimmed.is_empty() {
+            return Poll::Ready(std::mem::take(this.words));
         }
+        
+        // 每次 poll 只解析一个词，然后让出执行权；切分点都在字符边界上，多字节字符也安全
+        let word_len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        this.words.push(trimmed[..word_len].to_string());
+        *this.rest = Some(&trimmed[word_len..] as *const str);
+        
+        cx.waker().wake_by_ref();
+        Poll::Pending
     }
+}
+
+/// 演示 WordParser：跨越多次 poll 借用自己的 text
+async fn self_ref_future_demo() {
+    println!("=== 2.1 借用自身缓冲区的 Future ===");
+    println!("📝 WordParser 的 rest 指向自己的 text，每次 poll 解析一个词\n");
+    
+    let parser = WordParser::new("rest borrows from the text it owns");
+    let words = parser.await;
+    println!("✅ 解析结果: {:?}", words);
     
-    fn get_data(&self) -> &str {
-        &self.data
+    // 先 poll 一次，rest 已经指向 text；之后只能移动 Pin<Box<_>> 这个指针，内容原地不动
+    let mut parser = Box::pin(WordParser::new("pinned between polls"));
+    let waker = futures::task::noop_waker();
+    let first = parser.as_mut().poll(&mut Context::from_waker(&waker));
+    let moved = parser;
+    // let unpinned = *moved; // ❌ 取不出来：WordParser 是 !Unpin 的，不能从 Pin<Box<_>> 中移出
+    println!("✅ 第一次 poll 后 {:?}，移动 Box 后继续解析: {:?}", first.map(|_| ()), moved.await);
+    
+    // 超过 64 字节的中文文本：不截断，也不会在多字节字符中间切开
+    let text = "自引用 结构体 必须 先被 固定 才能 建立 指向 自身 字段 的 借用 否则 移动 之后 指针 就会 悬垂";
+    let words = WordParser::new(text).await;
+    let expected: Vec<&str> = text.split_whitespace().collect();
+    if words == expected {
+        println!("✅ {} 字节的中文文本完整解析出 {} 个词\n", text.len(), words.len());
+    } else {
+        println!("❌ 中文文本解析结果不完整: {:?}\n", words);
     }
 }
 
@@ -115,7 +234,7 @@ async fn pin_demo() {
     println!("   • Future 需要 Pin 因为 async 可能产生自引用\n");
 }
 
-/// === 3. 组合 Future ===
+// === 3. 组合 Future ===
 
 /// 手动实现一个组合 Future
 struct JoinFuture<F1, F2> {
@@ -197,15 +316,16 @@ async fn combined_future_demo() {
     println!("✅ {}\n", r2);
 }
 
-/// === 4. Stream - 异步迭代器 ===
+// === 4. Stream - 异步迭代器 ===
 
-/// Stream 类似于异步版本的 Iterator
-/// trait Stream {
-///     type Item;
-///     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
-/// }
+// Stream 类似于异步版本的 Iterator
+// trait Stream {
+//     type Item;
+//     fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>>;
+// }
 
-use futures::stream::{self, StreamExt};
+use futures::future::Either;
+use futures::stream::{self, Stream, StreamExt};
 
 async fn stream_demo() {
     println!("=== 4. Stream（异步迭代器）===");
@@ -243,7 +363,7 @@ async fn stream_demo() {
     println!("   fold 求和: {}\n", sum);
 }
 
-/// === 5. Waker 和唤醒机制 ===
+// === 5. Waker 和唤醒机制 ===
 
 async fn waker_concept() {
     println!("=== 5. Waker 唤醒机制 ===");
@@ -263,6 +383,895 @@ async fn waker_concept() {
     println!("   • Runtime 重新 poll，返回 Ready\n");
 }
 
+// === 6. 一个真正由 Waker 驱动的 Future ===
+
+/// 当标志位被置为 true 时完成的 Future
+///
+/// 与借助 tokio 定时器的 DelayFuture 不同，它自己在 Pending 时保存 Waker，
+/// 由另一个任务调用 set() 翻转标志并唤醒，期间不会被反复 poll。
+#[derive(Clone)]
+struct FlagFuture {
+    flag: Arc<AtomicBool>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl FlagFuture {
+    fn new() -> Self {
+        FlagFuture {
+            flag: Arc::new(AtomicBool::new(false)),
+            waker: Arc::new(Mutex::new(None)),
+        }
+    }
+    
+    /// 翻转标志并唤醒正在等待的任务
+    fn set(&self) {
+        self.flag.store(true, Ordering::Release);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for FlagFuture {
+    type Output = ();
+    
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.flag.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        
+        // 保存最新的 Waker（任务可能在不同线程间迁移，所以每次都要更新）
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        
+        // 再检查一次：防止在保存 Waker 之前 set() 已经执行，导致永远不被唤醒
+        if self.flag.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// 演示 FlagFuture：一个任务等待，另一个任务发信号
+async fn flag_future_demo() {
+    println!("=== 6. Waker 实战：FlagFuture ===");
+    println!("📝 Pending 时保存 Waker，由另一个任务 set() 后唤醒\n");
+    
+    let mut future = FlagFuture::new();
+    let signal = future.clone();
+    
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(500)).await;
+        println!("📢 信号任务: 调用 set()");
+        signal.set();
+    });
+    
+    // 包一层统计 poll 次数：第一次返回 Pending，set() 唤醒后第二次返回 Ready
+    let start = Instant::now();
+    let mut polls = 0;
+    std::future::poll_fn(|cx| {
+        polls += 1;
+        Pin::new(&mut future).poll(cx)
+    })
+    .await;
+    let elapsed = start.elapsed();
+    
+    println!("   📊 耗时 {:.3} 秒，共被 poll {} 次", elapsed.as_secs_f64(), polls);
+    if polls == 2 && elapsed >= Duration::from_millis(500) {
+        println!("✅ FlagFuture 在 set() 后及时完成，等待期间没有忙轮询\n");
+    } else {
+        println!("❌ 预期 poll 2 次、约 500ms 后完成\n");
+    }
+}
+
+// === 7. 基于 Stream 的令牌桶 ===
+
+/// 按令牌桶速率产出许可的 Stream
+///
+/// 桶里最多存 burst 个令牌，每秒补充 rate 个。
+/// 消费者在每次动作前 `next().await` 一个许可即可自我限速。
+fn permit_stream(rate: u32, burst: u32) -> impl Stream<Item = ()> {
+    let rate = rate.max(1) as f64;
+    let burst = burst.max(1) as f64;
+    
+    // 状态：(当前令牌数, 上次补充时间)；用 tokio 的时钟，和 sleep 保持一致
+    stream::unfold((burst, tokio::time::Instant::now()), move |(tokens, last)| async move {
+        let refill = |tokens: f64, last: tokio::time::Instant| {
+            let now = tokio::time::Instant::now();
+            let tokens = (tokens + now.duration_since(last).as_secs_f64() * rate).min(burst);
+            (tokens, now)
+        };
+        
+        let (mut tokens, mut last) = refill(tokens, last);
+        if tokens < 1.0 {
+            // 令牌不足：睡到刚好补满一个令牌
+            sleep(Duration::from_secs_f64((1.0 - tokens) / rate)).await;
+            (tokens, last) = refill(tokens, last);
+        }
+        
+        Some(((), (tokens - 1.0, last)))
+    })
+}
+
+/// 演示令牌桶 Stream：先突发 burst 个，之后按速率匀速产出
+async fn permit_stream_demo() {
+    println!("=== 7. 基于 Stream 的令牌桶 ===");
+    println!("📝 rate = 5/秒，burst = 3：前 3 个许可立即可用，之后每 200ms 一个\n");
+    
+    let start = Instant::now();
+    let permits = permit_stream(5, 3).take(8);
+    futures::pin_mut!(permits);
+    
+    let mut times = Vec::new();
+    while permits.next().await.is_some() {
+        let at = start.elapsed();
+        times.push(at);
+        println!("   🎫 许可 {} @ {:>4}ms", times.len(), at.as_millis());
+    }
+    
+    // 突发：前 burst 个立即拿到，第 burst + 1 个不能立即拿到；之后每个间隔约 1/rate
+    let immediate = times.iter().filter(|t| **t < Duration::from_millis(50)).count();
+    let paced = times[3..].windows(2).all(|w| {
+        let gap = w[1] - w[0];
+        gap >= Duration::from_millis(180) && gap <= Duration::from_millis(260)
+    });
+    if times.len() == 8 && immediate == 3 && times[3] >= Duration::from_millis(180) && paced {
+        println!("   ✅ 立即拿到 {} 个许可，其余按 200ms 间隔放行\n", immediate);
+    } else {
+        println!("   ❌ 立即拿到 {} 个许可（预期 3），之后的间隔{}符合 200ms\n",
+                 immediate, if paced { "" } else { "不" });
+    }
+}
+
+// === 8. 按时间窗口批处理 ===
+
+/// 把每个 period 时间窗口内到达的元素收集成一批，在窗口关闭时一起发出
+///
+/// 窗口首尾相接，没有元素的空窗口会被跳过；源 Stream 结束时发出最后一批。
+/// 第一个窗口从第一次 poll 开始计时；消费者处理得太慢、错过了下一个窗口时，
+/// 从当前时刻重新计时，而不是连续发出一串已经过期的窗口。
+fn window_by_time<S: Stream>(s: S, period: Duration) -> impl Stream<Item = Vec<S::Item>> {
+    stream::unfold(
+        (Box::pin(s), None, false),
+        move |(mut s, deadline, mut ended)| async move {
+            let mut deadline = deadline.unwrap_or_else(|| tokio::time::Instant::now() + period);
+            while !ended {
+                let mut batch = Vec::new();
+                let timer = tokio::time::sleep_until(deadline);
+                tokio::pin!(timer);
+                
+                loop {
+                    tokio::select! {
+                        item = s.next() => match item {
+                            Some(item) => batch.push(item),
+                            None => {
+                                ended = true;
+                                break;
+                            }
+                        },
+                        _ = &mut timer => break,
+                    }
+                }
+                
+                deadline += period;
+                let now = tokio::time::Instant::now();
+                if deadline <= now {
+                    deadline = now + period;
+                }
+                if !batch.is_empty() {
+                    return Some((batch, (s, Some(deadline), ended)));
+                }
+            }
+            None
+        },
+    )
+}
+
+/// 演示时间窗口批处理：元素按到达时刻落入不同窗口
+async fn window_by_time_demo() {
+    println!("=== 8. 按时间窗口批处理 ===");
+    println!("📝 窗口 300ms：1,2,3 在第一个窗口内到达，4,5 在第二个窗口内到达\n");
+    
+    // (元素, 距上一个元素的间隔 ms)
+    let source = stream::iter(vec![(1, 0), (2, 50), (3, 50), (4, 250), (5, 50)])
+        .then(|(item, gap)| async move {
+            sleep(Duration::from_millis(gap)).await;
+            item
+        });
+    
+    let windows = window_by_time(source, Duration::from_millis(300));
+    // 创建后过一会儿才开始消费：窗口从第一次 poll 开始计时，不受这段空闲影响
+    sleep(Duration::from_millis(350)).await;
+    let batches = windows.collect::<Vec<_>>().await;
+    for (i, batch) in batches.iter().enumerate() {
+        println!("   🪟 窗口 {}: {:?}", i + 1, batch);
+    }
+    if batches == [vec![1, 2, 3], vec![4, 5]] {
+        println!("   ✅ 元素按到达时所在的窗口分组\n");
+    } else {
+        println!("   ❌ 预期 [[1, 2, 3], [4, 5]]\n");
+    }
+}
+
+// === 9. 遇到第一个错误就停止的 Stream ===
+
+/// 收集成功的元素，直到遇到第一个错误为止
+///
+/// 返回已收集的元素以及那个错误（如果有）；错误之后的元素不会再被拉取。
+async fn try_collect_until_err<S, T, E>(s: S) -> (Vec<T>, Option<E>)
+where
+    S: Stream<Item = Result<T, E>>,
+{
+    futures::pin_mut!(s);
+    let mut items = Vec::new();
+    while let Some(item) = s.next().await {
+        match item {
+            Ok(value) => items.push(value),
+            Err(e) => return (items, Some(e)),
+        }
+    }
+    (items, None)
+}
+
+async fn try_collect_demo() {
+    println!("=== 9. 遇到第一个错误就停止 ===");
+    println!("📝 输入 [Ok(1), Ok(2), Err(\"x\"), Ok(3)]\n");
+    
+    let source = stream::iter(vec![Ok(1), Ok(2), Err("x"), Ok(3)]);
+    let (items, err) = try_collect_until_err(source).await;
+    println!("   📥 成功收集: {:?}，停在错误: {:?}", items, err);
+    if items == [1, 2] && err == Some("x") {
+        println!("   ✅ 只收集到第一个错误之前的元素，并返回了这个错误\n");
+    } else {
+        println!("   ❌ 预期 ([1, 2], Some(\"x\"))\n");
+    }
+}
+
+// === 10. 轮流交错两个 Stream ===
+
+/// 严格轮流地从 a、b 取元素：先等 a，再等 b，如此往复
+///
+/// 与 merge（谁先就绪取谁）不同，这里顺序是确定的；某一边结束后继续输出另一边剩下的元素。
+fn interleave<S1, S2>(a: S1, b: S2) -> impl Stream<Item = S1::Item>
+where
+    S1: Stream,
+    S2: Stream<Item = S1::Item>,
+{
+    let state = (Some(Box::pin(a)), Some(Box::pin(b)), true);
+    stream::unfold(state, |(mut a, mut b, a_turn)| async move {
+        loop {
+            // 轮到的一边已经结束时，改从另一边取
+            let from_a = match (&a, &b) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(_), Some(_)) => a_turn,
+            };
+            let next = if from_a {
+                a.as_mut()?.next().await
+            } else {
+                b.as_mut()?.next().await
+            };
+            match next {
+                Some(item) => return Some((item, (a, b, !from_a))),
+                None if from_a => a = None,
+                None => b = None,
+            }
+        }
+    })
+}
+
+async fn interleave_demo() {
+    println!("=== 10. 轮流交错两个 Stream ===");
+    println!("📝 a = [1, 3, 5], b = [2, 4]\n");
+    
+    let merged: Vec<i32> = interleave(stream::iter(vec![1, 3, 5]), stream::iter(vec![2, 4]))
+        .collect()
+        .await;
+    println!("   🔀 交错结果: {:?}", merged);
+    if merged == [1, 2, 3, 4, 5] {
+        println!("   ✅ 严格轮流取元素，b 结束后继续输出 a 剩下的 5\n");
+    } else {
+        println!("   ❌ 预期 [1, 2, 3, 4, 5]\n");
+    }
+}
+
+// === 11. 带下标的 Stream ===
+
+/// 给每个元素配上从 0 开始的下标，对应 Iterator::enumerate
+fn enumerate<S: Stream>(s: S) -> impl Stream<Item = (usize, S::Item)> {
+    let mut index = 0;
+    s.map(move |item| {
+        let current = index;
+        index += 1;
+        (current, item)
+    })
+}
+
+async fn enumerate_demo() {
+    println!("=== 11. 带下标的 Stream ===");
+    
+    let indexed: Vec<(usize, char)> = enumerate(stream::iter(vec!['a', 'b', 'c'])).collect().await;
+    println!("   🔢 enumerate: {:?}", indexed);
+    if indexed == [(0, 'a'), (1, 'b'), (2, 'c')] {
+        println!("   ✅ 下标从 0 开始依次递增\n");
+    } else {
+        println!("   ❌ 预期 [(0, 'a'), (1, 'b'), (2, 'c')]\n");
+    }
+}
+
+// === 12. 连续卡顿多次后关闭的 Stream ===
+
+/// 每个元素最多等 per_item；允许连续超时 max_stalls 次，再超时就结束 Stream
+///
+/// 任何一次成功拿到元素都会把卡顿计数清零。超时只是放弃本次等待，
+/// next() 可以安全取消，元素不会丢失，下一轮会继续等它。
+fn close_after_n_stalls<S: Stream>(s: S, per_item: Duration, max_stalls: u32) -> impl Stream<Item = S::Item> {
+    stream::unfold((Box::pin(s), 0u32), move |(mut s, mut stalls)| async move {
+        loop {
+            match tokio::time::timeout(per_item, s.next()).await {
+                Ok(Some(item)) => return Some((item, (s, 0))),
+                Ok(None) => return None,
+                Err(_) => {
+                    stalls += 1;
+                    if stalls > max_stalls {
+                        return None;
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn close_after_stalls_demo() {
+    println!("=== 12. 连续卡顿多次后关闭 ===");
+    println!("📝 每个元素限时 100ms，最多容忍连续 2 次卡顿\n");
+    
+    // 各元素的到达延迟：第 2 个卡 1 次，第 3 个卡 2 次，第 4 个卡 3 次
+    let delays = [(1, 10), (2, 150), (3, 250), (4, 350)];
+    let source = stream::iter(delays).then(|(value, ms)| async move {
+        sleep(Duration::from_millis(ms)).await;
+        value
+    });
+    
+    let received: Vec<i32> = close_after_n_stalls(source, Duration::from_millis(100), 2).collect().await;
+    println!("   📥 收到: {:?}", received);
+    if received == [1, 2, 3] {
+        println!("   ✅ 第 3 个元素连续卡顿 2 次仍然收到，第 4 个元素第 3 次卡顿时放弃\n");
+    } else {
+        println!("   ❌ 预期 [1, 2, 3]\n");
+    }
+}
+
+// === 13. 展开为子 Stream ===
+
+/// 把每个元素映射成一个子 Stream，并按顺序依次展开
+///
+/// 前一个子 Stream 结束后才开始拉取下一个元素，输出顺序与输入一致。
+fn flat_map_async<S, F, St>(s: S, f: F) -> impl Stream<Item = St::Item>
+where
+    S: Stream,
+    F: FnMut(S::Item) -> St,
+    St: Stream,
+{
+    s.map(f).flatten()
+}
+
+async fn flat_map_demo() {
+    println!("=== 13. 展开为子 Stream ===");
+    println!("📝 n -> iter(0..n)，输入 [1, 2, 3]\n");
+    
+    let expanded: Vec<i32> = flat_map_async(stream::iter(vec![1, 2, 3]), |n| stream::iter(0..n))
+        .collect()
+        .await;
+    println!("   🌿 展开结果: {:?}", expanded);
+    if expanded == [0, 0, 1, 0, 1, 2] {
+        println!("   ✅ 子 Stream 按输入顺序依次展开\n");
+    } else {
+        println!("   ❌ 预期 [0, 0, 1, 0, 1, 2]\n");
+    }
+}
+
+// === 14. 空闲时发心跳的 Stream ===
+
+/// 转发 s 的元素；s 连续 every 时长没有产出时插入一个 beat
+///
+/// 常用于让下游连接保持活跃。超时只是放弃这一轮等待，原元素不会丢失。
+fn with_heartbeat<S, T>(s: S, every: Duration, beat: T) -> impl Stream<Item = T>
+where
+    S: Stream<Item = T>,
+    T: Clone,
+{
+    stream::unfold(Box::pin(s), move |mut s| {
+        let beat = beat.clone();
+        async move {
+            match tokio::time::timeout(every, s.next()).await {
+                Ok(Some(item)) => Some((item, s)),
+                Ok(None) => None,
+                Err(_) => Some((beat, s)),
+            }
+        }
+    })
+}
+
+async fn heartbeat_demo() {
+    println!("=== 14. 空闲时发心跳 ===");
+    println!("📝 心跳间隔 100ms，数据源在两条数据之间停顿 350ms\n");
+    
+    let source = stream::iter(vec![("数据1", 10), ("数据2", 350)]).then(|(item, ms)| async move {
+        sleep(Duration::from_millis(ms)).await;
+        item
+    });
+    
+    let start = Instant::now();
+    let s = with_heartbeat(source, Duration::from_millis(100), "💓");
+    futures::pin_mut!(s);
+    let mut received = Vec::new();
+    while let Some(item) = s.next().await {
+        let at = start.elapsed();
+        println!("   [{:>3}ms] {}", at.as_millis(), item);
+        received.push((item, at));
+    }
+    
+    // 停顿 350ms 期间每 100ms 一个心跳，共 3 个，之后真实数据到达
+    let items: Vec<&str> = received.iter().map(|(item, _)| *item).collect();
+    let beats: Vec<Duration> = received.iter().filter(|(item, _)| *item == "💓").map(|(_, at)| *at).collect();
+    let spaced = beats.windows(2).all(|w| {
+        let gap = w[1] - w[0];
+        gap >= Duration::from_millis(90) && gap <= Duration::from_millis(140)
+    });
+    if items == ["数据1", "💓", "💓", "💓", "数据2"] && spaced {
+        println!("   ✅ 停顿期间按 100ms 间隔插入了 {} 个心跳，数据到达后停止\n", beats.len());
+    } else {
+        println!("   ❌ 预期 数据1、3 个间隔约 100ms 的心跳、数据2，实际 {:?}\n", items);
+    }
+}
+
+// === 15. 滑动窗口 ===
+
+/// 每来一个新元素就输出最近 size 个元素组成的窗口（相邻窗口互相重叠）
+///
+/// 至少收到 size 个元素后才开始输出；与不重叠的分块不同，每个元素会出现在多个窗口里。
+/// size 为 0 时 panic：窗口永远凑不满，流只会不断缓存元素而不输出。
+fn sliding_windows<S>(s: S, size: usize) -> impl Stream<Item = Vec<S::Item>>
+where
+    S: Stream,
+    S::Item: Clone,
+{
+    assert!(size > 0, "滑动窗口的大小必须大于 0");
+    let mut window = std::collections::VecDeque::with_capacity(size);
+    s.filter_map(move |item| {
+        if window.len() == size {
+            window.pop_front();
+        }
+        window.push_back(item);
+        let full = (window.len() == size).then(|| window.iter().cloned().collect());
+        async move { full }
+    })
+}
+
+async fn sliding_windows_demo() {
+    println!("=== 15. 滑动窗口 ===");
+    
+    let windows: Vec<Vec<i32>> = sliding_windows(stream::iter(vec![1, 2, 3, 4]), 2).collect().await;
+    println!("   🪟 [1, 2, 3, 4] 大小为 2 的滑动窗口: {:?}", windows);
+    if windows == [vec![1, 2], vec![2, 3], vec![3, 4]] {
+        println!("   ✅ 相邻窗口重叠一个元素，凑满 2 个后才开始输出\n");
+    } else {
+        println!("   ❌ 预期 [[1, 2], [2, 3], [3, 4]]\n");
+    }
+}
+
+// === 16. 带进度百分比的 Stream ===
+
+/// 给每个元素配上进度百分比：已输出个数 / total，超过 total 时停在 100
+///
+/// 适合给已知长度的异步任务画命令行进度条；total 为 0 时一律视为 100%。
+fn with_percent<S: Stream>(s: S, total: usize) -> impl Stream<Item = (S::Item, u8)> {
+    let mut emitted = 0usize;
+    s.map(move |item| {
+        emitted += 1;
+        let percent = (emitted * 100).checked_div(total).map_or(100, |p| p.min(100));
+        (item, percent as u8)
+    })
+}
+
+async fn with_percent_demo() {
+    println!("=== 16. 带进度百分比的 Stream ===");
+    
+    let exact: Vec<u8> = with_percent(stream::iter(0..4), 4).map(|(_, p)| p).collect().await;
+    println!("   📊 iter(0..4)，total = 4: {:?}", exact);
+    
+    let overflow: Vec<u8> = with_percent(stream::iter(0..6), 4).map(|(_, p)| p).collect().await;
+    println!("   📊 iter(0..6)，total = 4（超出预期）: {:?}", overflow);
+    if exact == [25, 50, 75, 100] && overflow == [25, 50, 75, 100, 100, 100] {
+        println!("   ✅ 进度按 total 均匀递增，超出 total 的元素停在 100%\n");
+    } else {
+        println!("   ❌ 预期 [25, 50, 75, 100] 和 [25, 50, 75, 100, 100, 100]\n");
+    }
+}
+
+// === 17. 手写 Timeout Future ===
+
+/// 超时错误：内部 Future 在期限内没有完成
+#[derive(Debug, PartialEq)]
+struct Elapsed;
+
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+pin_project! {
+    /// tokio::time::timeout 的简化版：同时持有内部 Future 和一个 Sleep
+    ///
+    /// inner 和 delay 都可能是 !Unpin（Sleep 就是），所以 poll 时要把 Pin<&mut Self>
+    /// 投影成两个字段各自的 Pin<&mut _>（pin projection），由 pin_project! 安全地生成。
+    struct Timeout<F> {
+        #[pin]
+        inner: F,
+        #[pin]
+        delay: tokio::time::Sleep,
+    }
+}
+
+/// 给 f 加上 dur 的期限
+fn timeout<F: Future>(dur: Duration, f: F) -> Timeout<F> {
+    Timeout {
+        inner: f,
+        delay: tokio::time::sleep(dur),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+    
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        
+        // 先 poll 内部 Future：即使期限同时到达，已经完成的结果也不会被丢掉
+        if let Poll::Ready(output) = this.inner.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        // 两个 poll 都登记了同一个 waker，任何一个就绪都会再次唤醒我们
+        match this.delay.poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+async fn timeout_future_demo() {
+    println!("=== 17. 手写 Timeout Future ===");
+    println!("📝 用 2 秒的期限分别包住一个 100ms 的任务和一个 3 秒的 sleep\n");
+    
+    let quick = timeout(Duration::from_secs(2), async {
+        sleep(Duration::from_millis(100)).await;
+        42
+    })
+    .await;
+    println!("   ⚡ 100ms 的任务: {:?}", quick);
+    
+    let start = Instant::now();
+    let slow = timeout(Duration::from_secs(2), sleep(Duration::from_secs(3))).await;
+    let elapsed = start.elapsed();
+    match &slow {
+        Ok(()) => println!("   ❌ 3 秒的 sleep 竟然在期限内完成"),
+        Err(e) => println!("   ⏱️  3 秒的 sleep: Err({})，用时 {:.1} 秒", e, elapsed.as_secs_f64()),
+    }
+    
+    let ok = quick == Ok(42) && slow == Err(Elapsed) && elapsed < Duration::from_millis(2500);
+    println!("   {} 期限内完成返回 Ok，超时返回 Err 且不会等满 3 秒\n", if ok { "✅" } else { "❌" });
+}
+
+// === 18. 手写 Select2 Future ===
+
+/// 和 JoinFuture 相对：两个 Future 中任意一个完成就结束，另一个被丢弃（取消）
+struct Select2<A, B> {
+    a: Option<A>,
+    b: Option<B>,
+}
+
+impl<A, B> Select2<A, B> {
+    fn new(a: A, b: B) -> Self {
+        Select2 { a: Some(a), b: Some(b) }
+    }
+}
+
+impl<A, B> Future for Select2<A, B>
+where
+    A: Future + Unpin,
+    B: Future + Unpin,
+{
+    type Output = Either<A::Output, B::Output>;
+    
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let (Some(a), Some(b)) = (this.a.as_mut(), this.b.as_mut()) else {
+            panic!("Select2 完成后又被 poll");
+        };
+        
+        // 两个都就绪时 a 优先，和 select! 的 biased 模式一样
+        let output = if let Poll::Ready(val) = Pin::new(a).poll(cx) {
+            Either::Left(val)
+        } else if let Poll::Ready(val) = Pin::new(b).poll(cx) {
+            Either::Right(val)
+        } else {
+            return Poll::Pending;
+        };
+        
+        // 立即丢弃两个 Future：输掉的那个在这里被取消，不必等 Select2 本身被 drop
+        this.a = None;
+        this.b = None;
+        Poll::Ready(output)
+    }
+}
+
+async fn fast_task() -> &'static str {
+    sleep(Duration::from_secs(1)).await;
+    "⚡ 快速任务完成"
+}
+
+async fn slow_task() -> &'static str {
+    sleep(Duration::from_secs(3)).await;
+    "🐌 慢速任务完成"
+}
+
+async fn select2_demo() {
+    println!("=== 18. 手写 Select2 Future ===");
+    println!("📝 手动实现类似 select! 的功能：1 秒的快速任务对 3 秒的慢速任务\n");
+    
+    let start = Instant::now();
+    let winner = Select2::new(Box::pin(slow_task()), Box::pin(fast_task())).await;
+    let elapsed = start.elapsed();
+    
+    match winner {
+        Either::Left(result) => println!("   {}（慢速任务获胜）", result),
+        Either::Right(result) => println!("   {}（快速任务获胜）", result),
+    }
+    let ok = matches!(winner, Either::Right(_)) && elapsed < Duration::from_secs(2);
+    println!("   {} 快速任务获胜，用时 {:.1} 秒，慢速任务已被丢弃\n",
+             if ok { "✅" } else { "❌" }, elapsed.as_secs_f64());
+}
+
+// === 19. 手写 Stream：定时计数器 ===
+
+pin_project! {
+    /// 每隔 period 产出下一个整数（1, 2, ...），产出 max 个之后结束
+    ///
+    /// delay 是内嵌的 Sleep（!Unpin），所以 Interval 本身也是 !Unpin，
+    /// poll_next 里需要把它投影成 Pin<&mut Sleep> 才能 poll 和 reset。
+    struct Interval {
+        count: u32,
+        max: u32,
+        period: Duration,
+        #[pin]
+        delay: tokio::time::Sleep,
+    }
+}
+
+impl Interval {
+    fn new(period: Duration, max: u32) -> Self {
+        Interval {
+            count: 0,
+            max,
+            period,
+            delay: tokio::time::sleep(period),
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = u32;
+    
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<u32>> {
+        // 只有 delay 标了 #[pin]，得到 Pin<&mut Sleep>；count 等其余字段得到普通的 &mut
+        let this = self.project();
+        if *this.count == *this.max {
+            return Poll::Ready(None);
+        }
+        
+        let mut delay = this.delay;
+        // 还没到时间：Sleep 已经登记了 waker，直接返回 Pending
+        std::task::ready!(delay.as_mut().poll(cx));
+        
+        *this.count += 1;
+        // 重新设定下一次的截止时间，复用同一个 Sleep 而不是新建
+        delay.reset(tokio::time::Instant::now() + *this.period);
+        Poll::Ready(Some(*this.count))
+    }
+}
+
+async fn interval_stream_demo() {
+    println!("=== 19. 手写 Stream：定时计数器 ===");
+    println!("📝 Interval 每 200ms 产出一个数，共 5 个\n");
+    
+    let start = Instant::now();
+    let interval = Interval::new(Duration::from_millis(200), 5);
+    futures::pin_mut!(interval);
+    
+    let mut values = Vec::new();
+    while let Some(n) = interval.next().await {
+        println!("   ⏰ {} @ {:>4}ms", n, start.elapsed().as_millis());
+        values.push(n);
+    }
+    let elapsed = start.elapsed();
+    
+    let ok = values == [1, 2, 3, 4, 5] && elapsed >= Duration::from_millis(1000);
+    println!("   {} 产出 {:?} 后返回 None，用时 {}ms\n", if ok { "✅" } else { "❌" }, values, elapsed.as_millis());
+}
+
+// === 20. 手写 Retry Future ===
+
+pin_project! {
+    /// 失败时重新调用 factory 生成新的 Future，最多尝试 max_attempts 次
+    ///
+    /// 两次尝试之间可以插入一个退避 Sleep。输出是最后一次尝试的 Result：
+    /// 成功则立即返回，次数用完则返回最后一个错误。
+    struct Retry<F, Fut> {
+        factory: F,
+        max_attempts: usize,
+        attempts: usize,
+        backoff: Option<Duration>,
+        #[pin]
+        current: Option<Fut>,
+        #[pin]
+        delay: Option<tokio::time::Sleep>,
+    }
+}
+
+/// 创建重试 Future；max_attempts 为 0 时按 1 次处理
+fn retry<F, Fut, T, E>(max_attempts: usize, factory: F) -> Retry<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    Retry {
+        factory,
+        max_attempts: max_attempts.max(1),
+        attempts: 0,
+        backoff: None,
+        current: None,
+        delay: None,
+    }
+}
+
+impl<F, Fut> Retry<F, Fut> {
+    /// 每次失败后先等待 backoff 再重试
+    fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = Some(backoff);
+        self
+    }
+}
+
+impl<F, Fut, T, E> Future for Retry<F, Fut>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    type Output = Result<T, E>;
+    
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            // 正在退避：等 Sleep 到期后再发起下一次尝试
+            if let Some(delay) = this.delay.as_mut().as_pin_mut() {
+                std::task::ready!(delay.poll(cx));
+                this.delay.set(None);
+            }
+            
+            if this.current.is_none() {
+                *this.attempts += 1;
+                this.current.set(Some((this.factory)()));
+            }
+            let result = std::task::ready!(this.current.as_mut().as_pin_mut().unwrap().poll(cx));
+            this.current.set(None);
+            
+            match result {
+                Err(_) if *this.attempts < *this.max_attempts => {
+                    if let Some(backoff) = *this.backoff {
+                        this.delay.set(Some(tokio::time::sleep(backoff)));
+                    }
+                }
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+async fn retry_demo() {
+    println!("=== 20. 手写 Retry Future ===");
+    println!("📝 一个前两次失败、第三次成功的操作，最多尝试 5 次，每次失败后退避 100ms\n");
+    
+    let calls = std::cell::Cell::new(0);
+    let flaky = || {
+        calls.set(calls.get() + 1);
+        let n = calls.get();
+        async move {
+            sleep(Duration::from_millis(20)).await;
+            if n < 3 {
+                println!("   ❌ 第 {} 次调用失败", n);
+                Err(format!("第 {} 次调用失败", n))
+            } else {
+                println!("   ✅ 第 {} 次调用成功", n);
+                Ok(n)
+            }
+        }
+    };
+    
+    let start = Instant::now();
+    let result = retry(5, flaky).with_backoff(Duration::from_millis(100)).await;
+    println!("   📦 结果: {:?}，用时 {}ms", result, start.elapsed().as_millis());
+    println!("   {} 共调用 {} 次\n", if result == Ok(3) && calls.get() == 3 { "✅" } else { "❌" }, calls.get());
+    
+    // 次数用完：返回最后一次的错误
+    let always_fails = retry(2, || async { Err::<(), _>("服务不可用") }).await;
+    println!("   🛑 总是失败的操作，最多 2 次: {:?}\n", always_fails);
+}
+
+// === 21. 限速 Stream：相邻元素至少间隔 min_interval ===
+
+pin_project! {
+    /// 每 min_interval 最多放出一个元素；来得太快的元素要等到间隔满了才放出
+    ///
+    /// delay 一开始就是到期的，所以第一个元素立即放出；之后每放出一个就把 delay
+    /// 重设为 now + min_interval。等待 delay 时不 poll 内部 Stream，背压自然传给上游。
+    struct Throttle<S> {
+        #[pin]
+        stream: S,
+        #[pin]
+        delay: tokio::time::Sleep,
+        min_interval: Duration,
+    }
+}
+
+/// 给 Stream 加上最小间隔
+fn throttle<S: Stream>(s: S, min_interval: Duration) -> Throttle<S> {
+    Throttle {
+        stream: s,
+        delay: tokio::time::sleep(Duration::ZERO),
+        min_interval,
+    }
+}
+
+impl<S: Stream> Stream for Throttle<S> {
+    type Item = S::Item;
+    
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let mut this = self.project();
+        
+        // 已经到期的 Sleep 再次 poll 仍然返回 Ready，不需要额外的状态标记
+        std::task::ready!(this.delay.as_mut().poll(cx));
+        
+        let item = std::task::ready!(this.stream.poll_next(cx));
+        if item.is_some() {
+            this.delay.reset(tokio::time::Instant::now() + *this.min_interval);
+        }
+        Poll::Ready(item)
+    }
+}
+
+async fn throttle_demo() {
+    println!("=== 21. 限速 Stream：相邻元素至少间隔 min_interval ===");
+    println!("📝 5 个元素同时就绪，throttle 200ms 后依次放出\n");
+    
+    let start = Instant::now();
+    let throttled = throttle(stream::iter(1..=5), Duration::from_millis(200));
+    futures::pin_mut!(throttled);
+    
+    let mut stamps = Vec::new();
+    while let Some(n) = throttled.next().await {
+        let at = start.elapsed();
+        println!("   🚦 元素 {} @ {:>4}ms", n, at.as_millis());
+        stamps.push(at);
+    }
+    
+    let min_gap = stamps.windows(2).map(|w| w[1] - w[0]).min().unwrap_or_default();
+    let ok = stamps.len() == 5 && stamps[0] < Duration::from_millis(50) && min_gap >= Duration::from_millis(195);
+    println!("   {} 第一个立即放出，之后相邻间隔最短 {}ms\n", if ok { "✅" } else { "❌" }, min_gap.as_millis());
+}
+
 #[tokio::main]
 async fn main() {
     println!("🎓 Futures 和 Pin 深入理解教程\n");
@@ -270,9 +1279,27 @@ async fn main() {
     
     custom_future_demo().await;
     pin_demo().await;
+    self_ref_future_demo().await;
+    self_referential_demo().await;
     combined_future_demo().await;
     stream_demo().await;
     waker_concept().await;
+    flag_future_demo().await;
+    permit_stream_demo().await;
+    window_by_time_demo().await;
+    try_collect_demo().await;
+    interleave_demo().await;
+    enumerate_demo().await;
+    close_after_stalls_demo().await;
+    flat_map_demo().await;
+    heartbeat_demo().await;
+    sliding_windows_demo().await;
+    with_percent_demo().await;
+    timeout_future_demo().await;
+    select2_demo().await;
+    interval_stream_demo().await;
+    retry_demo().await;
+    throttle_demo().await;
     
     println!("🎉 教程完成！\n");
     println!("💡 关键要点：");
@@ -282,6 +1309,182 @@ async fn main() {
     println!("   • Unpin 表示类型可以安全移动");
     println!("   • async/await 是 Future 的语法糖");
     println!("   • Stream 是异步版本的 Iterator");
+    println!("   • 手写 poll_next：未就绪返回 Pending，有值 Some，结束 None");
     println!("   • Waker 机制让运行时知道何时重新 poll");
+    println!("   • interleave 严格轮流取值，merge 则谁先就绪取谁");
+    println!("   • 组合子 Future 通过 pin projection 轮流 poll 自己的字段");
+    println!("   • Select2 先完成者胜，输家随即被 drop，这就是 select! 的取消语义");
+    println!("   • Retry 持有生成 Future 的工厂，失败时丢弃旧 Future 再造一个新的");
+    println!("   • Throttle 在内部 Sleep 到期前不 poll 上游，以此控制输出间隔");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test(start_paused = true)]
+    async fn flag_future_resolves_after_set_without_busy_polling() {
+        let future = FlagFuture::new();
+        let signal = future.clone();
+        
+        let waiter = tokio::spawn(async move {
+            let start = tokio::time::Instant::now();
+            let mut future = future;
+            let mut polls = 0;
+            std::future::poll_fn(|cx| {
+                polls += 1;
+                Pin::new(&mut future).poll(cx)
+            })
+            .await;
+            (polls, start.elapsed())
+        });
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(500)).await;
+            signal.set();
+        });
+        
+        let (polls, elapsed) = waiter.await.unwrap();
+        // 第一次 Pending，set() 唤醒后第二次 Ready，中间没有多余的 poll
+        assert_eq!(polls, 2);
+        assert_eq!(elapsed, Duration::from_millis(500));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn permit_stream_bursts_then_paces_at_rate() {
+        let start = tokio::time::Instant::now();
+        let permits = permit_stream(5, 3).take(7);
+        futures::pin_mut!(permits);
+        let mut times = Vec::new();
+        while permits.next().await.is_some() {
+            times.push(start.elapsed());
+        }
+        
+        // 前 burst 个立即拿到，之后每 1/rate = 200ms 一个
+        assert_eq!(&times[..3], [Duration::ZERO; 3]);
+        for (i, at) in times[3..].iter().enumerate() {
+            let expected = Duration::from_millis(200 * (i as u64 + 1));
+            assert!(at.abs_diff(expected) < Duration::from_millis(1), "许可 {} @ {:?}", i + 4, at);
+        }
+    }
+    
+    /// 按 (元素, 距上一个元素的间隔 ms) 逐个产出的 Stream
+    fn delayed<T>(items: Vec<(T, u64)>) -> impl Stream<Item = T> {
+        stream::iter(items).then(|(item, gap)| async move {
+            sleep(Duration::from_millis(gap)).await;
+            item
+        })
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn window_by_time_groups_items_by_arrival_window() {
+        // 窗口 300ms：1、2、3 在 0~100ms 到达，4、5 在 350~400ms 到达
+        let source = delayed(vec![(1, 0), (2, 50), (3, 50), (4, 250), (5, 50)]);
+        let batches: Vec<Vec<i32>> = window_by_time(source, Duration::from_millis(300)).collect().await;
+        assert_eq!(batches, [vec![1, 2, 3], vec![4, 5]]);
+    }
+    
+    #[tokio::test]
+    async fn word_parser_borrows_its_own_text_across_polls() {
+        let text = "自引用 结构体  必须 先被\t固定 pin";
+        let expected: Vec<&str> = text.split_whitespace().collect();
+        
+        let mut parser = Box::pin(WordParser::new(text));
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut polls = 0;
+        let words = loop {
+            polls += 1;
+            if let Poll::Ready(words) = parser.as_mut().poll(&mut cx) {
+                break words;
+            }
+            // 两次 poll 之间 rest 一直指向 parser 自己的 text
+            let this = parser.as_mut().project();
+            let rest = this.rest.unwrap() as *const u8;
+            let bytes = this.text.as_bytes().as_ptr_range();
+            assert!(bytes.contains(&rest) || rest == bytes.end);
+        };
+        assert_eq!(words, expected);
+        // 每个词一次 Pending，最后一次 Ready
+        assert_eq!(polls, expected.len() + 1);
+        
+        assert!(WordParser::new("   ").await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn try_collect_until_err_stops_at_first_error() {
+        let source = stream::iter(vec![Ok(1), Ok(2), Err("x"), Ok(3)]);
+        assert_eq!(try_collect_until_err(source).await, (vec![1, 2], Some("x")));
+        
+        let all_ok = stream::iter(vec![Ok::<_, &str>(1), Ok(2)]);
+        assert_eq!(try_collect_until_err(all_ok).await, (vec![1, 2], None));
+    }
+    
+    #[tokio::test]
+    async fn interleave_alternates_then_drains_the_longer_stream() {
+        let merged: Vec<i32> = interleave(stream::iter([1, 3, 5]), stream::iter([2, 4])).collect().await;
+        assert_eq!(merged, [1, 2, 3, 4, 5]);
+        
+        let merged: Vec<i32> = interleave(stream::iter([1]), stream::iter([2, 4, 6])).collect().await;
+        assert_eq!(merged, [1, 2, 4, 6]);
+    }
+    
+    #[tokio::test]
+    async fn enumerate_pairs_items_with_zero_based_index() {
+        let indexed: Vec<(usize, char)> = enumerate(stream::iter(['a', 'b', 'c'])).collect().await;
+        assert_eq!(indexed, [(0, 'a'), (1, 'b'), (2, 'c')]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn close_after_n_stalls_survives_two_stalls_then_gives_up_on_third() {
+        // 限时 100ms：1 卡 1 次、2 卡 2 次、3 准时（计数归零）、4 卡 3 次
+        let source = delayed(vec![(1, 150), (2, 250), (3, 50), (4, 350)]);
+        let start = tokio::time::Instant::now();
+        let received: Vec<i32> = close_after_n_stalls(source, Duration::from_millis(100), 2).collect().await;
+        assert_eq!(received, [1, 2, 3]);
+        // 第 3 次连续超时发生在 3 之后 300ms
+        assert_eq!(start.elapsed(), Duration::from_millis(150 + 250 + 50 + 300));
+    }
+    
+    #[tokio::test]
+    async fn flat_map_async_expands_each_item_in_order() {
+        let flattened: Vec<u32> = flat_map_async(stream::iter([1, 2, 3]), |n| stream::iter(0..n)).collect().await;
+        assert_eq!(flattened, [0, 0, 1, 0, 1, 2]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_fills_idle_gaps_until_real_item_arrives() {
+        let source = delayed(vec![("a", 10), ("b", 350)]);
+        let start = tokio::time::Instant::now();
+        let timed: Vec<(&str, u128)> = with_heartbeat(source, Duration::from_millis(100), "beat")
+            .map(|item| (item, start.elapsed().as_millis()))
+            .collect()
+            .await;
+        assert_eq!(timed, [("a", 10), ("beat", 110), ("beat", 210), ("beat", 310), ("b", 360)]);
+    }
+    
+    #[tokio::test]
+    async fn sliding_windows_overlap_by_all_but_one_item() {
+        let windows: Vec<Vec<i32>> = sliding_windows(stream::iter([1, 2, 3, 4]), 2).collect().await;
+        assert_eq!(windows, [vec![1, 2], vec![2, 3], vec![3, 4]]);
+        
+        // 元素不足一个窗口时什么也不输出
+        let windows: Vec<Vec<i32>> = sliding_windows(stream::iter([1, 2]), 3).collect().await;
+        assert!(windows.is_empty());
+    }
+    
+    #[test]
+    #[should_panic(expected = "滑动窗口的大小必须大于 0")]
+    fn sliding_windows_rejects_zero_size() {
+        let _ = sliding_windows(stream::iter([1, 2]), 0);
+    }
+    
+    #[tokio::test]
+    async fn with_percent_reports_progress_and_clamps_at_100() {
+        let percents: Vec<u8> = with_percent(stream::iter(0..4), 4).map(|(_, p)| p).collect().await;
+        assert_eq!(percents, [25, 50, 75, 100]);
+        
+        let percents: Vec<u8> = with_percent(stream::iter(0..6), 4).map(|(_, p)| p).collect().await;
+        assert_eq!(percents, [25, 50, 75, 100, 100, 100]);
+    }
+}
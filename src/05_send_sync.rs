@@ -10,15 +10,15 @@ use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 use tokio::time::{sleep, Duration};
 
-/// === 核心概念 ===
-///
-/// Send: 类型可以安全地在线程间转移所有权
-/// - 实现 Send 的类型可以被移动到另一个线程
-/// - 大部分类型都是 Send
-/// 
-/// Sync: 类型可以安全地在线程间共享引用
-/// - 如果 &T 是 Send，那么 T 就是 Sync
-/// - 实现 Sync 的类型可以被多个线程同时访问
+// === 核心概念 ===
+//
+// Send: 类型可以安全地在线程间转移所有权
+// - 实现 Send 的类型可以被移动到另一个线程
+// - 大部分类型都是 Send
+// 
+// Sync: 类型可以安全地在线程间共享引用
+// - 如果 &T 是 Send，那么 T 就是 Sync
+// - 实现 Sync 的类型可以被多个线程同时访问
 
 /// 演示 Send - 可以在线程间转移
 async fn send_demo() {
@@ -52,7 +52,7 @@ async fn not_send_demo() {
     let rc = Rc::new(42);
     println!("✅ Rc 在本地线程使用没问题: {}", rc);
     
-    // 下面的代码会编译错误！
+    // 下面的代码会编译错误！（tests/send_sync.rs 用 trybuild 验证这一点）
     // let handle = tokio::spawn(async move {
     //     println!("{}", rc); // ❌ 错误：Rc 不是 Send
     // });
@@ -244,6 +244,9 @@ async fn common_mistakes() {
     println!("❌ 错误 3：在 .await 点持有 std::sync::Mutex");
     println!("   解决方案：使用 tokio::sync::Mutex 或缩小锁的作用域\n");
     
+    println!("❌ 错误 4：不同任务以不同顺序获取多把锁（死锁，见第 9 部分）");
+    println!("   解决方案：所有任务按同一个固定顺序加锁\n");
+    
     println!("✅ 示例：正确的模式");
     
     // 正确：使用 Arc + Mutex
@@ -262,6 +265,65 @@ async fn common_mistakes() {
     println!("   数据: {:?}\n", data.lock().unwrap());
 }
 
+/// 先锁 first，停一会儿让另一个任务也拿到它的第一把锁，再去锁 second
+async fn lock_in_order(
+    task: &str,
+    first: (&str, &tokio::sync::Mutex<i32>),
+    second: (&str, &tokio::sync::Mutex<i32>),
+) {
+    let mut a = first.1.lock().await;
+    println!("   {} 拿到锁 {}，等待锁 {}", task, first.0, second.0);
+    sleep(Duration::from_millis(50)).await;
+    let mut b = second.1.lock().await;
+    *a += 1;
+    *b += 1;
+}
+
+/// 演示两把锁的经典死锁：两个任务以相反的顺序加锁，互相等待对方持有的锁
+async fn deadlock_demo() {
+    println!("=== 9. 死锁：以相反顺序获取两把锁 ===");
+    println!("📝 任务 1 先锁 A 再锁 B，任务 2 先锁 B 再锁 A，整体限时 1 秒\n");
+    
+    let a = Arc::new(tokio::sync::Mutex::new(0));
+    let b = Arc::new(tokio::sync::Mutex::new(0));
+    
+    let (a1, b1) = (a.clone(), b.clone());
+    let task1 = tokio::spawn(async move { lock_in_order("任务 1", ("A", &a1), ("B", &b1)).await });
+    let (a2, b2) = (a.clone(), b.clone());
+    let task2 = tokio::spawn(async move { lock_in_order("任务 2", ("B", &b2), ("A", &a2)).await });
+    
+    let (abort1, abort2) = (task1.abort_handle(), task2.abort_handle());
+    match tokio::time::timeout(Duration::from_secs(1), async { tokio::join!(task1, task2) }).await {
+        Ok(_) => println!("\n   ❌ 两个任务都完成了，没有发生死锁\n"),
+        Err(_) => {
+            // 死锁的任务永远不会自己结束，手动中止以释放它们持有的锁
+            // （先中止的任务释放锁后，另一个任务可能赶在被中止前拿到锁，这无关紧要）
+            abort1.abort();
+            abort2.abort();
+            println!("\n   ✅ 1 秒内没有完成：检测到死锁，已中止两个任务\n");
+        }
+    }
+}
+
+/// 修复死锁：所有任务都按 A → B 的固定顺序加锁
+async fn deadlock_fixed() {
+    println!("=== 10. 修复：固定加锁顺序 ===");
+    println!("📝 两个任务都先锁 A 再锁 B，后到的任务在 A 上排队，不会形成环形等待\n");
+    
+    let a = Arc::new(tokio::sync::Mutex::new(0));
+    let b = Arc::new(tokio::sync::Mutex::new(0));
+    
+    let (a1, b1) = (a.clone(), b.clone());
+    let task1 = tokio::spawn(async move { lock_in_order("任务 1", ("A", &a1), ("B", &b1)).await });
+    let (a2, b2) = (a.clone(), b.clone());
+    let task2 = tokio::spawn(async move { lock_in_order("任务 2", ("A", &a2), ("B", &b2)).await });
+    
+    match tokio::time::timeout(Duration::from_secs(1), async { tokio::join!(task1, task2) }).await {
+        Ok(_) => println!("\n   ✅ 两个任务都完成，A = {}，B = {}\n", *a.lock().await, *b.lock().await),
+        Err(_) => println!("\n   ❌ 固定顺序加锁仍然超时\n"),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     println!("🎓 Send 和 Sync Trait 深入理解教程\n");
@@ -275,6 +337,8 @@ async fn main() {
     rwlock_demo().await;
     custom_type_demo().await;
     common_mistakes().await;
+    deadlock_demo().await;
+    deadlock_fixed().await;
     
     println!("🎉 教程完成！\n");
     println!("💡 关键要点：");
@@ -285,5 +349,6 @@ async fn main() {
     println!("   • tokio::spawn 要求 Future 是 Send");
     println!("   • 使用 Arc<Mutex<T>> 或 Arc<RwLock<T>> 共享可变数据");
     println!("   • tokio::sync::Mutex 可以在 .await 点持有锁");
+    println!("   • 需要多把锁时所有任务按同一顺序获取，避免死锁");
 }
 
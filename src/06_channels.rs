@@ -6,7 +6,13 @@
 // 3. broadcast channel（广播）
 // 4. watch channel（状态共享）
 
-use tokio::sync::{mpsc, oneshot, broadcast, watch};
+use async_trait::async_trait;
+use futures::stream::{self, Stream, StreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, broadcast, watch, Semaphore};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::time::{sleep, Duration};
 
 /// === 1. MPSC Channel - 多生产者单消费者 ===
@@ -117,6 +123,30 @@ async fn oneshot_demo() {
     }
 }
 
+/// 广播订阅者循环：处理每条消息后等待 delay（模拟处理耗时）
+///
+/// 落后太多时 recv 返回 Lagged(n)，表示最旧的 n 条消息已被覆盖；
+/// 这不是致命错误，打印跳过的数量后继续接收，只有 Closed 才退出。
+/// 返回 (收到的消息数, 跳过的消息数)。
+async fn broadcast_subscriber(name: &str, mut rx: broadcast::Receiver<String>, delay: Duration) -> (usize, u64) {
+    let (mut received, mut skipped) = (0, 0);
+    loop {
+        match rx.recv().await {
+            Ok(msg) => {
+                println!("   📻 {}收到: {}", name, msg);
+                received += 1;
+                sleep(delay).await;
+            }
+            Err(RecvError::Lagged(n)) => {
+                println!("   ⏭️  {}落后了，跳过 {} 条消息", name, n);
+                skipped += n;
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    (received, skipped)
+}
+
 /// === 4. Broadcast Channel - 广播 ===
 async fn broadcast_demo() {
     println!("=== 4. Broadcast Channel（广播）===");
@@ -125,29 +155,23 @@ async fn broadcast_demo() {
     let (tx, _rx) = broadcast::channel::<String>(10);
     
     // 创建 3 个订阅者
-    let mut rx1 = tx.subscribe();
-    let mut rx2 = tx.subscribe();
-    let mut rx3 = tx.subscribe();
+    let rx1 = tx.subscribe();
+    let rx2 = tx.subscribe();
+    let rx3 = tx.subscribe();
     
     // 订阅者 1
     tokio::spawn(async move {
-        while let Ok(msg) = rx1.recv().await {
-            println!("   📻 订阅者1收到: {}", msg);
-        }
+        broadcast_subscriber("订阅者1", rx1, Duration::ZERO).await;
     });
     
     // 订阅者 2
     tokio::spawn(async move {
-        while let Ok(msg) = rx2.recv().await {
-            println!("   📻 订阅者2收到: {}", msg);
-        }
+        broadcast_subscriber("订阅者2", rx2, Duration::ZERO).await;
     });
     
     // 订阅者 3
     tokio::spawn(async move {
-        while let Ok(msg) = rx3.recv().await {
-            println!("   📻 订阅者3收到: {}", msg);
-        }
+        broadcast_subscriber("订阅者3", rx3, Duration::ZERO).await;
     });
     
     sleep(Duration::from_millis(100)).await;
@@ -282,6 +306,820 @@ async fn channel_selection_guide() {
     println!("   场景：配置更新、状态监控\n");
 }
 
+// === 8. 可复用的流水线阶段 ===
+
+/// 流水线中的一个处理阶段：把 I 异步地转换为 O
+#[async_trait]
+trait Stage<I, O>: Send + Sync + 'static {
+    async fn process(&self, input: I) -> O;
+}
+
+/// 多阶段异步流水线
+///
+/// 每个阶段运行在独立的任务中，阶段之间用有界 channel 相连，
+/// 下游处理慢时上游会因 send 等待而自然减速（背压）。
+struct Pipeline<I, O> {
+    input: mpsc::Sender<I>,
+    output: mpsc::Receiver<O>,
+    capacity: usize,
+}
+
+impl<I: Send + 'static> Pipeline<I, I> {
+    fn new(capacity: usize) -> Self {
+        let (input, output) = mpsc::channel(capacity);
+        Pipeline { input, output, capacity }
+    }
+}
+
+impl<I: Send + 'static, O: Send + 'static> Pipeline<I, O> {
+    /// 在流水线末尾追加一个阶段
+    fn stage<N, S>(self, stage: S) -> Pipeline<I, N>
+    where
+        N: Send + 'static,
+        S: Stage<O, N>,
+    {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let mut upstream = self.output;
+        
+        tokio::spawn(async move {
+            while let Some(item) = upstream.recv().await {
+                if tx.send(stage.process(item).await).await.is_err() {
+                    break;
+                }
+            }
+        });
+        
+        Pipeline {
+            input: self.input,
+            output: rx,
+            capacity: self.capacity,
+        }
+    }
+    
+    /// 拆出入口和出口；drop 入口后，关闭信号会逐级传到出口
+    fn split(self) -> (mpsc::Sender<I>, mpsc::Receiver<O>) {
+        (self.input, self.output)
+    }
+}
+
+/// 解析阶段：文本 -> 数字（无法解析时记为 0）
+struct ParseStage;
+
+#[async_trait]
+impl Stage<String, i64> for ParseStage {
+    async fn process(&self, input: String) -> i64 {
+        sleep(Duration::from_millis(50)).await;
+        input.trim().parse().unwrap_or(0)
+    }
+}
+
+/// 转换阶段：数字 -> 平方后的描述
+struct SquareStage;
+
+#[async_trait]
+impl Stage<i64, String> for SquareStage {
+    async fn process(&self, input: i64) -> String {
+        sleep(Duration::from_millis(50)).await;
+        format!("{}² = {}", input, input * input)
+    }
+}
+
+async fn pipeline_demo() {
+    println!("=== 8. 可复用的流水线阶段 ===");
+    println!("📝 解析阶段 → 平方阶段，每个阶段一个任务，阶段间是有界 channel\n");
+    
+    let (input, mut output) = Pipeline::new(2)
+        .stage(ParseStage)
+        .stage(SquareStage)
+        .split();
+    
+    tokio::spawn(async move {
+        for text in ["3", " 7", "abc", "12"] {
+            println!("   📤 输入: {:?}", text);
+            input.send(text.to_string()).await.unwrap();
+        }
+        // input 在这里被 drop，流水线依次关闭
+    });
+    
+    while let Some(result) = output.recv().await {
+        println!("   📥 输出: {}", result);
+    }
+    
+    println!("\n✅ 流水线处理完毕\n");
+}
+
+// === 9. 带确认的工作队列 ===
+
+struct QueueInner<T> {
+    items: std::sync::Mutex<VecDeque<T>>,
+    // 可被领取的元素数
+    available: Semaphore,
+    // 剩余容量：元素被 ack 之前一直占用容量
+    space: Semaphore,
+}
+
+/// 有界多消费者工作队列，提供“至少一次”投递语义
+///
+/// 消费者 reserve() 领取一个元素后必须 ack()；如果消费者在 ack 之前崩溃
+/// （Reservation 被 drop），元素会被放回队首，交给其他消费者重新处理。
+struct Queue<T> {
+    inner: Arc<QueueInner<T>>,
+}
+
+impl<T> Clone for Queue<T> {
+    fn clone(&self) -> Self {
+        Queue { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Queue<T> {
+    fn new(capacity: usize) -> Self {
+        Queue {
+            inner: Arc::new(QueueInner {
+                items: std::sync::Mutex::new(VecDeque::new()),
+                available: Semaphore::new(0),
+                space: Semaphore::new(capacity),
+            }),
+        }
+    }
+    
+    /// 放入一个元素；队列满时等待
+    async fn push(&self, item: T) {
+        self.inner.space.acquire().await.unwrap().forget();
+        self.inner.items.lock().unwrap().push_back(item);
+        self.inner.available.add_permits(1);
+    }
+    
+    /// 领取一个元素；队列为空时等待
+    async fn reserve(&self) -> Reservation<T> {
+        self.inner.available.acquire().await.unwrap().forget();
+        let item = self.inner.items.lock().unwrap().pop_front();
+        Reservation {
+            item,
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// 被领取但尚未确认的元素
+struct Reservation<T> {
+    item: Option<T>,
+    inner: Arc<QueueInner<T>>,
+}
+
+impl<T> Reservation<T> {
+    fn item(&self) -> &T {
+        self.item.as_ref().expect("reservation 已被确认")
+    }
+    
+    /// 确认处理完成：元素被真正移除，释放容量
+    fn ack(mut self) {
+        self.item.take();
+        self.inner.space.add_permits(1);
+    }
+}
+
+impl<T> Drop for Reservation<T> {
+    fn drop(&mut self) {
+        // 没有 ack 就被 drop：放回队首，等待重新投递
+        if let Some(item) = self.item.take() {
+            self.inner.items.lock().unwrap().push_front(item);
+            self.inner.available.add_permits(1);
+        }
+    }
+}
+
+async fn ack_queue_demo() {
+    println!("=== 9. 带确认的工作队列 ===");
+    println!("📝 消费者 1 领取任务后“崩溃”（没有 ack），任务会重新投递给消费者 2\n");
+    
+    let queue = Queue::new(4);
+    queue.push("任务A".to_string()).await;
+    queue.push("任务B".to_string()).await;
+    
+    let q1 = queue.clone();
+    let consumer1 = tokio::spawn(async move {
+        let reservation = q1.reserve().await;
+        println!("   👷 消费者1 领取 {}", reservation.item());
+        sleep(Duration::from_secs(60)).await; // 处理卡住了
+        reservation.ack();
+    });
+    
+    sleep(Duration::from_millis(100)).await;
+    println!("   💥 消费者1 崩溃（任务被 abort），没有 ack");
+    consumer1.abort();
+    let _ = consumer1.await; // 任务被取消时 Reservation 被 drop，任务A 回到队首
+    
+    let mut processed = Vec::new();
+    for _ in 0..2 {
+        let reservation = queue.reserve().await;
+        println!("   👷 消费者2 领取 {}", reservation.item());
+        sleep(Duration::from_millis(50)).await;
+        println!("   ✅ 消费者2 完成并 ack {}", reservation.item());
+        processed.push(reservation.item().clone());
+        reservation.ack();
+    }
+    
+    // 全部 ack 之后队列应该为空：再领取只会一直等待
+    let drained = tokio::time::timeout(Duration::from_millis(100), queue.reserve()).await.is_err();
+    let redelivered = processed.iter().filter(|item| *item == "任务A").count();
+    if processed == ["任务A", "任务B"] && redelivered == 1 && drained {
+        println!("\n✅ 任务A 在崩溃后重新投递并恰好处理了一次，队列已清空\n");
+    } else {
+        println!("\n❌ 处理记录 {:?}，任务A 处理了 {} 次，队列{}清空\n",
+                 processed, redelivered, if drained { "已" } else { "未" });
+    }
+}
+
+// === 10. 带背压的广播 ===
+
+/// 不丢消息的广播：每个订阅者一条有界 channel，发送方等所有订阅者都收下才返回
+///
+/// 与 broadcast::channel 丢弃落后接收者的消息不同，这里发送速度被最慢的订阅者拖住。
+/// 代价是队头阻塞：一个慢订阅者会让所有人（包括快订阅者）都只能以它的速度收到新消息。
+struct BackpressureBroadcast<T: Clone> {
+    subscribers: Vec<mpsc::Sender<T>>,
+    capacity: usize,
+}
+
+impl<T: Clone> BackpressureBroadcast<T> {
+    fn new(capacity: usize) -> Self {
+        BackpressureBroadcast {
+            subscribers: Vec::new(),
+            capacity,
+        }
+    }
+    
+    fn subscribe(&mut self) -> mpsc::Receiver<T> {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        self.subscribers.push(tx);
+        rx
+    }
+    
+    /// 发送给所有订阅者；任何一个订阅者的缓冲满了都会在这里等待
+    ///
+    /// 已经退订（Receiver 被 drop）的订阅者会被移除，返回成功送达的订阅者数量。
+    /// 发送中途被取消（例如外面包了 timeout）时，已经送出的订阅者收到这条消息，
+    /// 其余的没有收到，但订阅者列表保持不变，之后的发送照常送达所有人。
+    async fn send(&mut self, value: T) -> usize {
+        for tx in &self.subscribers {
+            // 失败说明订阅者已退订，下面统一移除
+            let _ = tx.send(value.clone()).await;
+        }
+        self.subscribers.retain(|tx| !tx.is_closed());
+        self.subscribers.len()
+    }
+}
+
+async fn backpressure_broadcast_demo() {
+    println!("=== 10. 带背压的广播 ===");
+    println!("📝 快订阅者立即处理，慢订阅者每条消息 100ms，缓冲区为 1\n");
+    
+    let mut hub = BackpressureBroadcast::new(1);
+    let mut fast_rx = hub.subscribe();
+    let mut slow_rx = hub.subscribe();
+    
+    let fast = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Some(msg) = fast_rx.recv().await {
+            received.push(msg);
+        }
+        received
+    });
+    let slow = tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Some(msg) = slow_rx.recv().await {
+            sleep(Duration::from_millis(100)).await;
+            received.push(msg);
+        }
+        received
+    });
+    
+    let start = tokio::time::Instant::now();
+    let mut blocked = Duration::ZERO;
+    for i in 1..=5 {
+        let before = tokio::time::Instant::now();
+        hub.send(i).await;
+        blocked += before.elapsed();
+        println!("   📤 第 {} 条发送完成 ({}ms)", i, start.elapsed().as_millis());
+    }
+    drop(hub);
+    
+    let fast_received = fast.await.unwrap();
+    let slow_received = slow.await.unwrap();
+    println!("   🐇 快订阅者收到 {} 条: {:?}", fast_received.len(), fast_received);
+    println!("   🐢 慢订阅者收到 {} 条: {:?}", slow_received.len(), slow_received);
+    println!("   ⏸️  发送方累计阻塞 {}ms", blocked.as_millis());
+    
+    // 缓冲区为 1：第 3~5 条各要等慢订阅者处理完一条（约 100ms），累计约 300ms
+    let all = vec![1, 2, 3, 4, 5];
+    if fast_received == all && slow_received == all && blocked >= Duration::from_millis(250) {
+        println!("\n✅ 发送方被慢订阅者限速，但没有任何消息丢失\n");
+    } else {
+        println!("\n❌ 预期两个订阅者都收到 5 条消息，且发送方被阻塞约 300ms\n");
+    }
+}
+
+// === 11. 成功与失败分流的工作者池 ===
+
+/// 通用工作者池：多个工作者共享任务队列，处理结果按成功/失败分别发往两个 channel
+///
+/// 两个输出 channel 都是有界的，调用者需要同时消费它们（例如用 tokio::join!），
+/// 否则其中一个写满后会阻塞工作者。
+struct WorkerPool<T> {
+    tx: mpsc::Sender<T>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// 启动 num_workers 个工作者，返回池本身以及成功、失败两个接收端
+    fn new<R, E, F, Fut>(
+        num_workers: usize,
+        capacity: usize,
+        handler: F,
+    ) -> (Self, mpsc::Receiver<R>, mpsc::Receiver<E>)
+    where
+        R: Send + 'static,
+        E: Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, E>> + Send,
+    {
+        let (tx, rx) = mpsc::channel::<T>(capacity);
+        let (ok_tx, ok_rx) = mpsc::channel(capacity);
+        let (err_tx, err_rx) = mpsc::channel(capacity);
+        let rx = Arc::new(tokio::sync::Mutex::new(rx));
+        let handler = Arc::new(handler);
+        
+        for _ in 0..num_workers {
+            let (rx, handler) = (rx.clone(), handler.clone());
+            let (ok_tx, err_tx) = (ok_tx.clone(), err_tx.clone());
+            tokio::spawn(async move {
+                loop {
+                    let task = rx.lock().await.recv().await;
+                    let Some(task) = task else { break };
+                    let delivered = match handler(task).await {
+                        Ok(result) => ok_tx.send(result).await.is_ok(),
+                        Err(e) => err_tx.send(e).await.is_ok(),
+                    };
+                    if !delivered {
+                        break;
+                    }
+                }
+            });
+        }
+        
+        (WorkerPool { tx }, ok_rx, err_rx)
+    }
+    
+    /// 提交任务；池已关闭时把任务原样还给调用者
+    async fn submit(&self, task: T) -> Result<(), T> {
+        self.tx.send(task).await.map_err(|e| e.0)
+    }
+}
+
+async fn worker_pool_demo() {
+    println!("=== 11. 成功与失败分流的工作者池 ===");
+    println!("📝 解析 8 个字符串，解析失败的进入错误 channel\n");
+    
+    let (pool, mut results, mut errors) = WorkerPool::new(3, 4, |text: String| async move {
+        sleep(Duration::from_millis(20)).await;
+        text.parse::<i32>().map_err(|e| format!("{:?}: {}", text, e))
+    });
+    
+    let inputs = ["1", "2", "x", "4", "", "6", "7.5", "8"];
+    let producer = async move {
+        for text in inputs {
+            let _ = pool.submit(text.to_string()).await;
+        }
+        // pool 在这里被 drop，任务队列关闭，工作者处理完剩余任务后退出
+    };
+    
+    let collect_ok = async {
+        let mut values = Vec::new();
+        while let Some(v) = results.recv().await {
+            values.push(v);
+        }
+        values.sort();
+        values
+    };
+    let collect_err = async {
+        let mut failures = Vec::new();
+        while let Some(e) = errors.recv().await {
+            failures.push(e);
+        }
+        failures
+    };
+    
+    let ((), values, failures) = tokio::join!(producer, collect_ok, collect_err);
+    println!("   ✅ 成功 {} 个: {:?}", values.len(), values);
+    println!("   ❌ 失败 {} 个:", failures.len());
+    for e in failures {
+        println!("      • {}", e);
+    }
+    println!();
+}
+
+// === 12. 把 Stream 接到 Channel ===
+
+/// 把 Stream 的元素依次送进 mpsc 发送端
+///
+/// 每次 send 都会 await，channel 满了就暂停拉取 Stream（背压）；
+/// 接收端被 drop 后立即停止，不再消费剩余元素。返回成功转发的数量。
+async fn forward_to_channel<S: Stream>(s: S, tx: mpsc::Sender<S::Item>) -> usize {
+    futures::pin_mut!(s);
+    let mut forwarded = 0;
+    while let Some(item) = s.next().await {
+        if tx.send(item).await.is_err() {
+            break; // 接收端已关闭
+        }
+        forwarded += 1;
+    }
+    forwarded
+}
+
+async fn forward_to_channel_demo() {
+    println!("=== 12. 把 Stream 接到 Channel ===");
+    println!("📝 把 1..=10 的 Stream 转发到容量为 2 的 channel\n");
+    
+    let (tx, mut rx) = mpsc::channel(2);
+    let forwarder = tokio::spawn(forward_to_channel(stream::iter(1..=10), tx));
+    
+    let mut received = Vec::new();
+    while let Some(n) = rx.recv().await {
+        received.push(n);
+    }
+    println!("   📥 接收端按顺序收到: {:?}", received);
+    println!("   📤 转发数量: {}", forwarder.await.unwrap());
+    
+    // 接收端提前关闭：转发在第一次发送失败时停止
+    let (tx, mut rx) = mpsc::channel(1);
+    let forwarder = tokio::spawn(forward_to_channel(stream::iter(1..=10), tx));
+    let first = rx.recv().await;
+    drop(rx);
+    println!("   🛑 接收端只取了 {:?} 就关闭，转发 {} 个后停止\n", first, forwarder.await.unwrap());
+}
+
+// === 13. 限流日志 ===
+
+/// 同一行日志在当前窗口内的计数
+struct LineWindow {
+    started: tokio::time::Instant,
+    emitted: usize,
+    suppressed: usize,
+}
+
+/// 合并重复日志的异步 logger
+///
+/// log() 只是把日志行送进 channel，由后台任务决定是否输出：
+/// 同一行在一个窗口内最多原样输出 burst 次，其余只计数，
+/// 窗口结束时补一行 "... N more" 的汇总，避免高负载时刷屏。
+#[derive(Clone)]
+struct RateLimitedLogger {
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl RateLimitedLogger {
+    /// 启动后台任务；所有 logger 被 drop 后任务输出剩余汇总并结束，返回实际输出的行
+    fn spawn(window: Duration, burst: usize) -> (Self, tokio::task::JoinHandle<Vec<String>>) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let handle = tokio::spawn(async move {
+            let mut output = Vec::new();
+            let mut emit = |line: String| {
+                println!("   📜 {}", line);
+                output.push(line);
+            };
+            let mut windows: HashMap<String, LineWindow> = HashMap::new();
+            let mut ticker = tokio::time::interval(window);
+            
+            loop {
+                tokio::select! {
+                    line = rx.recv() => {
+                        let Some(line) = line else { break };
+                        let now = tokio::time::Instant::now();
+                        let entry = windows.entry(line.clone()).or_insert(LineWindow {
+                            started: now,
+                            emitted: 0,
+                            suppressed: 0,
+                        });
+                        if entry.emitted < burst {
+                            entry.emitted += 1;
+                            emit(line);
+                        } else {
+                            entry.suppressed += 1;
+                        }
+                    }
+                    _ = ticker.tick() => {
+                        // 窗口到期：输出被合并的行数，下一窗口重新计数
+                        let now = tokio::time::Instant::now();
+                        windows.retain(|line, w| {
+                            if now.duration_since(w.started) < window {
+                                return true;
+                            }
+                            if w.suppressed > 0 {
+                                emit(format!("{} ... {} more", line, w.suppressed));
+                            }
+                            false
+                        });
+                    }
+                }
+            }
+            
+            for (line, w) in windows {
+                if w.suppressed > 0 {
+                    emit(format!("{} ... {} more", line, w.suppressed));
+                }
+            }
+            output
+        });
+        (RateLimitedLogger { tx }, handle)
+    }
+    
+    fn log(&self, line: impl Into<String>) {
+        // 后台任务已退出时直接丢弃日志
+        let _ = self.tx.send(line.into());
+    }
+}
+
+async fn rate_limited_logger_demo() {
+    println!("=== 13. 限流日志 ===");
+    println!("📝 1 秒窗口内最多原样输出 3 次，200 条相同日志 + 1 条不同日志\n");
+    
+    let (logger, handle) = RateLimitedLogger::spawn(Duration::from_secs(1), 3);
+    for _ in 0..200 {
+        logger.log("⚠️  上游响应变慢");
+    }
+    logger.log("✅ 缓存已刷新");
+    drop(logger);
+    
+    let output = handle.await.unwrap();
+    let summarized = output.iter().any(|line| line.ends_with("... 197 more"));
+    println!("   {} 201 条日志只输出了 {} 行（含汇总行）\n",
+             if output.len() <= 5 && summarized { "✅" } else { "❌" }, output.len());
+}
+
+// === 14. 把 Channel 当作 Stream ===
+
+/// 把 mpsc 接收端包装成 Stream，之后就可以使用 map/filter/take 等组合子
+///
+/// 每次 poll_next 直接转给 poll_recv：下游不拉取时就不会从 channel 取值，
+/// channel 保持满的状态，发送端在 send().await 上等待（背压）。
+/// 所有发送端关闭且 channel 已空时 Stream 结束；Stream 被 drop 时接收端随之关闭。
+fn receiver_stream<T>(mut rx: mpsc::Receiver<T>) -> impl Stream<Item = T> {
+    stream::poll_fn(move |cx| rx.poll_recv(cx))
+}
+
+async fn receiver_stream_demo() {
+    println!("=== 14. 把 Channel 当作 Stream ===");
+    println!("📝 生产者向容量为 2 的 channel 发送 1..=20，消费端 map(平方) → filter(偶数) → take(3)\n");
+    
+    let (tx, rx) = mpsc::channel(2);
+    let producer = tokio::spawn(async move {
+        let mut sent = 0;
+        for n in 1..=20u32 {
+            if tx.send(n).await.is_err() {
+                break; // Stream 已被 drop
+            }
+            sent += 1;
+        }
+        sent
+    });
+    
+    let results: Vec<u32> = receiver_stream(rx)
+        .map(|n| n * n)
+        .filter(|n| std::future::ready(n % 2 == 0))
+        .take(3)
+        .collect()
+        .await;
+    let sent = producer.await.unwrap();
+    
+    println!("   📥 结果: {:?}", results);
+    // take(3) 拿到第 3 个结果（来自 6）后就不再拉取，生产者最多多发出 channel 容量那么多个
+    println!("   {} 生产者只发送了 {} 个就停止（消费到 6，channel 容量 2）\n",
+             if results == [4, 16, 36] && sent <= 8 { "✅" } else { "❌" }, sent);
+}
+
+// === 15. 合并多个接收端 ===
+
+/// 把多个接收端合并成一个 Stream：哪个 channel 先有数据就先产出哪个
+///
+/// 每个接收端先用 receiver_stream 包装，再交给 select_all 轮流 poll；
+/// 某个 channel 关闭后只是从集合中移除，全部关闭后 Stream 才结束。
+fn merge_receivers<T>(rxs: Vec<mpsc::Receiver<T>>) -> impl Stream<Item = T> {
+    stream::select_all(rxs.into_iter().map(receiver_stream))
+}
+
+async fn merge_receivers_demo() {
+    println!("=== 15. 合并多个接收端 ===");
+    println!("📝 三个生产者各自拥有一个 channel，发送间隔分别为 30/50/70ms，由一个消费循环统一接收\n");
+    
+    let mut rxs = Vec::new();
+    for (name, interval) in [("A", 30), ("B", 50), ("C", 70)] {
+        let (tx, rx) = mpsc::channel(4);
+        rxs.push(rx);
+        tokio::spawn(async move {
+            for i in 1..=3 {
+                sleep(Duration::from_millis(interval)).await;
+                if tx.send(format!("{}{}", name, i)).await.is_err() {
+                    break;
+                }
+            }
+            // tx 在这里被 drop，对应的 channel 关闭
+        });
+    }
+    
+    let merged = merge_receivers(rxs);
+    futures::pin_mut!(merged);
+    let mut received = Vec::new();
+    while let Some(msg) = merged.next().await {
+        println!("   📥 收到 {}", msg);
+        received.push(msg);
+    }
+    
+    println!("   {} 三个 channel 全部关闭后结束，共收到 {} 条消息\n",
+             if received.len() == 9 { "✅" } else { "❌" }, received.len());
+}
+
+// === 16. 广播订阅者落后后恢复 ===
+
+async fn broadcast_lag_demo() {
+    println!("=== 16. 广播订阅者落后后恢复 ===");
+    println!("📝 容量 4 的广播，连续发送 12 条；慢订阅者每条处理 50ms，会落后并跳过旧消息\n");
+    
+    let (tx, _) = broadcast::channel::<String>(4);
+    let slow = tokio::spawn(broadcast_subscriber("慢订阅者", tx.subscribe(), Duration::from_millis(50)));
+    
+    for i in 1..=12 {
+        tx.send(format!("消息 {}", i)).unwrap();
+        sleep(Duration::from_millis(10)).await;
+    }
+    drop(tx);
+    
+    let (received, skipped) = slow.await.unwrap();
+    println!("   {} 慢订阅者收到 {} 条、跳过 {} 条，落后后继续接收直到发送端关闭\n",
+             if skipped > 0 && received as u64 + skipped == 12 { "✅" } else { "❌" }, received, skipped);
+}
+
+// === 17. 按主题路由的发布/订阅 ===
+
+/// 每个主题一个 broadcast channel，订阅者只收到自己订阅的主题上的消息
+///
+/// channel 在第一次订阅时创建；没人订阅过的主题上发布的消息直接丢弃。
+struct TopicRouter {
+    topics: std::sync::Mutex<HashMap<String, broadcast::Sender<String>>>,
+    capacity: usize,
+}
+
+impl TopicRouter {
+    fn new(capacity: usize) -> Self {
+        TopicRouter {
+            topics: std::sync::Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+    
+    fn subscribe(&self, topic: &str) -> broadcast::Receiver<String> {
+        let mut topics = self.topics.lock().unwrap();
+        topics
+            .entry(topic.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+    
+    /// 发布到 topic，返回收到这条消息的订阅者数量
+    fn publish(&self, topic: &str, msg: String) -> usize {
+        let topics = self.topics.lock().unwrap();
+        topics
+            .get(topic)
+            .and_then(|tx| tx.send(msg).ok())
+            .unwrap_or(0)
+    }
+}
+
+async fn topic_router_demo() {
+    println!("=== 17. 按主题路由的发布/订阅 ===");
+    println!("📝 小王订阅 news，小李订阅 sports，小张两个都订阅；weather 没有订阅者\n");
+    
+    let router = TopicRouter::new(16);
+    let subscribers = [
+        ("小王[news]", router.subscribe("news")),
+        ("小李[sports]", router.subscribe("sports")),
+        ("小张[news]", router.subscribe("news")),
+        ("小张[sports]", router.subscribe("sports")),
+    ];
+    let handles: Vec<_> = subscribers
+        .into_iter()
+        .map(|(name, rx)| tokio::spawn(async move { broadcast_subscriber(name, rx, Duration::ZERO).await.0 }))
+        .collect();
+    
+    for (topic, msg) in [("news", "央行降息"), ("sports", "主队获胜"), ("news", "新品发布"), ("weather", "明日有雨")] {
+        let delivered = router.publish(topic, msg.to_string());
+        println!("   📤 [{}] {} → {} 个订阅者", topic, msg, delivered);
+    }
+    // 关闭所有主题，订阅者收完剩余消息后退出
+    drop(router);
+    
+    let mut counts = Vec::new();
+    for handle in handles {
+        counts.push(handle.await.unwrap());
+    }
+    println!("   {} 各订阅者收到的消息数: {:?}（news 2 条，sports 1 条）\n",
+             if counts == [2, 1, 2, 1] { "✅" } else { "❌" }, counts);
+}
+
+// === 18. 扇出：每个工作者独占一个接收端 ===
+
+/// 启动一个分发任务，把 source 中的元素轮流发给 n 个工作者各自的 channel
+///
+/// 工作者各自拥有接收端，取任务时不用再争抢同一把锁。代价是分配在元素到达时就固定了：
+/// 某个工作者慢下来，它的 channel 满后分发任务会卡在这个工作者上，其他工作者也跟着等。
+fn fan_out<T: Send + 'static>(mut source: mpsc::Receiver<T>, n: usize) -> Vec<mpsc::Receiver<T>> {
+    let (txs, rxs): (Vec<_>, Vec<_>) = (0..n.max(1)).map(|_| mpsc::channel(2)).unzip();
+    
+    tokio::spawn(async move {
+        for i in 0.. {
+            let Some(item) = source.recv().await else { break };
+            if txs[i % txs.len()].send(item).await.is_err() {
+                break; // 工作者已退出
+            }
+        }
+        // txs 在这里被 drop，所有工作者的 channel 随之关闭
+    });
+    
+    rxs
+}
+
+/// 任务耗时：每第 3 个任务是慢任务
+fn task_cost(task: u32) -> Duration {
+    Duration::from_millis(if task % 3 == 0 { 300 } else { 50 })
+}
+
+async fn fan_out_demo() {
+    println!("=== 18. 扇出：每个工作者独占一个接收端 ===");
+    println!("📝 9 个任务、3 个工作者，任务 3/6/9 耗时 300ms，其余 50ms\n");
+    
+    // 共享接收端：谁空闲谁去取
+    let (tx, rx) = mpsc::channel::<u32>(10);
+    let rx = Arc::new(tokio::sync::Mutex::new(rx));
+    let start = tokio::time::Instant::now();
+    let workers: Vec<_> = (1..=3)
+        .map(|_| {
+            let rx = rx.clone();
+            tokio::spawn(async move {
+                let mut done = 0;
+                loop {
+                    let task = rx.lock().await.recv().await;
+                    let Some(task) = task else { break };
+                    sleep(task_cost(task)).await;
+                    done += 1;
+                }
+                done
+            })
+        })
+        .collect();
+    for task in 1..=9 {
+        tx.send(task).await.unwrap();
+    }
+    drop(tx);
+    let mut shared = Vec::new();
+    for worker in workers {
+        shared.push(worker.await.unwrap());
+    }
+    let shared_elapsed = start.elapsed();
+    
+    // 扇出：分配在任务到达时按轮转固定
+    let (tx, rx) = mpsc::channel::<u32>(10);
+    let start = tokio::time::Instant::now();
+    let workers: Vec<_> = fan_out(rx, 3)
+        .into_iter()
+        .map(|mut rx| {
+            tokio::spawn(async move {
+                let mut done = 0;
+                while let Some(task) = rx.recv().await {
+                    sleep(task_cost(task)).await;
+                    done += 1;
+                }
+                done
+            })
+        })
+        .collect();
+    for task in 1..=9 {
+        tx.send(task).await.unwrap();
+    }
+    drop(tx);
+    let mut fanned = Vec::new();
+    for worker in workers {
+        fanned.push(worker.await.unwrap());
+    }
+    let fanned_elapsed = start.elapsed();
+    
+    println!("   🔒 共享接收端: 各工作者处理 {:?}，总耗时 {}ms", shared, shared_elapsed.as_millis());
+    println!("   🔀 扇出轮转:   各工作者处理 {:?}，总耗时 {}ms", fanned, fanned_elapsed.as_millis());
+    println!("   {} 两种方式都处理完 9 个任务",
+             if shared.iter().sum::<u32>() == 9 && fanned.iter().sum::<u32>() == 9 { "✅" } else { "❌" });
+    println!("   ⚖️  取舍:");
+    println!("      • 共享接收端: 取任务要抢锁，但空闲的工作者总能拿到下一个任务，负载自动均衡");
+    println!("      • 扇出: 热路径上没有锁，但轮转不看负载，慢任务全落到工作者 3 上拖长了总时间\n");
+}
+
 #[tokio::main]
 async fn main() {
     println!("🎓 Channel 通信模式教程\n");
@@ -294,6 +1132,17 @@ async fn main() {
     watch_demo().await;
     work_queue_demo().await;
     channel_selection_guide().await;
+    pipeline_demo().await;
+    ack_queue_demo().await;
+    backpressure_broadcast_demo().await;
+    worker_pool_demo().await;
+    forward_to_channel_demo().await;
+    rate_limited_logger_demo().await;
+    receiver_stream_demo().await;
+    merge_receivers_demo().await;
+    broadcast_lag_demo().await;
+    topic_router_demo().await;
+    fan_out_demo().await;
     
     println!("🎉 教程完成！\n");
     println!("💡 关键要点：");
@@ -303,5 +1152,195 @@ async fn main() {
     println!("   • watch: 状态共享，接收者看到最新值");
     println!("   • 有界 channel 有背压控制");
     println!("   • 无界 channel 需要注意内存使用");
+    println!("   • 有界 channel 串联的流水线天然具备背压");
+    println!("   • ack + 重新投递让工作队列做到“至少一次”");
+    println!("   • 背压广播不丢消息，但会被最慢的订阅者拖慢");
+    println!("   • 成功和失败走不同 channel，调用者可以分别处理");
+    println!("   • Stream 转发到有界 channel 时，send().await 自然形成背压");
+    println!("   • 日志经 channel 交给后台任务，重复行在窗口内合并成汇总");
+    println!("   • 接收端包装成 Stream 后可以用组合子处理，背压照样生效");
+    println!("   • select_all 把多个接收端合并成一个 Stream，全部关闭才结束");
+    println!("   • 广播接收端落后时收到 Lagged(n)，跳过旧消息后可以继续接收");
+    println!("   • 每个主题一个 broadcast channel，就是最简单的发布/订阅路由");
+    println!("   • 扇出给每个工作者独占的 channel 去掉了锁，但失去了按空闲程度分配");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test(start_paused = true)]
+    async fn pipeline_parses_then_squares_end_to_end() {
+        let (input, mut output) = Pipeline::new(2).stage(ParseStage).stage(SquareStage).split();
+        tokio::spawn(async move {
+            for text in ["3", " 7", "abc", "-4"] {
+                input.send(text.to_string()).await.unwrap();
+            }
+        });
+        
+        let mut results = Vec::new();
+        while let Some(result) = output.recv().await {
+            results.push(result);
+        }
+        assert_eq!(results, ["3² = 9", "7² = 49", "0² = 0", "-4² = 16"]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn dropped_reservation_is_redelivered_to_another_consumer() {
+        let queue = Queue::new(2);
+        queue.push(1).await;
+        queue.push(2).await;
+        
+        let crashed = queue.clone();
+        let seen_by_crashed = tokio::spawn(async move {
+            let reservation = crashed.reserve().await;
+            *reservation.item()
+            // 没有 ack，Reservation 在这里被 drop
+        })
+        .await
+        .unwrap();
+        assert_eq!(seen_by_crashed, 1);
+        
+        let other = queue.clone();
+        let redelivered = tokio::spawn(async move {
+            let first = other.reserve().await;
+            let second = other.reserve().await;
+            let items = (*first.item(), *second.item());
+            first.ack();
+            second.ack();
+            items
+        })
+        .await
+        .unwrap();
+        assert_eq!(redelivered, (1, 2));
+        
+        // 都 ack 之后队列为空，容量也全部归还
+        assert!(tokio::time::timeout(Duration::from_secs(1), queue.reserve()).await.is_err());
+        queue.push(3).await;
+        queue.push(4).await;
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn backpressure_broadcast_paces_sender_without_losing_messages() {
+        let mut hub = BackpressureBroadcast::new(1);
+        let mut fast_rx = hub.subscribe();
+        let mut slow_rx = hub.subscribe();
+        let fast = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(msg) = fast_rx.recv().await {
+                received.push(msg);
+            }
+            received
+        });
+        let slow = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(msg) = slow_rx.recv().await {
+                sleep(Duration::from_millis(100)).await;
+                received.push(msg);
+            }
+            received
+        });
+        
+        let start = tokio::time::Instant::now();
+        for i in 1..=5 {
+            assert_eq!(hub.send(i).await, 2);
+        }
+        // 缓冲区为 1：第 3~5 条各要等慢订阅者处理完一条
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+        drop(hub);
+        
+        assert_eq!(fast.await.unwrap(), [1, 2, 3, 4, 5]);
+        assert_eq!(slow.await.unwrap(), [1, 2, 3, 4, 5]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_send_keeps_every_subscriber() {
+        let mut hub = BackpressureBroadcast::new(1);
+        let mut first = hub.subscribe();
+        let mut stalled = hub.subscribe();
+        
+        hub.send(1).await;
+        assert_eq!(first.recv().await, Some(1));
+        // stalled 的缓冲已满，这次发送送达 first 之后卡住，被 timeout 取消
+        assert!(tokio::time::timeout(Duration::from_millis(50), hub.send(2)).await.is_err());
+        assert_eq!(first.recv().await, Some(2));
+        
+        assert_eq!(stalled.recv().await, Some(1));
+        assert_eq!(hub.send(3).await, 2, "取消之后两个订阅者都应该还在");
+        assert_eq!(first.recv().await, Some(3));
+        assert_eq!(stalled.recv().await, Some(3));
+        
+        drop(stalled);
+        assert_eq!(hub.send(4).await, 1);
+    }
+    
+    #[tokio::test]
+    async fn worker_pool_routes_errors_to_their_own_channel() {
+        let (pool, mut results, mut errors) = WorkerPool::new(3, 2, |n: u32| async move {
+            if matches!(n, 3 | 6 | 9) { Err(format!("{} 被拒绝", n)) } else { Ok(n * 10) }
+        });
+        
+        let producer = async move {
+            for n in 1..=10 {
+                pool.submit(n).await.unwrap();
+            }
+        };
+        let collect_ok = async {
+            let mut values = Vec::new();
+            while let Some(v) = results.recv().await {
+                values.push(v);
+            }
+            values.sort();
+            values
+        };
+        let collect_err = async {
+            let mut failures = Vec::new();
+            while let Some(e) = errors.recv().await {
+                failures.push(e);
+            }
+            failures.sort();
+            failures
+        };
+        
+        let ((), values, failures) = tokio::join!(producer, collect_ok, collect_err);
+        assert_eq!(values, [10, 20, 40, 50, 70, 80, 100]);
+        assert_eq!(failures, ["3 被拒绝", "6 被拒绝", "9 被拒绝"]);
+    }
+    
+    #[tokio::test]
+    async fn forward_to_channel_delivers_in_order_and_stops_when_receiver_drops() {
+        let (tx, mut rx) = mpsc::channel(2);
+        let forwarder = tokio::spawn(forward_to_channel(stream::iter(1..=5), tx));
+        let mut received = Vec::new();
+        while let Some(item) = rx.recv().await {
+            received.push(item);
+        }
+        assert_eq!(received, [1, 2, 3, 4, 5]);
+        assert_eq!(forwarder.await.unwrap(), 5);
+        
+        // 接收端提前关闭：转发立即停止，不再消费剩余元素
+        let (tx, rx) = mpsc::channel(2);
+        drop(rx);
+        assert_eq!(forward_to_channel(stream::iter(1..=5), tx).await, 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn rate_limited_logger_coalesces_a_burst_into_a_summary() {
+        let (logger, handle) = RateLimitedLogger::spawn(Duration::from_secs(1), 3);
+        for _ in 0..200 {
+            logger.log("上游响应变慢");
+        }
+        logger.log("缓存已刷新");
+        // 等窗口到期，汇总行由计时器输出，而不是等 logger 被 drop
+        sleep(Duration::from_millis(1500)).await;
+        logger.log("上游响应变慢");
+        drop(logger);
+        
+        let output = handle.await.unwrap();
+        assert_eq!(
+            output,
+            ["上游响应变慢", "上游响应变慢", "上游响应变慢", "缓存已刷新", "上游响应变慢 ... 197 more", "上游响应变慢"]
+        );
+    }
+}
@@ -7,10 +7,15 @@
 // 4. 错误处理
 // 5. 优雅关闭
 
-use tokio::sync::{mpsc, Semaphore};
+use async_trait::async_trait;
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Notify, Semaphore};
 use tokio::time::{sleep, Duration, timeout};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
 /// 请求结构
 #[derive(Debug, Clone)]
@@ -18,6 +23,31 @@ struct Request {
     id: u64,
     path: String,
     processing_time: Duration,
+    // 提交者所在的 span，跨越 channel 之后工作者据此把日志挂回调用方的上下文
+    //
+    // 由 Handler::prepare 在提交时填入，调用者不需要也不应该自己设置。
+    span: tracing::Span,
+    // 0 为普通请求；大于 0 的请求经 submit_prioritized 进入高优先级通道
+    priority: u8,
+}
+
+impl Request {
+    /// 创建一个普通优先级的请求
+    fn new(id: u64, path: impl Into<String>, processing_time: Duration) -> Self {
+        Request {
+            id,
+            path: path.into(),
+            processing_time,
+            span: tracing::Span::none(),
+            priority: 0,
+        }
+    }
+    
+    /// 设置优先级，便于链式调用
+    fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
 }
 
 /// 响应结构
@@ -25,47 +55,452 @@ struct Request {
 struct Response {
     request_id: u64,
     status: u16,
-    #[allow(dead_code)]
     body: String,
 }
 
+/// 服务器配置，可以通过环境变量调整而无需重新编译
+#[derive(Debug, Clone, PartialEq)]
+struct ServerConfig {
+    max_concurrent: usize,
+    num_workers: usize,
+    queue_cap: usize,
+    num_requests: u64,
+    // 每个工作者开始接收请求前的初始化时间（不从环境变量读取）
+    warmup: Duration,
+    // 单个请求的最长处理时间，超过则返回 504（不从环境变量读取）
+    max_processing_time: Duration,
+    // 同一路径连续失败多少次后熔断，以及熔断的冷却时间（不从环境变量读取）
+    breaker_threshold: u32,
+    breaker_cooldown: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            max_concurrent: 3,
+            num_workers: 4,
+            queue_cap: 100,
+            num_requests: 20,
+            warmup: Duration::ZERO,
+            max_processing_time: Duration::from_secs(5),
+            breaker_threshold: 5,
+            breaker_cooldown: Duration::from_secs(1),
+        }
+    }
+}
+
+/// 配置解析错误
+#[derive(Debug, PartialEq)]
+enum ConfigError {
+    /// 值不是合法的数字
+    Invalid { var: &'static str, value: String },
+    /// 值必须大于 0
+    Zero { var: &'static str },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Invalid { var, value } => write!(f, "环境变量 {} 的值 {:?} 不是合法的正整数", var, value),
+            ConfigError::Zero { var } => write!(f, "环境变量 {} 必须大于 0", var),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ServerConfig {
+    /// 从环境变量读取配置：MAX_CONCURRENT、NUM_WORKERS、QUEUE_CAP、NUM_REQUESTS
+    fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|var| std::env::var(var).ok())
+    }
+    
+    /// 用任意查找函数读取配置，未设置的变量使用默认值
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        fn parse<T: std::str::FromStr + Default + PartialEq>(
+            lookup: &impl Fn(&str) -> Option<String>,
+            var: &'static str,
+            default: T,
+        ) -> Result<T, ConfigError> {
+            let Some(raw) = lookup(var) else { return Ok(default) };
+            let value: T = raw.trim().parse().map_err(|_| ConfigError::Invalid { var, value: raw.clone() })?;
+            if value == T::default() {
+                return Err(ConfigError::Zero { var });
+            }
+            Ok(value)
+        }
+        
+        let defaults = ServerConfig::default();
+        Ok(ServerConfig {
+            max_concurrent: parse(&lookup, "MAX_CONCURRENT", defaults.max_concurrent)?,
+            num_workers: parse(&lookup, "NUM_WORKERS", defaults.num_workers)?,
+            queue_cap: parse(&lookup, "QUEUE_CAP", defaults.queue_cap)?,
+            num_requests: parse(&lookup, "NUM_REQUESTS", defaults.num_requests)?,
+            warmup: defaults.warmup,
+            max_processing_time: defaults.max_processing_time,
+            breaker_threshold: defaults.breaker_threshold,
+            breaker_cooldown: defaults.breaker_cooldown,
+        })
+    }
+}
+
+/// 某一时刻的统计快照
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StatsSnapshot {
+    total: u64,
+    success: u64,
+    failed: u64,
+    // 已开始处理但还没有结果的请求数
+    in_flight: u64,
+}
+
+impl StatsSnapshot {
+    /// 由三个计数器得出快照，处理中的请求数 = total - success - failed
+    fn new(total: u64, success: u64, failed: u64) -> Self {
+        StatsSnapshot { total, success, failed, in_flight: total.saturating_sub(success + failed) }
+    }
+}
+
 /// 服务器统计信息
 struct ServerStats {
     total_requests: AtomicU64,
     successful_requests: AtomicU64,
     failed_requests: AtomicU64,
+    // 最近 LATENCY_WINDOW 个请求的处理耗时（环形缓冲），用于计算分位数
+    //
+    // 选 Mutex 而不是原子分桶：每个请求只在结束时短暂加锁 push 一次，竞争很小；
+    // 保留原始样本可以算出精确分位数，而分桶只能给出桶边界的近似值。
+    latencies: std::sync::Mutex<VecDeque<Duration>>,
+    // 熔断器状态变化的次数
+    breaker_transitions: AtomicU64,
+    // 计数器变化时发布最新快照，监控方 await 变化而不必轮询
+    changes: watch::Sender<StatsSnapshot>,
+    // 每个工作者处理过的请求数，按工作者 id 排序
+    //
+    // 扩容会不断分配新的 id，所以用 map 而不是固定长度的 Vec<AtomicU64>。
+    per_worker: std::sync::Mutex<BTreeMap<usize, u64>>,
+    // 每个路径成功请求的 (个数, 总耗时)；失败的请求只登记路径，不计入耗时
+    endpoints: std::sync::Mutex<HashMap<String, (u64, Duration)>>,
+    // 工作者正在处理的请求数（gauge）：开始处理时加一，InFlightGuard 被 drop 时减一
+    //
+    // 与 StatsSnapshot::in_flight 不同，它由工作者直接维护，不是从计数器推算出来的。
+    in_flight: AtomicU64,
 }
 
+/// 延迟统计保留的最大样本数，超出后丢弃最旧的样本，内存占用有上限
+const LATENCY_WINDOW: usize = 1024;
+
 impl ServerStats {
     fn new() -> Self {
         ServerStats {
             total_requests: AtomicU64::new(0),
             successful_requests: AtomicU64::new(0),
             failed_requests: AtomicU64::new(0),
+            latencies: std::sync::Mutex::new(VecDeque::with_capacity(LATENCY_WINDOW)),
+            breaker_transitions: AtomicU64::new(0),
+            changes: watch::channel(StatsSnapshot::new(0, 0, 0)).0,
+            per_worker: std::sync::Mutex::new(BTreeMap::new()),
+            endpoints: std::sync::Mutex::new(HashMap::new()),
+            in_flight: AtomicU64::new(0),
         }
     }
     
+    /// 订阅统计变化：每当计数器变化，接收端都能看到最新快照
+    fn subscribe(&self) -> watch::Receiver<StatsSnapshot> {
+        self.changes.subscribe()
+    }
+    
+    /// 发布最新快照，值没有变化时不唤醒订阅者
+    ///
+    /// 快照在 watch 的锁内读取，并发更新时最后一次发布总能看到所有计数。
+    fn publish(&self) {
+        self.changes.send_if_modified(|current| {
+            let latest = self.snapshot();
+            let changed = *current != latest;
+            *current = latest;
+            changed
+        });
+    }
+    
     fn record_request(&self) {
         self.total_requests.fetch_add(1, Ordering::Relaxed);
+        self.publish();
     }
     
+    // 结果计数用 Release：读到某个结果时，对应的 total 增量一定也可见
     fn record_success(&self) {
-        self.successful_requests.fetch_add(1, Ordering::Relaxed);
+        self.successful_requests.fetch_add(1, Ordering::Release);
+        self.publish();
     }
     
     fn record_failure(&self) {
-        self.failed_requests.fetch_add(1, Ordering::Relaxed);
+        self.failed_requests.fetch_add(1, Ordering::Release);
+        self.publish();
     }
     
-    fn print_stats(&self) {
+    /// 记录一次熔断器状态变化，同时打印出来便于观察
+    fn record_breaker_transition(&self, path: &str, from: BreakerPhase, to: BreakerPhase) {
+        self.breaker_transitions.fetch_add(1, Ordering::Relaxed);
+        println!("🔌 熔断器 {}: {:?} -> {:?}", path, from, to);
+    }
+    
+    /// 工作者开始处理一个请求，返回的守卫被 drop 时计数减一
+    fn start_request(&self) -> InFlightGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard { stats: self }
+    }
+    
+    /// 当前正在处理的请求数
+    fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+    
+    /// 记录 worker_id 处理完一个请求（被拒绝的请求不计入）
+    fn record_worker(&self, worker_id: usize) {
+        *self.per_worker.lock().unwrap().entry(worker_id).or_insert(0) += 1;
+    }
+    
+    /// 每个工作者处理过的请求数
+    fn per_worker_counts(&self) -> BTreeMap<usize, u64> {
+        self.per_worker.lock().unwrap().clone()
+    }
+    
+    /// 打印每个工作者处理的请求数，用来观察共享接收端分发是否公平
+    fn print_per_worker(&self) {
+        let counts = self.per_worker_counts();
+        let total: u64 = counts.values().sum();
+        println!("   各工作者处理的请求数:");
+        for (worker_id, count) in counts {
+            println!("     工作者 {}: {:>3} ({:>5.1}%) {}",
+                     worker_id, count, count as f64 * 100.0 / total as f64, "█".repeat(count as usize));
+        }
+    }
+    
+    /// 记录某个路径上一个请求的实际耗时
+    fn record_endpoint(&self, path: &str, elapsed: Duration, success: bool) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let (count, total) = endpoints.entry(path.to_string()).or_insert((0, Duration::ZERO));
+        if success {
+            *count += 1;
+            *total += elapsed;
+        }
+    }
+    
+    /// 每个路径成功请求的平均耗时，按平均耗时从高到低排列；没有成功请求的路径为 None，排在最后
+    fn endpoint_averages(&self) -> Vec<(String, Option<Duration>)> {
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut averages: Vec<_> = endpoints
+            .iter()
+            .map(|(path, &(count, total))| {
+                let mean = u32::try_from(count).ok().and_then(|count| total.checked_div(count));
+                (path.clone(), mean)
+            })
+            .collect();
+        averages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        averages
+    }
+    
+    fn print_endpoint_averages(&self) {
+        println!("   各路径平均耗时:");
+        for (path, mean) in self.endpoint_averages() {
+            match mean {
+                Some(mean) => println!("     {:<20} {:>7.1}ms", path, mean.as_secs_f64() * 1000.0),
+                None => println!("     {:<20}   (没有成功的请求)", path),
+            }
+        }
+    }
+    
+    fn record_latency(&self, latency: Duration) {
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == LATENCY_WINDOW {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+    
+    /// 处理耗时的分位数，q 取 0.0..=1.0（还没有数据时为 0）
+    ///
+    /// 复制样本后在锁外排序，不阻塞正在上报延迟的工作者。
+    fn latency_percentile(&self, q: f64) -> Duration {
+        let mut latencies: Vec<Duration> = self.latencies.lock().unwrap().iter().copied().collect();
+        latencies.sort();
+        let index = ((latencies.len() as f64 * q) as usize).min(latencies.len().saturating_sub(1));
+        latencies.get(index).copied().unwrap_or_default()
+    }
+    
+    /// 处理耗时的中位数（还没有数据时为 0）
+    fn p50_latency(&self) -> Duration {
+        self.latency_percentile(0.5)
+    }
+    
+    fn print_latency(&self) {
+        let ms = |q| self.latency_percentile(q).as_secs_f64() * 1000.0;
+        println!("   延迟: p50 {:.1}ms / p95 {:.1}ms / p99 {:.1}ms", ms(0.50), ms(0.95), ms(0.99));
+    }
+    
+    /// 读取统计快照
+    ///
+    /// 先读结果计数再读 total，保证快照中 success + failed <= total。
+    fn snapshot(&self) -> StatsSnapshot {
+        let success = self.successful_requests.load(Ordering::Acquire);
+        let failed = self.failed_requests.load(Ordering::Acquire);
         let total = self.total_requests.load(Ordering::Relaxed);
-        let success = self.successful_requests.load(Ordering::Relaxed);
-        let failed = self.failed_requests.load(Ordering::Relaxed);
+        StatsSnapshot::new(total, success, failed)
+    }
+    
+    fn print_stats(&self) {
+        let StatsSnapshot { total, success, failed, .. } = self.snapshot();
         
         println!("\n📊 服务器统计:");
         println!("   总请求数: {}", total);
         println!("   成功: {} ({:.1}%)", success, (success as f64 / total as f64) * 100.0);
         println!("   失败: {} ({:.1}%)", failed, (failed as f64 / total as f64) * 100.0);
+        self.print_latency();
+        println!("   熔断器状态变化: {} 次", self.breaker_transitions.load(Ordering::Relaxed));
+        self.print_per_worker();
+        self.print_endpoint_averages();
+    }
+}
+
+/// 用 seqlock 保护的三元计数器：读者无锁，且总能读到一致的 (total, success, failed)
+///
+/// 版本号为奇数表示有写者正在修改。写者先把版本号改成奇数（CAS 保证同一时刻只有一个写者），
+/// 写完再加一变回偶数；读者读数据前后各读一次版本号，不一致或为奇数就重试。
+struct Stats {
+    seq: AtomicU64,
+    total: AtomicU64,
+    success: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Stats {
+            seq: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            success: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+    
+    /// 在写临界区内执行 f
+    fn write(&self, f: impl FnOnce()) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq % 2 == 1 {
+                std::hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.seq.compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        // 保证数据写入不会被重排到“版本号变奇数”之前
+        std::sync::atomic::fence(Ordering::Release);
+        f();
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+    
+    fn record_request(&self) {
+        self.write(|| {
+            self.total.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    
+    fn record_outcome(&self, success: bool) {
+        self.write(|| {
+            let counter = if success { &self.success } else { &self.failed };
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    
+    /// 读取一致的快照，遇到并发写入时重试
+    fn snapshot(&self) -> StatsSnapshot {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                std::hint::spin_loop();
+                continue;
+            }
+            let snapshot = StatsSnapshot::new(
+                self.total.load(Ordering::Relaxed),
+                self.success.load(Ordering::Relaxed),
+                self.failed.load(Ordering::Relaxed),
+            );
+            // 保证上面的读取不会被重排到第二次读版本号之后
+            std::sync::atomic::fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return snapshot;
+            }
+        }
+    }
+}
+
+/// in_flight gauge 的守卫
+///
+/// 处理请求的 future 可能在任意 await 点被丢弃（工作者任务被 abort），
+/// 放在 Drop 里减一，计数就不会因为取消而泄漏。
+struct InFlightGuard<'a> {
+    stats: &'a ServerStats,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.stats.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 请求计时守卫
+///
+/// 正常完成时调用 finish() 记录完成事件；如果请求在完成前被取消
+/// （Future 被 drop），Drop 实现会记录一条 "cancelled" 事件，
+/// 保证每个请求都留下带耗时的日志。被取消的请求同时计为一次失败，
+/// 否则它只有 record_request 而没有结果，快照里会一直多出一个处理中的请求。
+struct RequestTimer<'a> {
+    request_id: u64,
+    stats: &'a ServerStats,
+    // 用 tokio 的时钟，与请求处理中的 sleep / timeout 一致
+    start: tokio::time::Instant,
+    finished: bool,
+}
+
+impl<'a> RequestTimer<'a> {
+    fn start(request_id: u64, stats: &'a ServerStats) -> Self {
+        RequestTimer {
+            request_id,
+            stats,
+            start: tokio::time::Instant::now(),
+            finished: false,
+        }
+    }
+    
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+    
+    fn finish(mut self, status: u16) {
+        self.finished = true;
+        tracing::info!(
+            request_id = self.request_id,
+            status,
+            elapsed_ms = self.elapsed_ms(),
+            "request completed"
+        );
+    }
+}
+
+impl Drop for RequestTimer<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.stats.record_failure();
+            tracing::warn!(
+                request_id = self.request_id,
+                elapsed_ms = self.elapsed_ms(),
+                "cancelled"
+            );
+        }
     }
 }
 
@@ -73,296 +508,3894 @@ impl ServerStats {
 struct RequestHandler {
     id: usize,
     stats: Arc<ServerStats>,
+    // 超过这个时间仍未处理完就放弃，避免一个异常慢的请求一直占着工作者
+    max_processing_time: Duration,
+    // 所有工作者共享的按路径熔断器，打开期间直接返回 503
+    breakers: Arc<BreakerRegistry>,
+}
+
+tokio::task_local! {
+    /// 当前正在处理的请求 id，由工作者在调用处理器之前设置
+    static REQUEST_ID: u64;
+}
+
+/// 读取当前任务的请求 id；不在请求作用域内时返回 None
+fn current_request_id() -> Option<u64> {
+    REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// 生成响应体：请求 id 从 task-local 读取，不需要层层传参
+fn render_body(path: &str) -> String {
+    match current_request_id() {
+        Some(id) => format!("Response for {} (request #{})", path, id),
+        None => format!("Response for {}", path),
+    }
 }
 
 impl RequestHandler {
+    #[tracing::instrument(
+        name = "request",
+        parent = &request.span,
+        skip_all,
+        fields(worker = self.id, path = %request.path)
+    )]
     async fn handle_request(&self, request: Request) -> Response {
         println!("🔧 处理器{} 开始处理请求 #{} ({})", 
             self.id, request.id, request.path);
         
+        let timer = RequestTimer::start(request.id, &self.stats);
+        let start = std::time::Instant::now();
         self.stats.record_request();
         
-        // 模拟请求处理
-        sleep(request.processing_time).await;
+        // 熔断器打开时不做任何处理，立即拒绝
+        let breaker = self.breakers.for_path(&request.path);
+        if !breaker.allow() {
+            self.stats.record_failure();
+            self.stats.record_endpoint(&request.path, start.elapsed(), false);
+            println!("⛔ 处理器{} 拒绝请求 #{}：{} 已熔断", self.id, request.id, request.path);
+            timer.finish(503);
+            return Response {
+                request_id: request.id,
+                status: 503,
+                body: "Circuit open".to_string(),
+            };
+        }
+        
+        // 模拟请求处理，超时则返回 504
+        let finished = timeout(self.max_processing_time, sleep(request.processing_time)).await;
         
         // 模拟偶尔的失败
-        let status = if request.id % 7 == 0 {
+        let status = if finished.is_err() {
+            self.stats.record_failure();
+            504
+        } else if request.id % 7 == 0 {
             self.stats.record_failure();
             500
         } else {
             self.stats.record_success();
             200
         };
+        breaker.record(status < 500);
+        self.stats.record_endpoint(&request.path, start.elapsed(), status == 200);
         
         let response = Response {
             request_id: request.id,
             status,
-            body: format!("Response for {}", request.path),
+            body: render_body(&request.path),
         };
         
         println!("✅ 处理器{} 完成请求 #{} (状态: {})", 
             self.id, request.id, status);
+        timer.finish(status);
         
         response
     }
 }
 
-/// 负载均衡器
-struct LoadBalancer {
-    request_tx: mpsc::Sender<Request>,
-    response_rx: Arc<tokio::sync::Mutex<mpsc::Receiver<Response>>>,
-    semaphore: Arc<Semaphore>,
-    #[allow(dead_code)]
-    stats: Arc<ServerStats>,
+/// 从请求路径中提取租户名：取路径的第一段，如 "/tenant-a/orders" -> "tenant-a"
+fn tenant_of(path: &str) -> &str {
+    path.trim_start_matches('/').split('/').next().unwrap_or("")
 }
 
-impl LoadBalancer {
-    fn new(max_concurrent: usize, stats: Arc<ServerStats>) -> Self {
-        let (request_tx, request_rx) = mpsc::channel(100);
-        let (response_tx, response_rx) = mpsc::channel(100);
-        let semaphore = Arc::new(Semaphore::new(max_concurrent));
-        
-        // 启动工作者池 - 所有工作者共享一个 receiver
-        let num_workers = 4;
-        let request_rx = Arc::new(tokio::sync::Mutex::new(request_rx));
-        
-        for worker_id in 0..num_workers {
-            let rx = request_rx.clone();
-            let tx = response_tx.clone();
-            let sem = semaphore.clone();
-            let stats = stats.clone();
-            
-            tokio::spawn(async move {
-                let handler = RequestHandler {
-                    id: worker_id,
-                    stats,
-                };
-                
-                loop {
-                    // 从共享 receiver 中获取请求
-                    let request = {
-                        let mut rx = rx.lock().await;
-                        rx.recv().await
-                    };
-                    
-                    match request {
-                        Some(request) => {
-                            let _permit = sem.acquire().await.unwrap();
-                            let response = handler.handle_request(request).await;
-                            if tx.send(response).await.is_err() {
-                                break;
-                            }
-                        }
-                        None => break,
-                    }
-                }
-                
-                println!("⚠️  工作者 {} 退出", worker_id);
-            });
-        }
-        
-        drop(response_tx); // 关闭发送端
-        
-        LoadBalancer {
-            request_tx,
-            response_rx: Arc::new(tokio::sync::Mutex::new(response_rx)),
-            semaphore,
-            stats,
+/// WFQ 调度器的内部状态（只在持锁期间短暂访问，不跨越 .await）
+struct WfqState<T> {
+    weights: HashMap<String, u32>,
+    // 每个租户一个队列，元素为 (虚拟开始时间, 虚拟完成时间, 请求)
+    queues: HashMap<String, VecDeque<(f64, f64, T)>>,
+    // 每个租户最后一个入队请求的虚拟完成时间
+    last_finish: HashMap<String, f64>,
+    virtual_time: f64,
+    closed: bool,
+}
+
+impl<T> WfqState<T> {
+    /// cost 是请求的“成本”，通常就是它占用工作者的时间
+    fn push(&mut self, tenant: String, cost: f64, request: T) {
+        let weight = self.weights.get(&tenant).copied().unwrap_or(1).max(1) as f64;
+
+        let last = self.last_finish.get(&tenant).copied().unwrap_or(0.0);
+        let start = last.max(self.virtual_time);
+        let finish = start + cost / weight;
+
+        self.last_finish.insert(tenant.clone(), finish);
+        self.queues.entry(tenant).or_default().push_back((start, finish, request));
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        // 选出队首虚拟完成时间最小的租户
+        let tenant = self
+            .queues
+            .iter()
+            .filter_map(|(tenant, queue)| queue.front().map(|(_, finish, _)| (tenant, *finish)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(tenant, _)| tenant.clone())?;
+
+        let (start, _, request) = self.queues.get_mut(&tenant)?.pop_front()?;
+        self.virtual_time = self.virtual_time.max(start);
+        Some(request)
+    }
+}
+
+/// 加权公平队列（Weighted Fair Queueing）调度器
+///
+/// 每个租户按权重分得工作者时间：权重越大，虚拟时间走得越慢，
+/// 也就越早被调度。这样一个“吵闹”的租户无法独占整个工作者池。
+struct WfqScheduler<T> {
+    state: std::sync::Mutex<WfqState<T>>,
+    notify: Notify,
+}
+
+impl<T> WfqScheduler<T> {
+    fn new(weights: HashMap<String, u32>) -> Self {
+        WfqScheduler {
+            state: std::sync::Mutex::new(WfqState {
+                weights,
+                queues: HashMap::new(),
+                last_finish: HashMap::new(),
+                virtual_time: 0.0,
+                closed: false,
+            }),
+            notify: Notify::new(),
         }
     }
-    
-    async fn submit_request(&self, request: Request) -> Result<(), &'static str> {
-        self.request_tx
-            .send(request)
-            .await
-            .map_err(|_| "无法提交请求")
+
+    fn enqueue(&self, tenant: String, cost: f64, request: T) {
+        self.state.lock().unwrap().push(tenant, cost, request);
+        self.notify.notify_one();
     }
-    
-    async fn get_response(&self) -> Option<Response> {
-        let mut rx = self.response_rx.lock().await;
-        rx.recv().await
+
+    /// 各租户队列中等待调度的请求总数
+    fn len(&self) -> usize {
+        self.state.lock().unwrap().queues.values().map(VecDeque::len).sum()
     }
-    
-    fn available_slots(&self) -> usize {
-        self.semaphore.available_permits()
+
+    /// 关闭调度器：已入队的请求仍会被取走，之后 dequeue 返回 None
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_waiters();
     }
-}
 
-/// 请求生成器
-async fn request_generator(lb: Arc<LoadBalancer>, num_requests: u64) {
-    println!("🚀 开始生成 {} 个请求\n", num_requests);
-    
-    for i in 1..=num_requests {
-        let request = Request {
-            id: i,
-            path: format!("/api/endpoint{}", i % 5),
-            processing_time: Duration::from_millis(100 + (i % 5) * 50),
-        };
-        
-        println!("📤 提交请求 #{}", i);
-        
-        match lb.submit_request(request).await {
-            Ok(_) => {},
-            Err(e) => {
-                println!("❌ 提交请求失败: {}", e);
-                break;
+    async fn dequeue(&self) -> Option<T> {
+        loop {
+            // 先注册通知再检查状态，避免错过 close() 发出的 notify_waiters
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(request) = state.pop() {
+                    return Some(request);
+                }
+                if state.closed {
+                    return None;
+                }
             }
+
+            notified.await;
         }
-        
-        // 模拟请求到达的间隔
-        sleep(Duration::from_millis(50)).await;
     }
-    
-    println!("\n✅ 所有请求已提交");
 }
 
-/// 响应收集器
-async fn response_collector(lb: Arc<LoadBalancer>, expected_count: u64) {
-    println!("📥 响应收集器启动\n");
-    
-    let mut received = 0;
+/// 平滑加权轮询（smooth weighted round-robin）
+///
+/// 每次选择时所有工作者的 current 加上自己的权重，选出 current 最大的一个，再减去总权重。
+/// 每一轮 sum(weights) 次选择中工作者 i 恰好被选中 weights[i] 次，而且分布均匀，
+/// 不会连续把一串请求都发给权重最大的工作者。
+struct WeightedRoundRobin {
+    weights: Vec<u32>,
+    current: Vec<i64>,
+    total: i64,
+}
+
+impl WeightedRoundRobin {
+    fn new(weights: Vec<u32>) -> Self {
+        let total = weights.iter().map(|&w| w as i64).sum();
+        WeightedRoundRobin { current: vec![0; weights.len()], weights, total }
+    }
     
-    while received < expected_count {
-        // 设置超时避免无限等待
-        match timeout(Duration::from_secs(10), lb.get_response()).await {
-            Ok(Some(response)) => {
-                received += 1;
-                if response.status == 200 {
-                    println!("✅ 收到响应 #{}: 成功", response.request_id);
-                } else {
-                    println!("⚠️  收到响应 #{}: 失败 (状态: {})", 
-                        response.request_id, response.status);
-                }
+    /// 选出下一个请求的工作者；权重为 0 的工作者永远不会被选中
+    fn pick(&mut self) -> usize {
+        for (current, &weight) in self.current.iter_mut().zip(&self.weights) {
+            *current += weight as i64;
+        }
+        // 并列时取编号最小的，保证结果可复现
+        let (index, _) = self
+            .current
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(&a.0)))
+            .expect("至少有一个工作者");
+        self.current[index] -= self.total;
+        index
+    }
+}
+
+/// 工作者获取请求的来源
+enum RequestSource<T> {
+    /// 所有工作者共享一个 receiver（先进先出）
+    Shared(Arc<tokio::sync::Mutex<mpsc::Receiver<T>>>),
+    /// 高、低优先级两个 receiver，总是先取高优先级的请求
+    Prioritized(tokio::sync::Mutex<(mpsc::Receiver<T>, mpsc::Receiver<T>)>),
+    /// 由 WFQ 调度器按租户公平分发
+    Wfq(Arc<WfqScheduler<T>>),
+    /// 每个工作者一个专属 receiver，由分发任务决定请求发给谁（工作者数量固定，不支持 scale_to）
+    ///
+    /// 计数器是分发任务已经取走、还没被工作者收到的请求数：专属通道的 receiver
+    /// 被等待中的工作者锁住，无法直接查询长度。
+    PerWorker(Vec<tokio::sync::Mutex<mpsc::Receiver<T>>>, Arc<AtomicUsize>),
+}
+
+impl<T> RequestSource<T> {
+    /// 为 worker_id 取下一个请求；来源已关闭时返回 None
+    async fn next(&self, worker_id: usize) -> Option<T> {
+        match self {
+            RequestSource::Shared(rx) => {
+                let mut rx = rx.lock().await;
+                rx.recv().await
             }
-            Ok(None) => {
-                println!("⚠️  响应通道关闭");
-                break;
+            RequestSource::Prioritized(receivers) => {
+                let mut receivers = receivers.lock().await;
+                let (high, low) = &mut *receivers;
+                // biased 让 select! 按书写顺序检查：高优先级通道有请求就不会轮到低优先级
+                tokio::select! {
+                    biased;
+                    Some(job) = high.recv() => Some(job),
+                    Some(job) = low.recv() => Some(job),
+                    else => None, // 两个通道都已关闭
+                }
             }
-            Err(_) => {
-                println!("⏱️  等待响应超时");
-                break;
+            RequestSource::Wfq(scheduler) => scheduler.dequeue().await,
+            // scale_to 对加权均衡器不生效，worker_id 总能找到对应的专属通道
+            RequestSource::PerWorker(receivers, buffered) => {
+                let job = receivers.get(worker_id)?.lock().await.recv().await;
+                if job.is_some() {
+                    buffered.fetch_sub(1, Ordering::AcqRel);
+                }
+                job
             }
         }
     }
     
-    println!("\n📦 收集器完成，共收到 {} 个响应", received);
+    /// 已离开请求通道、但还没被工作者取走的请求数
+    fn buffered(&self) -> usize {
+        match self {
+            // 请求一直留在通道里，由 LoadBalancer::queued 按发送端的容量计算
+            RequestSource::Shared(_) | RequestSource::Prioritized(_) => 0,
+            RequestSource::Wfq(scheduler) => scheduler.len(),
+            RequestSource::PerWorker(_, buffered) => buffered.load(Ordering::Acquire),
+        }
+    }
 }
 
-/// 监控任务
-async fn monitor_task(lb: Arc<LoadBalancer>, duration: Duration) {
-    let start = tokio::time::Instant::now();
-    let mut interval = tokio::time::interval(Duration::from_secs(2));
+/// 基于延迟的自适应并发限制（AIMD：加性增、乘性减）
+///
+/// 请求延迟低于阈值时上限加 1（add_permits）；超过阈值时上限减半。
+/// Semaphore 无法直接减少许可，所以减半时由后台任务 acquire 多余的许可后
+/// forget，相当于把它们永久“没收”，等正在使用的许可归还后收缩自然生效。
+struct AdaptiveLimiter {
+    semaphore: Arc<Semaphore>,
+    limit: std::sync::Mutex<usize>,
+    min_limit: usize,
+    max_limit: usize,
+    latency_threshold: Duration,
+}
+
+impl AdaptiveLimiter {
+    /// semaphore 当前的许可数必须等于 initial
+    fn new(
+        semaphore: Arc<Semaphore>,
+        initial: usize,
+        min_limit: usize,
+        max_limit: usize,
+        latency_threshold: Duration,
+    ) -> Self {
+        AdaptiveLimiter {
+            semaphore,
+            limit: std::sync::Mutex::new(initial),
+            min_limit: min_limit.max(1),
+            max_limit,
+            latency_threshold,
+        }
+    }
+    
+    /// 每个请求完成后上报一次延迟
+    fn on_latency(&self, latency: Duration) {
+        let mut limit = self.limit.lock().unwrap();
+        
+        if latency <= self.latency_threshold {
+            // 加性增
+            if *limit < self.max_limit {
+                self.semaphore.add_permits(1);
+                *limit += 1;
+            }
+        } else {
+            // 乘性减
+            let target = (*limit / 2).max(self.min_limit);
+            let excess = *limit - target;
+            if excess > 0 {
+                *limit = target;
+                let sem = self.semaphore.clone();
+                tokio::spawn(async move {
+                    if let Ok(permits) = sem.acquire_many_owned(excess as u32).await {
+                        permits.forget();
+                    }
+                });
+            }
+        }
+    }
     
-    while start.elapsed() < duration {
-        interval.tick().await;
-        println!("\n📊 监控: 可用槽位 = {}", lb.available_slots());
+    fn limit(&self) -> usize {
+        *self.limit.lock().unwrap()
     }
 }
 
-/// 主服务器函数
-async fn run_server() {
-    println!("🎓 综合实战：异步 HTTP 服务器模拟\n");
-    println!("{}", "=".repeat(50));
+/// 基于延迟的过载保护：滚动 p99 超过阈值时按比例直接拒绝请求（503）
+///
+/// 拒绝比例为 1 - threshold / p99，超出越多丢弃越多，最高 90%，
+/// 保证总有请求能通过，从而观察到延迟恢复。
+struct LoadShedder {
+    state: std::sync::Mutex<ShedderState>,
+    window: usize,
+    threshold: Duration,
+}
+
+struct ShedderState {
+    // 最近 window 个被处理请求的延迟
+    recent: VecDeque<Duration>,
+    // 累计的丢弃额度，满 1 就丢弃一个请求，使丢弃均匀分布
+    credit: f64,
+}
+
+impl LoadShedder {
+    fn new(window: usize, threshold: Duration) -> Self {
+        LoadShedder {
+            state: std::sync::Mutex::new(ShedderState {
+                recent: VecDeque::with_capacity(window),
+                credit: 0.0,
+            }),
+            window,
+            threshold,
+        }
+    }
+    
+    fn record(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if state.recent.len() == self.window {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(latency);
+    }
+    
+    fn p99(recent: &VecDeque<Duration>) -> Option<Duration> {
+        let mut sorted: Vec<_> = recent.iter().copied().collect();
+        sorted.sort();
+        let index = (sorted.len() * 99).div_ceil(100).checked_sub(1)?;
+        sorted.get(index).copied()
+    }
+    
+    /// 当前应丢弃的请求比例
+    fn shed_fraction(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        match Self::p99(&state.recent) {
+            Some(p99) if p99 > self.threshold => {
+                (1.0 - self.threshold.as_secs_f64() / p99.as_secs_f64()).min(0.9)
+            }
+            _ => 0.0,
+        }
+    }
+    
+    /// 决定是否丢弃下一个请求
+    fn should_shed(&self) -> bool {
+        let fraction = self.shed_fraction();
+        let mut state = self.state.lock().unwrap();
+        if fraction == 0.0 {
+            state.credit = 0.0;
+            return false;
+        }
+        state.credit += fraction;
+        if state.credit >= 1.0 {
+            state.credit -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// 工作者池调用的处理逻辑：LoadBalancer 只通过这个 trait 接触具体的请求和响应类型
+///
+/// HTTP 模拟（RequestHandler）只是其中一种实现，自定义的消息类型同样可以复用
+/// 排队、并发限制、预热、排空和 oneshot 回复这些机制。
+#[async_trait]
+trait Handler: Send + Sync + 'static {
+    type Req: Send + 'static;
+    type Resp: Send + 'static;
+    
+    async fn handle(&self, req: Self::Req) -> Self::Resp;
+    
+    /// 提交请求时调用（在调用者的上下文中），默认什么都不做
+    fn prepare(_req: &mut Self::Req) {}
+    
+    /// 过载保护要丢弃请求时返回的响应；返回 None 表示不支持丢弃，照常处理
+    fn reject(&self, _req: &Self::Req) -> Option<Self::Resp> {
+        None
+    }
+}
+
+#[async_trait]
+impl Handler for RequestHandler {
+    type Req = Request;
+    type Resp = Response;
+    
+    async fn handle(&self, request: Request) -> Response {
+        REQUEST_ID.scope(request.id, self.handle_request(request)).await
+    }
+    
+    // 记下提交时的 span，工作者处理时以它为父 span
+    fn prepare(request: &mut Request) {
+        request.span = tracing::Span::current();
+    }
+    
+    fn reject(&self, request: &Request) -> Option<Response> {
+        Some(Response {
+            request_id: request.id,
+            status: 503,
+            body: "服务过载，请求被丢弃".to_string(),
+        })
+    }
+}
+
+/// 排队中的一个请求；reply 不为空时响应直接送回调用者，不经过共享的响应通道
+struct Job<H: Handler> {
+    request: H::Req,
+    reply: Option<oneshot::Sender<H::Resp>>,
+}
+
+/// 工作者池的可选行为
+#[derive(Default)]
+struct WorkerOptions {
+    limiter: Option<Arc<AdaptiveLimiter>>,
+    shedder: Option<Arc<LoadShedder>>,
+    warmup: Duration,
+}
+
+/// 为每个工作者创建处理器，参数是工作者编号
+type HandlerFactory<H> = Arc<dyn Fn(usize) -> H + Send + Sync>;
+
+/// 所有工作者共享的运行环境
+struct WorkerContext<H: Handler> {
+    source: Arc<RequestSource<Job<H>>>,
+    // 弱引用：只有正在运行的工作者持有强引用，它们全部退出后响应通道才会关闭
+    response_tx: mpsc::WeakSender<H::Resp>,
+    semaphore: Arc<Semaphore>,
+    stats: Arc<ServerStats>,
+    limiter: Option<Arc<AdaptiveLimiter>>,
+    shedder: Option<Arc<LoadShedder>>,
+    make_handler: HandlerFactory<H>,
+    warmup: Duration,
+    // 尚未完成预热的工作者数量
+    warming: Arc<watch::Sender<usize>>,
+    // 缩容令牌：空闲的工作者拿到一个就退出
+    retire: Arc<Semaphore>,
+    // 仍在运行的工作者数量
+    live: Arc<AtomicUsize>,
+    // 停止信号：收到后工作者处理完手上的请求就退出，不再从队列取新请求
+    shutdown: broadcast::Sender<()>,
+}
+
+// 手写 Clone：derive 会要求 H: Clone，而这里只克隆 Arc 和 Sender
+impl<H: Handler> Clone for WorkerContext<H> {
+    fn clone(&self) -> Self {
+        WorkerContext {
+            source: self.source.clone(),
+            response_tx: self.response_tx.clone(),
+            semaphore: self.semaphore.clone(),
+            stats: self.stats.clone(),
+            limiter: self.limiter.clone(),
+            shedder: self.shedder.clone(),
+            make_handler: self.make_handler.clone(),
+            warmup: self.warmup,
+            warming: self.warming.clone(),
+            retire: self.retire.clone(),
+            live: self.live.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+/// HTTP 模拟用的处理器工厂：每个工作者一个 RequestHandler
+///
+/// 熔断器按路径共享：任何一个工作者观察到的失败都会计入同一个熔断器。
+fn http_handlers(stats: Arc<ServerStats>, config: &ServerConfig) -> HandlerFactory<RequestHandler> {
+    let max_processing_time = config.max_processing_time;
+    let breakers = Arc::new(
+        BreakerRegistry::new(config.breaker_threshold, config.breaker_cooldown).observed_by(stats.clone()),
+    );
+    Arc::new(move |id| RequestHandler {
+        id,
+        stats: stats.clone(),
+        max_processing_time,
+        breakers: breakers.clone(),
+    })
+}
+
+/// 预热计数的守卫
+///
+/// 工作者可能在预热期间被 abort，或者在 make_handler 中 panic；
+/// 放在 Drop 里减一，wait_ready 就不会一直等一个永远不会预热完的工作者。
+struct WarmupGuard {
+    warming: Arc<watch::Sender<usize>>,
+}
+
+impl WarmupGuard {
+    fn new(warming: Arc<watch::Sender<usize>>) -> Self {
+        warming.send_modify(|n| *n += 1);
+        WarmupGuard { warming }
+    }
+}
+
+impl Drop for WarmupGuard {
+    fn drop(&mut self) {
+        self.warming.send_modify(|n| *n -= 1);
+    }
+}
+
+/// 启动一个工作者：不断从 source 取请求，处理后把响应送回调用者或发往 response_tx
+///
+/// 响应通道已经关闭（所有工作者都已退出）时返回 None。
+fn spawn_worker<H: Handler>(worker_id: usize, ctx: WorkerContext<H>) -> Option<tokio::task::JoinHandle<()>> {
+    // 在调用者这里升级：任务启动前原来的强引用可能已经被 drop
+    let response_tx = ctx.response_tx.upgrade()?;
+    ctx.live.fetch_add(1, Ordering::AcqRel);
+    // 在启动任务前计数，wait_ready 立刻就能看到这个工作者；守卫随任务一起移动
+    let warming = WarmupGuard::new(ctx.warming.clone());
+    // 在启动任务前订阅，保证之后发出的停止信号一定能收到
+    let mut shutdown = ctx.shutdown.subscribe();
+    
+    Some(tokio::spawn(async move {
+        let handler = (ctx.make_handler)(worker_id);
+        
+        // 模拟初始化：预热完成之前不接收请求
+        sleep(ctx.warmup).await;
+        drop(warming);
+        
+        loop {
+            // 空闲时才响应缩容；正在处理的请求总会先处理完
+            let job = tokio::select! {
+                biased;
+                Ok(token) = ctx.retire.acquire() => {
+                    token.forget();
+                    println!("📉 工作者 {} 缩容退出", worker_id);
+                    break;
+                }
+                // 负载均衡器被 drop 时通道关闭，recv 返回 Err，这里不当作停止信号
+                Ok(()) = shutdown.recv() => {
+                    println!("🛑 工作者 {} 收到停止信号", worker_id);
+                    break;
+                }
+                job = ctx.source.next(worker_id) => job,
+            };
+            let Some(Job { request, mut reply }) = job else { break };
+            // 调用者在处理开始前就已放弃（例如对冲中落败的一方）：直接跳过
+            if reply.as_ref().is_some_and(|reply| reply.is_closed()) {
+                println!("🗑️  工作者 {} 跳过调用者已放弃的请求", worker_id);
+                continue;
+            }
+            // 过载时直接拒绝（HTTP 模拟中是 503），不占用处理资源
+            let rejected = ctx.shedder.as_ref()
+                .filter(|s| s.should_shed())
+                .and_then(|_| handler.reject(&request));
+            let response = if let Some(response) = rejected {
+                ctx.stats.record_request();
+                ctx.stats.record_failure();
+                response
+            } else {
+                let permit = ctx.semaphore.acquire().await.unwrap();
+                let start = tokio::time::Instant::now();
+                let in_flight = ctx.stats.start_request();
+                // 处理期间调用者放弃（对冲的另一方先返回）就中止处理，不再占用许可
+                let caller_gone = async {
+                    match reply.as_mut() {
+                        Some(reply) => reply.closed().await,
+                        None => std::future::pending().await,
+                    }
+                };
+                let response = tokio::select! {
+                    response = handler.handle(request) => Some(response),
+                    _ = caller_gone => None,
+                };
+                drop(in_flight);
+                
+                // 先归还许可，再上报延迟（收缩时需要拿回这些许可）
+                drop(permit);
+                let Some(response) = response else {
+                    println!("🗑️  工作者 {} 中止处理：调用者已放弃", worker_id);
+                    continue;
+                };
+                ctx.stats.record_worker(worker_id);
+                ctx.stats.record_latency(start.elapsed());
+                if let Some(limiter) = &ctx.limiter {
+                    limiter.on_latency(start.elapsed());
+                }
+                if let Some(shedder) = &ctx.shedder {
+                    shedder.record(start.elapsed());
+                }
+                response
+            };
+            
+            // 调用者在等待时直接交给它；恰好在处理完成时放弃则丢弃结果
+            if let Some(reply) = reply {
+                if reply.send(response).is_err() {
+                    println!("🗑️  调用者已放弃，结果被丢弃");
+                }
+                continue;
+            }
+            
+            if response_tx.send(response).await.is_err() {
+                break;
+            }
+        }
+        
+        ctx.live.fetch_sub(1, Ordering::AcqRel);
+        println!("⚠️  工作者 {} 退出", worker_id);
+    }))
+}
+
+/// LoadBalancerBuilder 的参数错误
+#[derive(Debug, PartialEq)]
+enum BuildError {
+    /// 参数必须大于 0
+    Zero { field: &'static str },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::Zero { field } => write!(f, "{} 必须至少为 1", field),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// try_submit 的失败原因，调用者据此决定是退避重试还是放弃
+#[derive(Debug, PartialEq)]
+enum SubmitError {
+    /// 请求队列已满，稍后可以重试
+    Busy,
+    /// 请求通道已关闭或服务器正在排空，不会再接收请求
+    Closed,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubmitError::Busy => write!(f, "请求队列已满，请稍后重试"),
+            SubmitError::Closed => write!(f, "请求通道已关闭"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+/// 负载均衡器构建器：未设置的参数沿用 ServerConfig 的默认值
+struct LoadBalancerBuilder {
+    config: ServerConfig,
+}
+
+impl LoadBalancerBuilder {
+    fn new() -> Self {
+        LoadBalancerBuilder { config: ServerConfig::default() }
+    }
+    
+    /// 工作者数量
+    fn workers(mut self, n: usize) -> Self {
+        self.config.num_workers = n;
+        self
+    }
+    
+    /// 请求队列容量（高、低优先级通道各一个）
+    fn queue_capacity(mut self, n: usize) -> Self {
+        self.config.queue_cap = n;
+        self
+    }
+    
+    /// 同时处理的请求数上限
+    fn max_concurrent(mut self, n: usize) -> Self {
+        self.config.max_concurrent = n;
+        self
+    }
+    
+    /// 校验参数后创建负载均衡器
+    fn build(self, stats: Arc<ServerStats>) -> Result<LoadBalancer, BuildError> {
+        let checks = [
+            ("workers", self.config.num_workers),
+            ("max_concurrent", self.config.max_concurrent),
+            ("queue_capacity", self.config.queue_cap),
+        ];
+        if let Some((field, _)) = checks.into_iter().find(|(_, value)| *value == 0) {
+            return Err(BuildError::Zero { field });
+        }
+        Ok(LoadBalancer::with_config(&self.config, stats))
+    }
+}
+
+/// 负载均衡器
+///
+/// 工作者池、排队和并发控制都只依赖 Handler trait；默认的类型参数是 HTTP 模拟。
+struct LoadBalancer<H: Handler = RequestHandler> {
+    request_tx: mpsc::Sender<Job<H>>,
+    // 高优先级请求的通道（只有按配置创建的负载均衡器才有）
+    urgent_tx: Option<mpsc::Sender<Job<H>>>,
+    response_rx: Arc<tokio::sync::Mutex<mpsc::Receiver<H::Resp>>>,
+    semaphore: Arc<Semaphore>,
+    stats: Arc<ServerStats>,
+    limiter: Option<Arc<AdaptiveLimiter>>,
+    draining: AtomicBool,
+    ready: watch::Receiver<usize>,
+    next_attempt_id: AtomicU64,
+    workers: std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>,
+    // 扩容时用来启动新工作者
+    spawner: WorkerContext<H>,
+    next_worker_id: AtomicUsize,
+}
+
+impl LoadBalancer<RequestHandler> {
+    /// 使用默认的工作者数量和队列容量，max_concurrent 为 0 时 panic
+    fn new(max_concurrent: usize, stats: Arc<ServerStats>) -> Self {
+        LoadBalancerBuilder::new()
+            .max_concurrent(max_concurrent)
+            .build(stats)
+            .expect("LoadBalancer::new 的参数无效")
+    }
+    
+    /// 按配置创建负载均衡器
+    fn with_config(config: &ServerConfig, stats: Arc<ServerStats>) -> Self {
+        let handlers = http_handlers(stats.clone(), config);
+        Self::with_handler(config, stats, handlers)
+    }
+    
+    /// 以 WFQ 模式创建负载均衡器：按租户权重公平分配工作者时间
+    ///
+    /// 队列容量、并发上限、工作者数量和处理器设置都取自 config。
+    fn new_wfq(config: &ServerConfig, weights: HashMap<String, u32>, stats: Arc<ServerStats>) -> Self {
+        let (request_tx, mut request_rx) = mpsc::channel::<Job<RequestHandler>>(config.queue_cap);
+        let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+        let scheduler = Arc::new(WfqScheduler::new(weights));
+        
+        // 入队任务：把提交的请求按租户放入调度器，成本是请求占用工作者的时间
+        let feeder = scheduler.clone();
+        tokio::spawn(async move {
+            while let Some(job) = request_rx.recv().await {
+                let tenant = tenant_of(&job.request.path).to_string();
+                let cost = job.request.processing_time.as_millis().max(1) as f64;
+                feeder.enqueue(tenant, cost, job);
+            }
+            feeder.close();
+        });
+        
+        let source = RequestSource::Wfq(scheduler);
+        let options = WorkerOptions { warmup: config.warmup, ..WorkerOptions::default() };
+        let handlers = http_handlers(stats.clone(), config);
+        Self::start(request_tx, source, config.num_workers, semaphore, stats, options, handlers)
+    }
+    
+    /// 创建按权重分配请求的负载均衡器：每个权重对应一个工作者
+    ///
+    /// 分发任务用平滑加权轮询把请求发到各工作者的专属通道，权重为 2 的工作者
+    /// 收到的请求是权重为 1 的两倍。专属通道写满时分发任务会等待，
+    /// 所以慢工作者会拖慢后续请求的分发。所有权重都为 0 时 panic。
+    /// 工作者与权重一一对应，之后调用 scale_to 不会改变工作者数量。
+    /// 队列容量、并发上限和处理器设置取自 config，config.num_workers 不起作用。
+    fn new_weighted(weights: Vec<u32>, config: &ServerConfig, stats: Arc<ServerStats>) -> Self {
+        assert!(weights.iter().any(|&w| w > 0), "至少需要一个权重大于 0 的工作者");
+        let (request_tx, mut request_rx) = mpsc::channel::<Job<RequestHandler>>(config.queue_cap);
+        let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+        
+        let (worker_txs, worker_rxs): (Vec<_>, Vec<_>) = weights
+            .iter()
+            .map(|_| {
+                let (tx, rx) = mpsc::channel(16);
+                (tx, tokio::sync::Mutex::new(rx))
+            })
+            .unzip();
+        
+        // 分发任务：请求通道关闭后 worker_txs 被 drop，各工作者取完剩余请求后退出
+        let num_workers = weights.len();
+        let mut balancer = WeightedRoundRobin::new(weights);
+        let buffered = Arc::new(AtomicUsize::new(0));
+        let dispatched = buffered.clone();
+        tokio::spawn(async move {
+            while let Some(job) = request_rx.recv().await {
+                // 先计数再发送：等待专属通道空位的这个请求也算作排队中
+                dispatched.fetch_add(1, Ordering::AcqRel);
+                if worker_txs[balancer.pick()].send(job).await.is_err() {
+                    // 工作者已经退出，这个请求没能送达，不再算作排队中
+                    dispatched.fetch_sub(1, Ordering::AcqRel);
+                    break;
+                }
+            }
+        });
+        
+        let source = RequestSource::PerWorker(worker_rxs, buffered);
+        let options = WorkerOptions { warmup: config.warmup, ..WorkerOptions::default() };
+        let handlers = http_handlers(stats.clone(), config);
+        Self::start(request_tx, source, num_workers, semaphore, stats, options, handlers)
+    }
+    
+    /// 创建并发上限随延迟自适应调整（AIMD）的负载均衡器
+    fn new_adaptive(
+        num_workers: usize,
+        initial_limit: usize,
+        max_limit: usize,
+        latency_threshold: Duration,
+        stats: Arc<ServerStats>,
+    ) -> Self {
+        let (request_tx, request_rx) = mpsc::channel(100);
+        let semaphore = Arc::new(Semaphore::new(initial_limit));
+        let limiter = Arc::new(AdaptiveLimiter::new(
+            semaphore.clone(),
+            initial_limit,
+            1,
+            max_limit,
+            latency_threshold,
+        ));
+        
+        let source = RequestSource::Shared(Arc::new(tokio::sync::Mutex::new(request_rx)));
+        let options = WorkerOptions { limiter: Some(limiter), ..WorkerOptions::default() };
+        let handlers = http_handlers(stats.clone(), &ServerConfig::default());
+        Self::start(request_tx, source, num_workers, semaphore, stats, options, handlers)
+    }
+    
+    /// 创建带延迟过载保护的负载均衡器
+    fn new_shedding(max_concurrent: usize, shedder: Arc<LoadShedder>, stats: Arc<ServerStats>) -> Self {
+        let config = ServerConfig { max_concurrent, ..ServerConfig::default() };
+        let (request_tx, request_rx) = mpsc::channel(config.queue_cap);
+        let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+        let source = RequestSource::Shared(Arc::new(tokio::sync::Mutex::new(request_rx)));
+        let options = WorkerOptions { shedder: Some(shedder), ..WorkerOptions::default() };
+        let handlers = http_handlers(stats.clone(), &config);
+        Self::start(request_tx, source, config.num_workers, semaphore, stats, options, handlers)
+    }
+    
+    /// 按 request.priority 提交：大于 0 的请求进入高优先级通道，工作者总是先处理它们
+    ///
+    /// 没有高优先级通道的负载均衡器（如 WFQ 模式）按普通请求提交。
+    async fn submit_prioritized(&self, request: Request) -> Result<(), &'static str> {
+        match &self.urgent_tx {
+            Some(urgent_tx) if request.priority > 0 => self.submit_via(urgent_tx, request, None).await,
+            _ => self.submit_request(request).await,
+        }
+    }
+    
+    /// 提交一次尝试，响应通过 oneshot 单独送回
+    ///
+    /// 返回的尝试 id 只用来区分同一请求的多次尝试，request.id 保持调用者给的值。
+    /// 丢弃返回的 Receiver 即表示放弃这次尝试，其结果会被工作者丢弃。
+    async fn dispatch(&self, request: Request) -> Result<(u64, oneshot::Receiver<Response>), &'static str> {
+        let attempt_id = self.next_attempt_id.fetch_add(1, Ordering::Relaxed);
+        Ok((attempt_id, self.submit_with_reply(request).await?))
+    }
+    
+    /// 对冲请求：primary 在 hedge_delay 内没有响应就再提交 hedge，取先完成的一个
+    ///
+    /// hedge 通常是 primary 的副本（也可以指向另一个副本节点）。
+    /// 返回响应以及它是否来自对冲请求；落败一方的 Receiver 被 drop，工作者发现后会中止
+    /// 对它的处理（还在排队的则直接跳过），其结果不会出现在响应通道里。
+    async fn call_hedged(
+        &self,
+        primary: Request,
+        hedge: Request,
+        hedge_delay: Duration,
+    ) -> Result<(Response, bool), &'static str> {
+        let (_, mut primary_rx) = self.dispatch(primary).await?;
+        
+        tokio::select! {
+            response = &mut primary_rx => return response.map(|r| (r, false)).map_err(|_| "工作者已退出"),
+            _ = sleep(hedge_delay) => {}
+        }
+        
+        let request_id = hedge.id;
+        let (attempt_id, mut hedge_rx) = self.dispatch(hedge).await?;
+        println!("⏩ {}ms 内无响应，发出对冲请求 #{}（尝试 {}）", hedge_delay.as_millis(), request_id, attempt_id);
+        
+        // 返回时另一个 Receiver 被 drop，相当于取消落败的一方
+        tokio::select! {
+            response = &mut primary_rx => response.map(|r| (r, false)).map_err(|_| "工作者已退出"),
+            response = &mut hedge_rx => response.map(|r| (r, true)).map_err(|_| "工作者已退出"),
+        }
+    }
+}
+
+impl<H: Handler> LoadBalancer<H> {
+    /// 用自定义处理器创建负载均衡器，每个工作者调用一次 make_handler 得到自己的处理器
+    fn with_handler(config: &ServerConfig, stats: Arc<ServerStats>, make_handler: HandlerFactory<H>) -> Self {
+        let (request_tx, request_rx) = mpsc::channel(config.queue_cap);
+        let (urgent_tx, urgent_rx) = mpsc::channel(config.queue_cap);
+        let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+        
+        // 启动工作者池 - 所有工作者共享高、低优先级两个 receiver
+        let source = RequestSource::Prioritized(tokio::sync::Mutex::new((urgent_rx, request_rx)));
+        
+        let options = WorkerOptions { warmup: config.warmup, ..WorkerOptions::default() };
+        let mut lb = Self::start(request_tx, source, config.num_workers, semaphore, stats, options, make_handler);
+        lb.urgent_tx = Some(urgent_tx);
+        lb
+    }
+    
+    /// 按给定的请求来源启动工作者池
+    fn start(
+        request_tx: mpsc::Sender<Job<H>>,
+        source: RequestSource<Job<H>>,
+        num_workers: usize,
+        semaphore: Arc<Semaphore>,
+        stats: Arc<ServerStats>,
+        options: WorkerOptions,
+        make_handler: HandlerFactory<H>,
+    ) -> Self {
+        let (response_tx, response_rx) = mpsc::channel(100);
+        let (warming, ready) = watch::channel(0);
+        let limiter = options.limiter;
+        let ctx = WorkerContext {
+            source: Arc::new(source),
+            response_tx: response_tx.downgrade(),
+            semaphore: semaphore.clone(),
+            stats: stats.clone(),
+            limiter: limiter.clone(),
+            shedder: options.shedder,
+            make_handler,
+            warmup: options.warmup,
+            warming: Arc::new(warming),
+            retire: Arc::new(Semaphore::new(0)),
+            live: Arc::new(AtomicUsize::new(0)),
+            shutdown: broadcast::channel(1).0,
+        };
+        
+        let workers = (0..num_workers)
+            .filter_map(|worker_id| spawn_worker(worker_id, ctx.clone()))
+            .collect();
+        // response_tx 在这里被 drop，只剩工作者持有强引用，它们全部退出后响应通道关闭
+        
+        LoadBalancer {
+            request_tx,
+            urgent_tx: None,
+            response_rx: Arc::new(tokio::sync::Mutex::new(response_rx)),
+            semaphore,
+            stats,
+            limiter,
+            draining: AtomicBool::new(false),
+            ready,
+            // 内部分配的尝试 id 从一个足够大的数开始，避免和外部请求 id 冲突
+            next_attempt_id: AtomicU64::new(1_000_000),
+            workers: std::sync::Mutex::new(workers),
+            spawner: ctx,
+            next_worker_id: AtomicUsize::new(num_workers),
+        }
+    }
+    
+    async fn submit_request(&self, request: H::Req) -> Result<(), &'static str> {
+        self.submit_job(request, None).await
+    }
+    
+    async fn submit_job(&self, request: H::Req, reply: Option<oneshot::Sender<H::Resp>>) -> Result<(), &'static str> {
+        self.submit_via(&self.request_tx, request, reply).await
+    }
+    
+    async fn submit_via(
+        &self,
+        tx: &mpsc::Sender<Job<H>>,
+        mut request: H::Req,
+        reply: Option<oneshot::Sender<H::Resp>>,
+    ) -> Result<(), &'static str> {
+        if self.draining.load(Ordering::Acquire) {
+            return Err("服务器正在排空，拒绝新请求");
+        }
+        H::prepare(&mut request);
+        tx.send(Job { request, reply })
+            .await
+            .map_err(|_| "无法提交请求")
+    }
+    
+    /// 请求通道满时按指数退避重试提交（100ms、200ms、400ms……），最多重试 max_retries 次
+    ///
+    /// 用 try_send 区分可以重试的"通道已满"和无法恢复的"通道已关闭"。
+    async fn submit_request_retry(&self, mut request: H::Req, max_retries: usize) -> Result<(), &'static str> {
+        if self.draining.load(Ordering::Acquire) {
+            return Err("服务器正在排空，拒绝新请求");
+        }
+        H::prepare(&mut request);
+        
+        let mut job = Job { request, reply: None };
+        let mut backoff = Duration::from_millis(100);
+        for attempt in 0..=max_retries {
+            job = match self.request_tx.try_send(job) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::error::TrySendError::Closed(_)) => return Err("请求通道已关闭"),
+                Err(mpsc::error::TrySendError::Full(job)) => job,
+            };
+            if attempt < max_retries {
+                println!("⏳ 请求通道已满，{}ms 后重试", backoff.as_millis());
+                sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        Err("请求通道已满，重试次数已用完")
+    }
+    
+    /// 不等待地提交请求：队列满时立即返回 Busy，把背压交给调用者处理
+    fn try_submit(&self, mut request: H::Req) -> Result<(), SubmitError> {
+        if self.draining.load(Ordering::Acquire) {
+            return Err(SubmitError::Closed);
+        }
+        H::prepare(&mut request);
+        self.request_tx
+            .try_send(Job { request, reply: None })
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => SubmitError::Busy,
+                mpsc::error::TrySendError::Closed(_) => SubmitError::Closed,
+            })
+    }
+    
+    async fn get_response(&self) -> Option<H::Resp> {
+        let mut rx = self.response_rx.lock().await;
+        rx.recv().await
+    }
+    
+    /// 提交请求并等待它自己的响应
+    ///
+    /// 响应通过随请求一起排队的 oneshot 直接送回调用者，不经过共享的响应通道，
+    /// 所以并发调用之间不会拿错响应。fire-and-forget 的 submit_request 不受影响。
+    async fn call(&self, request: H::Req) -> Result<H::Resp, &'static str> {
+        let reply_rx = self.submit_with_reply(request).await?;
+        reply_rx.await.map_err(|_| "工作者已退出")
+    }
+    
+    /// 带上 oneshot 提交请求，返回等待响应的 Receiver
+    async fn submit_with_reply(&self, request: H::Req) -> Result<oneshot::Receiver<H::Resp>, &'static str> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.submit_job(request, Some(reply_tx)).await?;
+        Ok(reply_rx)
+    }
+    
+    /// 关闭负载均衡器：不再接收新请求，等已排队和正在处理的请求全部完成后返回
+    ///
+    /// 关闭请求通道后工作者取完剩余请求就会退出。期间没人读取的响应会被取走丢弃，
+    /// 否则响应通道写满后工作者会卡在发送上，永远等不到它们退出。
+    async fn shutdown(self) {
+        let LoadBalancer { request_tx, urgent_tx, response_rx, workers, spawner, .. } = self;
+        drop((request_tx, urgent_tx));
+        drop(spawner);
+        let workers = workers.into_inner().unwrap();
+        
+        let discard_responses = async {
+            let mut rx = response_rx.lock().await;
+            while rx.recv().await.is_some() {}
+        };
+        let (_, results) = tokio::join!(discard_responses, futures::future::join_all(workers));
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        if failed > 0 {
+            println!("⚠️  {} 个工作者异常退出", failed);
+        }
+    }
+    
+    /// 立即停止：通知所有工作者处理完手上的请求后退出，等它们全部结束，返回被丢弃的排队请求数
+    ///
+    /// 与 shutdown 不同，队列中还没被工作者取走的请求不再处理。
+    /// 工作者退出前可能还要发出最后一个响应，所以调用期间需要有人在读取响应通道。
+    async fn stop(&self) -> usize {
+        self.begin_drain();
+        let _ = self.spawner.shutdown.send(());
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let results = futures::future::join_all(workers).await;
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        if failed > 0 {
+            println!("⚠️  {} 个工作者异常退出", failed);
+        }
+        self.queued()
+    }
+    
+    /// 等待工作者取走的请求数
+    ///
+    /// 包括请求通道中的请求，以及已经转入 WFQ 调度器或加权模式专属通道、还没被处理的请求。
+    fn queued(&self) -> usize {
+        let in_channels: usize = std::iter::once(&self.request_tx)
+            .chain(self.urgent_tx.as_ref())
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .sum();
+        in_channels + self.spawner.source.buffered()
+    }
+    
+    /// 把工作者数量调整到 target
+    ///
+    /// 扩容直接启动新工作者，它们共享同一个请求来源；缩容发放退出令牌，
+    /// 由空闲的工作者领取后退出，正在处理请求的工作者会先把手上的请求处理完。
+    /// 所以缩容是渐进的，worker_count() 会在工作者真正退出后才减少。
+    ///
+    /// 加权负载均衡器（new_weighted）的每个工作者都有自己的权重和专属通道，
+    /// 新工作者没有通道可取，退出的工作者会留下无人消费的请求，所以此时什么也不做。
+    ///
+    /// target 为 0 时按 1 处理：没有工作者的话，排队的请求永远不会被处理。
+    fn scale_to(&self, target: usize) {
+        if matches!(*self.spawner.source, RequestSource::PerWorker(..)) {
+            println!("⚠️  加权负载均衡器的工作者数量由权重决定，忽略 scale_to({})", target);
+            return;
+        }
+        if target == 0 {
+            println!("⚠️  至少保留 1 个工作者，scale_to(0) 按 scale_to(1) 处理");
+        }
+        let target = target.max(1);
+        
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|handle| !handle.is_finished());
+        
+        // 已发出但还没被领取的令牌对应的工作者也算作即将退出
+        let outstanding = self.spawner.retire.available_permits();
+        let effective = self.spawner.live.load(Ordering::Acquire).saturating_sub(outstanding);
+        
+        if target < effective {
+            self.spawner.retire.add_permits(effective - target);
+            println!("📉 缩容: {} -> {} 个工作者", effective, target);
+        } else if target > effective {
+            // 先收回尚未领取的令牌，不够再启动新工作者
+            let mut missing = target - effective;
+            let reclaim = missing.min(outstanding);
+            if let Ok(tokens) = self.spawner.retire.try_acquire_many(reclaim as u32) {
+                tokens.forget();
+                missing -= reclaim;
+            }
+            for _ in 0..missing {
+                let worker_id = self.next_worker_id.fetch_add(1, Ordering::Relaxed);
+                match spawn_worker(worker_id, self.spawner.clone()) {
+                    Some(handle) => workers.push(handle),
+                    None => break, // 已关闭，不再扩容
+                }
+            }
+            println!("📈 扩容: {} -> {} 个工作者", effective, target);
+        }
+    }
+    
+    /// 仍在运行的工作者数量
+    fn worker_count(&self) -> usize {
+        self.spawner.live.load(Ordering::Acquire)
+    }
+    
+    /// 等待所有工作者完成预热
+    async fn wait_ready(&self) {
+        let mut ready = self.ready.clone();
+        // 每个工作者预热结束或中途退出时都会把计数减一（见 WarmupGuard），计数不会卡住
+        let _ = ready.wait_for(|warming| *warming == 0).await;
+    }
+    
+    /// 当前统计信息的快照
+    fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+    
+    /// 订阅统计变化，见 ServerStats::subscribe
+    fn watch_stats(&self) -> watch::Receiver<StatsSnapshot> {
+        self.stats.subscribe()
+    }
+    
+    /// 开始排空：之后提交的请求一律被拒绝，已排队的请求照常处理
+    fn begin_drain(&self) {
+        self.draining.store(true, Ordering::Release);
+    }
+    
+    fn available_slots(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+    
+    /// 自适应模式下当前的有效并发上限
+    fn concurrency_limit(&self) -> Option<usize> {
+        self.limiter.as_ref().map(|l| l.limit())
+    }
+}
+
+// === 弹性调用：重试 + 退避 + 熔断 ===
+
+/// 熔断器所处的阶段
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerPhase {
+    /// 正常放行
+    Closed,
+    /// 冷却中，拒绝所有请求
+    Open,
+    /// 冷却结束，只放行一个试探请求
+    HalfOpen,
+}
+
+/// 熔断器内部状态
+struct BreakerState {
+    phase: BreakerPhase,
+    consecutive_failures: u32,
+    opened_at: Option<tokio::time::Instant>,
+    // 半开状态下试探请求的发出时间
+    //
+    // 发出试探的 future 可能在 record 之前被丢弃（调用方超时、任务被取消），
+    // 所以超过 cooldown 仍没有结果的试探视为作废，下一个请求可以重新试探，熔断器不会永远卡在半开。
+    trial_started: Option<tokio::time::Instant>,
+}
+
+/// 熔断器：连续失败达到阈值后打开，冷却期过后半开，只放行一个试探请求
+///
+/// 试探成功则关闭，失败则重新打开并重新计时；试探超过冷却期仍没有结果则允许重新试探。
+struct CircuitBreaker {
+    state: std::sync::Mutex<BreakerState>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    // 状态变化时通知的统计对象和熔断器名称（通常是 path）
+    observer: Option<(String, Arc<ServerStats>)>,
+}
+
+impl CircuitBreaker {
+    fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            state: std::sync::Mutex::new(BreakerState {
+                phase: BreakerPhase::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                trial_started: None,
+            }),
+            failure_threshold,
+            cooldown,
+            observer: None,
+        }
+    }
+    
+    /// 切换阶段，并把变化报告给统计对象
+    fn transition(&self, state: &mut BreakerState, to: BreakerPhase) {
+        let from = state.phase;
+        if from == to {
+            return;
+        }
+        state.phase = to;
+        if let Some((name, stats)) = &self.observer {
+            stats.record_breaker_transition(name, from, to);
+        }
+    }
+    
+    /// 是否允许发出请求：关闭状态放行；冷却期过后转为半开，只放行一个试探请求
+    fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.phase {
+            BreakerPhase::Closed => true,
+            BreakerPhase::Open => {
+                if state.opened_at.is_some_and(|at| at.elapsed() < self.cooldown) {
+                    return false;
+                }
+                self.transition(&mut state, BreakerPhase::HalfOpen);
+                state.trial_started = Some(tokio::time::Instant::now());
+                true
+            }
+            BreakerPhase::HalfOpen => {
+                if state.trial_started.is_some_and(|at| at.elapsed() < self.cooldown) {
+                    return false;
+                }
+                state.trial_started = Some(tokio::time::Instant::now());
+                true
+            }
+        }
+    }
+    
+    /// 熔断器当前是否处于打开或半开状态（只查询，不会占用试探名额）
+    fn is_open(&self) -> bool {
+        self.state.lock().unwrap().phase != BreakerPhase::Closed
+    }
+    
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.trial_started = None;
+        if success {
+            state.consecutive_failures = 0;
+            state.opened_at = None;
+            self.transition(&mut state, BreakerPhase::Closed);
+        } else {
+            state.consecutive_failures += 1;
+            if state.phase == BreakerPhase::HalfOpen || state.consecutive_failures >= self.failure_threshold {
+                // 半开试探失败时重新计时
+                state.opened_at = Some(tokio::time::Instant::now());
+                self.transition(&mut state, BreakerPhase::Open);
+            }
+        }
+    }
+}
+
+/// 按 path 划分的熔断器集合：每个端点状态独立，首次用到时才创建
+struct BreakerRegistry {
+    breakers: std::sync::Mutex<HashMap<String, Arc<CircuitBreaker>>>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    observer: Option<Arc<ServerStats>>,
+}
+
+impl BreakerRegistry {
+    fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        BreakerRegistry {
+            breakers: std::sync::Mutex::new(HashMap::new()),
+            failure_threshold,
+            cooldown,
+            observer: None,
+        }
+    }
+    
+    /// 让之后创建的熔断器把状态变化记入 stats
+    fn observed_by(mut self, stats: Arc<ServerStats>) -> Self {
+        self.observer = Some(stats);
+        self
+    }
+    
+    /// 取得 path 对应的熔断器，不存在时按统一参数创建
+    fn for_path(&self, path: &str) -> Arc<CircuitBreaker> {
+        let mut breakers = self.breakers.lock().unwrap();
+        breakers
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                let mut breaker = CircuitBreaker::new(self.failure_threshold, self.cooldown);
+                breaker.observer = self.observer.clone().map(|stats| (path.to_string(), stats));
+                Arc::new(breaker)
+            })
+            .clone()
+    }
+}
+
+/// 弹性调用策略
+struct ResiliencePolicy {
+    max_attempts: u32,
+    base_backoff: Duration,
+    breakers: Arc<BreakerRegistry>,
+}
+
+/// 弹性调用的最终结果
+#[derive(Debug)]
+enum CallOutcome {
+    Success(Response),
+    /// 重试预算耗尽，附带最后一次的状态码
+    Failed(u16),
+    /// 熔断器打开，请求未发出
+    Rejected,
+}
+
+/// 弹性调用的详细报告
+#[derive(Debug)]
+struct CallResult {
+    attempts: u32,
+    breaker_open: bool,
+    outcome: CallOutcome,
+}
+
+/// 带重试、指数退避和熔断的调用入口
+///
+/// 每次重试都作为一个新请求通过 lb.call 提交（id 依次加 1），响应经由随请求排队的 oneshot
+/// 直接送回，不经过共享的响应通道，所以可以与其他调用者并发使用同一个 lb。
+async fn resilient_call(lb: &LoadBalancer, req: Request, policy: &ResiliencePolicy) -> CallResult {
+    let mut last_status = 0;
+    let breaker = policy.breakers.for_path(&req.path);
+    
+    for attempt in 0..policy.max_attempts {
+        if !breaker.allow() {
+            return CallResult { attempts: attempt, breaker_open: true, outcome: CallOutcome::Rejected };
+        }
+        
+        let request = Request { id: req.id + attempt as u64, ..req.clone() };
+        let status = lb.call(request).await.ok().map(|r| (r.status, r));
+        
+        match status {
+            Some((status, response)) if status < 500 => {
+                breaker.record(true);
+                return CallResult {
+                    attempts: attempt + 1,
+                    breaker_open: false,
+                    outcome: CallOutcome::Success(response),
+                };
+            }
+            other => {
+                last_status = other.map_or(503, |(status, _)| status);
+                breaker.record(false);
+            }
+        }
+        
+        if attempt + 1 < policy.max_attempts {
+            sleep(policy.base_backoff * 2u32.pow(attempt)).await;
+        }
+    }
+    
+    CallResult {
+        attempts: policy.max_attempts,
+        breaker_open: breaker.is_open(),
+        outcome: CallOutcome::Failed(last_status),
+    }
+}
+
+/// 令牌桶限速器：桶里最多存 capacity 个令牌，每秒补充 refill_rate 个
+///
+/// 桶满时允许一次突发 capacity 个请求，之后按补充速率匀速放行。
+/// 桶状态放在 tokio 的 Mutex 里并且在等待期间一直持有，多个调用者按到达顺序排队取令牌。
+struct RateLimiter {
+    // (当前令牌数, 上次补充时间)
+    bucket: tokio::sync::Mutex<(f64, tokio::time::Instant)>,
+    capacity: f64,
+    refill_rate: f64,
+}
+
+impl RateLimiter {
+    /// 最低补充速率：每 1000 秒一个令牌
+    const MIN_REFILL_RATE: f64 = 0.001;
+    
+    /// 创建时桶是满的
+    ///
+    /// 与 capacity 一样，refill_rate 会被修正到有效范围：0、负数和 NaN 都按 MIN_REFILL_RATE 处理，
+    /// 否则等待时长无法表示为 Duration。
+    fn new(capacity: u32, refill_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let refill_rate = refill_rate.max(Self::MIN_REFILL_RATE); // f64::max 遇到 NaN 时取另一边
+        RateLimiter {
+            bucket: tokio::sync::Mutex::new((capacity, tokio::time::Instant::now())),
+            capacity,
+            refill_rate,
+        }
+    }
+    
+    /// 取走一个令牌，没有令牌时等到补充出一个为止
+    async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        let (tokens, last) = &mut *bucket;
+        
+        let now = tokio::time::Instant::now();
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.refill_rate).min(self.capacity);
+        *last = now;
+        
+        if *tokens < 1.0 {
+            // 令牌不足：睡到刚好补满一个，这段时间补充的令牌正好被这次取走
+            sleep(Duration::from_secs_f64((1.0 - *tokens) / self.refill_rate)).await;
+            *tokens = 1.0;
+            *last = tokio::time::Instant::now();
+        }
+        *tokens -= 1.0;
+    }
+}
+
+/// 请求生成器
+async fn request_generator(lb: Arc<LoadBalancer>, num_requests: u64, limiter: &RateLimiter) {
+    println!("🚀 开始生成 {} 个请求\n", num_requests);
+    
+    for i in 1..=num_requests {
+        // 按限速器的节奏模拟请求到达
+        limiter.acquire().await;
+        
+        let request = Request::new(i, format!("/api/endpoint{}", i % 5), Duration::from_millis(100 + (i % 5) * 50));
+        
+        println!("📤 提交请求 #{}", i);
+        
+        match lb.submit_request(request).await {
+            Ok(_) => {},
+            Err(e) => {
+                println!("❌ 提交请求失败: {}", e);
+                break;
+            }
+        }
+        
+    }
+    
+    println!("\n✅ 所有请求已提交");
+}
+
+/// 响应收集器
+async fn response_collector(lb: Arc<LoadBalancer>, expected_count: u64) {
+    println!("📥 响应收集器启动\n");
+    
+    let mut received = 0;
+    
+    while received < expected_count {
+        // 设置超时避免无限等待
+        match timeout(Duration::from_secs(10), lb.get_response()).await {
+            Ok(Some(response)) => {
+                received += 1;
+                if response.status == 200 {
+                    println!("✅ 收到响应 #{}: 成功", response.request_id);
+                } else {
+                    println!("⚠️  收到响应 #{}: 失败 (状态: {})", 
+                        response.request_id, response.status);
+                }
+            }
+            Ok(None) => {
+                println!("⚠️  响应通道关闭");
+                break;
+            }
+            Err(_) => {
+                println!("⏱️  等待响应超时");
+                break;
+            }
+        }
+    }
+    
+    println!("\n📦 收集器完成，共收到 {} 个响应", received);
+}
+
+/// 有界重排缓冲：按 request_id 顺序交付响应
+///
+/// 缺失的响应不会让收集器无限等下去：缓冲超过 `max_buffered` 个，
+/// 或者队首缺口等待超过 `max_wait`，就跳到已缓冲的最小 id，并把跳过的 id 记为缺口。
+/// 缺口之后才迟到的响应直接丢弃，保证交付顺序单调。
+struct ReorderBuffer {
+    next_id: u64,
+    pending: BTreeMap<u64, Response>,
+    max_buffered: usize,
+    max_wait: Duration,
+    stalled_since: Option<tokio::time::Instant>,
+    gaps: Vec<u64>,
+}
+
+impl ReorderBuffer {
+    fn new(first_id: u64, max_buffered: usize, max_wait: Duration) -> Self {
+        ReorderBuffer {
+            next_id: first_id,
+            pending: BTreeMap::new(),
+            max_buffered,
+            max_wait,
+            stalled_since: None,
+            gaps: Vec::new(),
+        }
+    }
+    
+    /// 放入一个响应，返回现在可以按顺序交付的响应
+    fn push(&mut self, response: Response) -> Vec<Response> {
+        if response.request_id < self.next_id {
+            println!("   🗑️  请求 #{} 的响应来得太迟（已记为缺口），丢弃", response.request_id);
+            return Vec::new();
+        }
+        self.pending.insert(response.request_id, response);
+        let mut ready = self.drain_ready();
+        if self.pending.len() > self.max_buffered {
+            ready.extend(self.skip_ahead());
+        }
+        ready
+    }
+    
+    /// 队首缺口的等待期限；没有缓冲的响应时为 None
+    fn deadline(&self) -> Option<tokio::time::Instant> {
+        self.stalled_since.map(|since| since + self.max_wait)
+    }
+    
+    /// 放弃等待队首缺口，跳到已缓冲的最小 id
+    fn skip_ahead(&mut self) -> Vec<Response> {
+        if let Some(&first) = self.pending.keys().next() {
+            self.gaps.extend(self.next_id..first);
+            self.next_id = first;
+        }
+        self.drain_ready()
+    }
+    
+    fn drain_ready(&mut self) -> Vec<Response> {
+        let mut ready = Vec::new();
+        while let Some(response) = self.pending.remove(&self.next_id) {
+            self.next_id += 1;
+            ready.push(response);
+        }
+        // 每次有进展都重新计时，只有队首一直卡住才会超时
+        self.stalled_since = if self.pending.is_empty() {
+            None
+        } else if ready.is_empty() {
+            self.stalled_since.or_else(|| Some(tokio::time::Instant::now()))
+        } else {
+            Some(tokio::time::Instant::now())
+        };
+        ready
+    }
+}
+
+/// 从 channel 读取乱序响应，按 id 顺序交付；返回 (交付顺序, 缺口)
+async fn collect_ordered(
+    mut rx: mpsc::Receiver<Response>,
+    mut buffer: ReorderBuffer,
+) -> (Vec<u64>, Vec<u64>) {
+    let mut delivered = Vec::new();
+    loop {
+        let deadline = buffer.deadline();
+        let ready = tokio::select! {
+            received = rx.recv() => match received {
+                Some(response) => buffer.push(response),
+                None => break,
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if deadline.is_some() => {
+                println!("   ⏱️  请求 #{} 的响应等待超时，跳过", buffer.next_id);
+                buffer.skip_ahead()
+            }
+        };
+        for response in ready {
+            println!("   📬 按序交付 #{}", response.request_id);
+            delivered.push(response.request_id);
+        }
+    }
+    // 通道关闭后不会再有响应，剩下的缺口全部跳过
+    while !buffer.pending.is_empty() {
+        delivered.extend(buffer.skip_ahead().iter().map(|r| r.request_id));
+    }
+    (delivered, buffer.gaps)
+}
+
+/// 监控任务采集的一条指标：统计计数加上采集时刻的并发状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct MetricsSnapshot {
+    timestamp: std::time::SystemTime,
+    available_slots: usize,
+    total: u64,
+    success: u64,
+    failed: u64,
+    // 工作者正在处理的请求数，来自 ServerStats 的 in_flight gauge
+    in_flight: u64,
+}
+
+impl MetricsSnapshot {
+    /// 在当前时刻采集一条指标
+    fn capture(lb: &LoadBalancer) -> Self {
+        let StatsSnapshot { total, success, failed, .. } = lb.stats();
+        MetricsSnapshot {
+            timestamp: std::time::SystemTime::now(),
+            available_slots: lb.available_slots(),
+            total,
+            success,
+            failed,
+            in_flight: lb.stats.in_flight(),
+        }
+    }
+}
+
+/// 监控任务：统计一有变化就采集一条指标并打印，最多运行 duration，返回采集到的全部指标
+async fn monitor_task(lb: Arc<LoadBalancer>, duration: Duration) -> Vec<MetricsSnapshot> {
+    let mut changes = lb.watch_stats();
+    let deadline = sleep(duration);
+    tokio::pin!(deadline);
+    let mut metrics = Vec::new();
+    
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            changed = changes.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                changes.borrow_and_update();
+                let m = MetricsSnapshot::capture(&lb);
+                println!("📊 监控: 总数 {} / 成功 {} / 失败 {} / 处理中 {} / 可用槽位 {}",
+                         m.total, m.success, m.failed, m.in_flight, m.available_slots);
+                metrics.push(m);
+            }
+        }
+    }
+    metrics
+}
+
+/// 关闭钩子：一个返回 Future 的一次性闭包
+type ShutdownHook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// 关闭钩子注册表
+///
+/// 各组件在启动时注册自己的清理逻辑（刷新统计、关闭文件等），
+/// 关闭时由 run_all() 按注册的相反顺序（LIFO）依次执行，
+/// 就像栈上变量的 drop 顺序一样：后创建的组件先清理。
+struct ShutdownHooks {
+    hooks: std::sync::Mutex<Vec<(String, ShutdownHook)>>,
+}
+
+impl ShutdownHooks {
+    fn new() -> Self {
+        ShutdownHooks {
+            hooks: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+    
+    fn register<F, Fut>(&self, name: &str, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: ShutdownHook = Box::new(move || Box::pin(hook()));
+        self.hooks.lock().unwrap().push((name.to_string(), hook));
+    }
+    
+    /// 按 LIFO 顺序执行所有钩子；每个钩子只会执行一次
+    async fn run_all(&self) {
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        for (name, hook) in hooks.into_iter().rev() {
+            println!("🧹 执行关闭钩子: {}", name);
+            hook().await;
+        }
+    }
+}
+
+/// 服务器的结束方式
+#[derive(Debug)]
+enum ServerOutcome {
+    /// 所有组件都在期限内正常结束
+    Clean,
+    /// 超过期限：开始排空，并强制中止了仍未结束的组件
+    Forced { aborted: Vec<&'static str> },
+    /// 收到 Ctrl+C：工作者处理完手上的请求后退出，队列中剩余的请求被丢弃
+    Interrupted { dropped: usize },
+}
+
+/// run_server 结束时的汇总报告，便于程序化检查
+#[derive(Debug)]
+struct ServerReport {
+    total: u64,
+    success: u64,
+    failed: u64,
+    p50_latency: Duration,
+    duration: Duration,
+    outcome: ServerOutcome,
+}
+
+/// 在总期限内等待所有组件结束；超时则排空负载均衡器并中止剩余组件
+async fn supervise(
+    lb: &LoadBalancer,
+    components: Vec<(&'static str, tokio::task::JoinHandle<()>)>,
+    deadline: Duration,
+) -> ServerOutcome {
+    let deadline = tokio::time::Instant::now() + deadline;
+    let mut aborted = Vec::new();
+    let mut draining = false;
+    
+    for (name, mut handle) in components {
+        if !draining {
+            if tokio::time::timeout_at(deadline, &mut handle).await.is_ok() {
+                continue;
+            }
+            println!("⏰ 超过关闭期限，开始排空并强制停止剩余组件");
+            lb.begin_drain();
+            draining = true;
+        }
+        
+        if !handle.is_finished() {
+            handle.abort();
+            aborted.push(name);
+        }
+    }
+    
+    if aborted.is_empty() {
+        ServerOutcome::Clean
+    } else {
+        ServerOutcome::Forced { aborted }
+    }
+}
+
+/// 主服务器函数：从环境变量读取配置，按 Ctrl+C 提前停止
+///
+/// tokio::signal::ctrl_c 第一次调用时会为整个进程安装 SIGINT 处理器，之后再按 Ctrl+C
+/// 也不会终止进程。所以被中断后 main 直接退出，不再运行后面的演示。
+async fn run_server() -> ServerReport {
+    println!("🎓 综合实战：异步 HTTP 服务器模拟\n");
+    println!("{}", "=".repeat(50));
+    
+    // 读取配置，解析失败时使用默认值
+    let config = ServerConfig::from_env().unwrap_or_else(|e| {
+        println!("⚠️  {}，使用默认配置", e);
+        ServerConfig::default()
+    });
+    
+    // 安装信号处理失败时当作永远不会被中断
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    run_server_with(config, ctrl_c).await
+}
+
+/// 按给定配置运行服务器模拟；interrupt 完成时提前停止
+async fn run_server_with(config: ServerConfig, interrupt: impl Future<Output = ()>) -> ServerReport {
+    let started = tokio::time::Instant::now();
+    
+    // 创建服务器组件
+    let stats = Arc::new(ServerStats::new());
+    let load_balancer = Arc::new(LoadBalancer::with_config(&config, stats.clone()));
+    
+    // 注册关闭时的清理逻辑
+    let hooks = ShutdownHooks::new();
+    let stats_for_hook = stats.clone();
+    hooks.register("打印统计", move || async move {
+        stats_for_hook.print_stats();
+    });
+    
+    println!("⚙️  服务器配置:");
+    println!("   • 最大并发: {}", config.max_concurrent);
+    println!("   • 工作者数量: {}", config.num_workers);
+    println!("   • 请求队列大小: {}\n", config.queue_cap);
+    
+    let num_requests = config.num_requests;
+    
+    // 启动各个组件
+    let lb_clone1 = load_balancer.clone();
+    let generator = tokio::spawn(async move {
+        // 每秒 20 个、不允许突发，与原来固定间隔 50ms 的节奏相同
+        let limiter = RateLimiter::new(1, 20.0);
+        request_generator(lb_clone1, num_requests, &limiter).await;
+    });
+    
+    let lb_clone2 = load_balancer.clone();
+    let collector = tokio::spawn(async move {
+        response_collector(lb_clone2, num_requests).await;
+    });
+    
+    let lb_clone3 = load_balancer.clone();
+    let monitor = tokio::spawn(async move {
+        let metrics = monitor_task(lb_clone3, Duration::from_secs(15)).await;
+        let peak = metrics.iter().map(|m| m.in_flight).max().unwrap_or(0);
+        println!("📈 监控共采集 {} 条指标，处理中请求数峰值 {}", metrics.len(), peak);
+    });
+    
+    // 在总期限内等待所有组件完成；期间被中断（按下 Ctrl+C）则提前停止
+    let components = vec![("生成器", generator), ("收集器", collector), ("监控", monitor)];
+    let abort_handles: Vec<_> = components.iter().map(|(_, handle)| handle.abort_handle()).collect();
+    let outcome = tokio::select! {
+        outcome = supervise(&load_balancer, components, Duration::from_secs(30)) => outcome,
+        () = interrupt => {
+            println!("\n🛑 收到 Ctrl+C，通知工作者处理完手上的请求后退出");
+            // 停止期间收集器仍在运行，工作者发出的最后一批响应有人读取
+            let dropped = load_balancer.stop().await;
+            println!("🗑️  停止时队列中还有 {} 个请求，已被丢弃", dropped);
+            for handle in abort_handles {
+                handle.abort();
+            }
+            ServerOutcome::Interrupted { dropped }
+        }
+    };
+    
+    let snapshot = load_balancer.stats();
+    println!("\n📸 统计快照: {:?}", snapshot);
+    if snapshot.total != num_requests {
+        println!("⚠️  处理的请求数 ({}) 与提交数 ({}) 不一致", snapshot.total, num_requests);
+    }
+    
+    println!("\n{}", "=".repeat(50));
+    println!("{}", "=".repeat(50));
+    hooks.run_all().await;
+    println!("{}", "=".repeat(50));
+    
+    println!("\n🎉 服务器模拟完成！({:?})", outcome);
+    ServerReport {
+        total: snapshot.total,
+        success: snapshot.success,
+        failed: snapshot.failed,
+        p50_latency: stats.p50_latency(),
+        duration: started.elapsed(),
+        outcome,
+    }
+}
+
+/// 关闭原因，随关闭信号一起广播，让任务可以记录原因并选择不同的清理方式
+#[derive(Debug, Clone, PartialEq)]
+enum ShutdownReason {
+    UserRequested,
+    Timeout,
+    FatalError(String),
+}
+
+/// 任务退出时的记录：看到的关闭原因，以及是否执行了刷盘
+#[derive(Debug)]
+struct TaskExit {
+    reason: ShutdownReason,
+    flushed: bool,
+}
+
+/// 启动 3 个长时间运行的任务，after 之后以 reason 广播关闭信号，返回每个任务的退出记录
+async fn run_until_shutdown(reason: ShutdownReason, after: Duration) -> Vec<TaskExit> {
+    let (shutdown_tx, _) = broadcast::channel::<ShutdownReason>(1);
+    
+    // 模拟一些长时间运行的任务
+    let mut tasks = vec![];
+    
+    for i in 1..=3 {
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        let task = tokio::spawn(async move {
+            let reason = loop {
+                tokio::select! {
+                    reason = shutdown_rx.recv() => {
+                        // 发送端提前消失也按致命错误处理
+                        let reason = reason.unwrap_or_else(|e| ShutdownReason::FatalError(e.to_string()));
+                        println!("   🛑 任务 {} 收到关闭信号: {:?}", i, reason);
+                        break reason;
+                    }
+                    _ = sleep(Duration::from_millis(500)) => {
+                        println!("   🔄 任务 {} 运行中...", i);
+                    }
+                }
+            };
+            
+            // 致命错误时状态可能已经损坏，跳过刷盘，避免把坏数据写出去
+            let flushed = !matches!(reason, ShutdownReason::FatalError(_));
+            if flushed {
+                println!("   💾 任务 {} 刷新缓冲数据", i);
+            } else {
+                println!("   ⏭️  任务 {} 跳过刷盘", i);
+            }
+            println!("   ✅ 任务 {} 清理完成", i);
+            TaskExit { reason, flushed }
+        });
+        tasks.push(task);
+    }
+    
+    // 模拟接收关闭信号
+    sleep(after).await;
+    println!("\n📢 发送关闭信号: {:?}\n", reason);
+    let _ = shutdown_tx.send(reason);
+    
+    // 等待所有任务完成
+    let mut exits = Vec::new();
+    for task in tasks {
+        if let Ok(exit) = task.await {
+            exits.push(exit);
+        }
+    }
+    exits
+}
+
+/// 演示优雅关闭
+async fn graceful_shutdown_demo() {
+    println!("\n\n🛑 优雅关闭演示");
+    println!("📝 按 Ctrl+C 不会立即终止，而是等待任务完成\n");
+    
+    let exits = run_until_shutdown(ShutdownReason::UserRequested, Duration::from_secs(2)).await;
+    println!("\n✅ 所有任务已优雅关闭（刷盘 {}/{}）", exits.iter().filter(|e| e.flushed).count(), exits.len());
+    
+    println!("\n📝 关闭原因决定清理方式：超时照常刷盘，致命错误跳过刷盘\n");
+    let cases = [
+        (ShutdownReason::Timeout, true),
+        (ShutdownReason::FatalError("磁盘写满".to_string()), false),
+    ];
+    for (reason, expect_flush) in cases {
+        let exits = run_until_shutdown(reason.clone(), Duration::from_millis(700)).await;
+        let as_expected = exits.iter().all(|e| e.reason == reason && e.flushed == expect_flush);
+        println!("\n{} {} 个任务都看到了 {:?}，{}",
+                 if as_expected { "✅" } else { "❌" }, exits.len(), reason,
+                 if expect_flush { "并完成刷盘" } else { "并跳过了刷盘" });
+    }
+}
+
+// === 分阶段有序关闭 ===
+
+/// 有序关闭的各个阶段，按顺序推进
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ShutdownPhase {
+    Running,
+    StopIntake,
+    Draining,
+    StopWorkers,
+    StopCollectors,
+}
+
+/// 分阶段关闭的结果，用来检查每个阶段是否真的等到了上一阶段完成
+#[derive(Debug)]
+struct OrderedShutdownReport {
+    // 进入排空阶段时已提交的请求数
+    submitted_at_drain: u64,
+    // 全部结束时已提交的请求数
+    submitted: u64,
+    // 各工作者退出时看到的队列剩余数之和
+    left_on_exit: u64,
+    collected: u64,
+}
+
+/// 运行 run_for 之后分阶段关闭：生成器 → 队列排空 → 工作者 → 收集器，每一阶段都等上一阶段完成
+async fn run_ordered_shutdown(run_for: Duration) -> OrderedShutdownReport {
+    use tokio::sync::watch;
+    
+    let (phase_tx, phase_rx) = watch::channel(ShutdownPhase::Running);
+    let (request_tx, request_rx) = mpsc::channel::<Request>(32);
+    let (response_tx, mut response_rx) = mpsc::channel::<Response>(32);
+    let request_rx = Arc::new(tokio::sync::Mutex::new(request_rx));
+    // 已入队但尚未被工作者取走的请求数
+    let queued = Arc::new(AtomicU64::new(0));
+    let submitted = Arc::new(AtomicU64::new(0));
+    
+    // 生成器：收到 StopIntake 就停止提交，并释放发送端
+    let generator = {
+        let mut phase_rx = phase_rx.clone();
+        let (queued, submitted) = (queued.clone(), submitted.clone());
+        tokio::spawn(async move {
+            let mut id = 0;
+            loop {
+                tokio::select! {
+                    // Ref 不是 Send，需要在分支内部丢弃
+                    _ = async { phase_rx.wait_for(|p| *p >= ShutdownPhase::StopIntake).await.is_ok() } => break,
+                    _ = sleep(Duration::from_millis(20)) => {
+                        id += 1;
+                        let request = Request::new(2000 + id, "/api/ordered", Duration::from_millis(60));
+                        queued.fetch_add(1, Ordering::SeqCst);
+                        submitted.fetch_add(1, Ordering::SeqCst);
+                        if request_tx.send(request).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            println!("   🛑 生成器停止（共提交 {} 个请求）", id);
+        })
+    };
+    
+    // 工作者：队列空了也要等到 StopWorkers 阶段才退出
+    let mut workers = Vec::new();
+    for worker_id in 1..=3 {
+        let mut phase_rx = phase_rx.clone();
+        let (request_rx, response_tx, queued) = (request_rx.clone(), response_tx.clone(), queued.clone());
+        workers.push(tokio::spawn(async move {
+            let mut handled = 0;
+            loop {
+                let request = request_rx.lock().await.recv().await;
+                let Some(request) = request else { break };
+                queued.fetch_sub(1, Ordering::SeqCst);
+                sleep(request.processing_time).await;
+                handled += 1;
+                let _ = response_tx
+                    .send(Response { request_id: request.id, status: 200, body: String::new() })
+                    .await;
+            }
+            let _ = phase_rx.wait_for(|p| *p >= ShutdownPhase::StopWorkers).await;
+            let left = queued.load(Ordering::SeqCst);
+            println!("   ✅ 工作者 {} 退出（处理 {} 个，队列剩余 {}）", worker_id, handled, left);
+            left
+        }));
+    }
+    drop(response_tx);
+    
+    // 收集器：直到 StopCollectors 阶段且所有响应收完才退出
+    let collector = {
+        let mut phase_rx = phase_rx.clone();
+        tokio::spawn(async move {
+            let mut collected = 0;
+            while response_rx.recv().await.is_some() {
+                collected += 1;
+            }
+            let _ = phase_rx.wait_for(|p| *p >= ShutdownPhase::StopCollectors).await;
+            println!("   📥 收集器退出（共收到 {} 个响应）", collected);
+            collected
+        })
+    };
+    
+    sleep(run_for).await;
+    
+    println!("\n📢 阶段 1：停止接收新请求");
+    let _ = phase_tx.send(ShutdownPhase::StopIntake);
+    let _ = generator.await;
+    
+    println!("📢 阶段 2：排空队列");
+    let _ = phase_tx.send(ShutdownPhase::Draining);
+    let submitted_at_drain = submitted.load(Ordering::SeqCst);
+    while queued.load(Ordering::SeqCst) > 0 {
+        sleep(Duration::from_millis(10)).await;
+    }
+    
+    println!("📢 阶段 3：停止工作者");
+    let _ = phase_tx.send(ShutdownPhase::StopWorkers);
+    let mut left_on_exit = 0;
+    for worker in workers {
+        left_on_exit += worker.await.unwrap_or(0);
+    }
+    
+    println!("📢 阶段 4：停止收集器");
+    let _ = phase_tx.send(ShutdownPhase::StopCollectors);
+    let collected = collector.await.unwrap_or(0);
+    
+    OrderedShutdownReport {
+        submitted_at_drain,
+        submitted: submitted.load(Ordering::SeqCst),
+        left_on_exit,
+        collected,
+    }
+}
+
+/// 演示分阶段关闭
+async fn ordered_shutdown_demo() {
+    println!("\n\n🪜 分阶段有序关闭演示");
+    println!("📝 先停生成器，再排空队列，然后停工作者，最后停收集器\n");
+    
+    let OrderedShutdownReport { submitted_at_drain, submitted, left_on_exit, collected } =
+        run_ordered_shutdown(Duration::from_millis(300)).await;
+    println!("\n📊 检查：");
+    println!("   {} 排空开始后没有新请求（{} → {}）",
+             if submitted == submitted_at_drain { "✅" } else { "❌" }, submitted_at_drain, submitted);
+    println!("   {} 工作者退出时队列为空（剩余合计 {}）",
+             if left_on_exit == 0 { "✅" } else { "❌" }, left_on_exit);
+    println!("   {} 所有请求都有响应（{} / {}）",
+             if collected == submitted { "✅" } else { "❌" }, collected, submitted);
+}
+
+/// 演示弹性调用：先失败后成功的请求被重试救回，连续失败后熔断器直接拒绝
+async fn resilient_call_demo() {
+    println!("\n\n🛡️  弹性调用演示（重试 + 退避 + 熔断）");
+    println!("📝 id 为 7 的倍数的请求会失败\n");
+    
+    let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+    let request_to = |path: &str, id| Request::new(id, path, Duration::from_millis(20));
+    let request = |id| request_to("/api/resilient", id);
+    
+    let policy = ResiliencePolicy {
+        max_attempts: 3,
+        base_backoff: Duration::from_millis(50),
+        breakers: Arc::new(BreakerRegistry::new(2, Duration::from_secs(5))),
+    };
+    let result = resilient_call(&lb, request(7), &policy).await;
+    let succeeded = matches!(result.outcome, CallOutcome::Success(_));
+    println!("   🔁 请求 7: 共尝试 {} 次，{}", result.attempts, if succeeded { "最终成功" } else { "最终失败" });
+    // 第一次失败后重试（重试换用下一个 id），第二次成功
+    if result.attempts == 2 && succeeded {
+        println!("   ✅ 失败一次后重试成功");
+    } else {
+        println!("   ❌ 预期尝试 2 次并最终成功");
+    }
+    
+    // 不重试的策略：两次失败就会触发熔断
+    let strict = ResiliencePolicy {
+        max_attempts: 1,
+        base_backoff: Duration::from_millis(50),
+        breakers: Arc::new(BreakerRegistry::new(2, Duration::from_secs(5))),
+    };
+    let mut outcomes = Vec::new();
+    for id in [14, 21, 22] {
+        let result = resilient_call(&lb, request(id), &strict).await;
+        let rejected = matches!(result.outcome, CallOutcome::Rejected);
+        let outcome = match result.outcome {
+            CallOutcome::Success(r) => format!("成功 ({})", r.status),
+            CallOutcome::Failed(status) => format!("失败 ({})", status),
+            CallOutcome::Rejected => "被熔断拒绝".to_string(),
+        };
+        println!("   ⚡ 请求 {}: 尝试 {} 次，{}，熔断器打开: {}",
+                 id, result.attempts, outcome, result.breaker_open);
+        outcomes.push((rejected, result.breaker_open));
+    }
+    // 14、21 失败后熔断器打开，22 不再发出请求而是直接被拒绝
+    if outcomes == [(false, false), (false, true), (true, true)] {
+        println!("   ✅ 两次失败后熔断，第三个请求被直接拒绝");
+    } else {
+        println!("   ❌ 熔断时机与预期不符: {:?}", outcomes);
+    }
+    
+    // 每个 path 一个熔断器：/a 连续失败被熔断，/b 不受影响
+    println!("\n   🔀 按端点独立熔断：");
+    let per_path = ResiliencePolicy {
+        max_attempts: 1,
+        base_backoff: Duration::from_millis(50),
+        breakers: Arc::new(BreakerRegistry::new(2, Duration::from_secs(5))),
+    };
+    let mut a_rejected = false;
+    let mut b_all_ok = true;
+    for (path, id) in [("/a", 28), ("/b", 29), ("/a", 35), ("/b", 36), ("/a", 37), ("/b", 38)] {
+        let result = resilient_call(&lb, request_to(path, id), &per_path).await;
+        let outcome = match result.outcome {
+            CallOutcome::Success(_) => "✅ 成功",
+            CallOutcome::Failed(_) => "❌ 失败",
+            CallOutcome::Rejected => "⛔ 被熔断拒绝",
+        };
+        println!("   {} 请求 {}: {}", path, id, outcome);
+        match path {
+            "/a" if id == 37 => a_rejected = matches!(result.outcome, CallOutcome::Rejected),
+            "/b" => b_all_ok &= matches!(result.outcome, CallOutcome::Success(_)),
+            _ => {}
+        }
+    }
+    if a_rejected && b_all_ok {
+        println!("   ✅ /a 被熔断，/b 的请求全部成功");
+    } else {
+        println!("   ❌ 熔断器没有按端点隔离");
+    }
+}
+
+/// 演示提交重试：队列只能放 1 个请求，满了之后按指数退避重试
+async fn submit_retry_demo() {
+    println!("\n\n🔁 提交重试演示");
+    println!("📝 1 个工作者、队列容量 1，每个请求处理 250ms\n");
+    
+    let config = ServerConfig {
+        max_concurrent: 1,
+        num_workers: 1,
+        queue_cap: 1,
+        ..ServerConfig::default()
+    };
+    let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+    let request = |id| Request::new(id, "/api/queued", Duration::from_millis(250));
+    
+    // 第 1 个被工作者取走，第 2 个占满队列
+    lb.submit_request(request(6001)).await.unwrap();
+    sleep(Duration::from_millis(20)).await;
+    lb.submit_request(request(6002)).await.unwrap();
+    
+    match lb.submit_request_retry(request(6003), 0).await {
+        Ok(()) => println!("   ✅ 请求 #6003 不重试就提交成功"),
+        Err(e) => println!("   ❌ 请求 #6003 不重试: {}", e),
+    }
+    match lb.submit_request_retry(request(6003), 3).await {
+        Ok(()) => println!("   ✅ 请求 #6003 重试后提交成功"),
+        Err(e) => println!("   ❌ 请求 #6003 重试后仍失败: {}", e),
+    }
+    for _ in 0..3 {
+        lb.get_response().await;
+    }
+}
+
+/// 演示背压感知的提交：生成器遇到 Busy 就退避，而不是阻塞在满队列上
+async fn try_submit_demo() {
+    println!("\n\n🚦 背压感知提交演示");
+    println!("📝 1 个工作者、队列容量 2，每个请求处理 100ms；生成器连续提交 8 个请求\n");
+    
+    let lb = LoadBalancerBuilder::new()
+        .workers(1)
+        .max_concurrent(1)
+        .queue_capacity(2)
+        .build(Arc::new(ServerStats::new()))
+        .expect("参数均大于 0");
+    
+    let mut busy = 0;
+    let mut submitted = 0;
+    for id in 7001..=7008 {
+        let request = Request::new(id, "/api/backpressure", Duration::from_millis(100));
+        let mut backoff = Duration::from_millis(25);
+        loop {
+            match lb.try_submit(request.clone()) {
+                Ok(()) => {
+                    submitted += 1;
+                    break;
+                }
+                Err(SubmitError::Busy) => {
+                    // 不占着队列等待：让出时间给工作者消化积压，期间可以做别的事或直接丢弃
+                    busy += 1;
+                    println!("   ⏳ 请求 #{} 遇到 {}，{}ms 后重试", id, SubmitError::Busy, backoff.as_millis());
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_millis(200));
+                }
+                Err(e @ SubmitError::Closed) => {
+                    println!("   ❌ 请求 #{} 提交失败: {}", id, e);
+                    return;
+                }
+            }
+        }
+    }
+    for _ in 0..submitted {
+        lb.get_response().await;
+    }
+    
+    if submitted == 8 && busy > 0 {
+        println!("   ✅ 8 个请求全部提交，期间 {} 次遇到 Busy 并退避", busy);
+    } else {
+        println!("   ❌ 提交 {} 个，遇到 Busy {} 次", submitted, busy);
+    }
+    
+    lb.begin_drain();
+    let late = Request::new(7009, "/api/backpressure", Duration::from_millis(100));
+    match lb.try_submit(late) {
+        Err(SubmitError::Closed) => println!("   ✅ 排空后提交返回 Closed，调用者不必再重试"),
+        other => println!("   ❌ 排空后预期 Closed，实际 {:?}", other),
+    }
+    lb.shutdown().await;
+}
+
+/// 演示立即停止：run_server 收到 Ctrl+C 时走的就是这条路径
+async fn stop_demo() {
+    println!("\n\n🛑 立即停止演示");
+    println!("📝 1 个工作者，提交 6 个 200ms 的请求；300ms 后发出停止信号\n");
+    
+    let lb = LoadBalancerBuilder::new()
+        .workers(1)
+        .max_concurrent(1)
+        .build(Arc::new(ServerStats::new()))
+        .expect("参数均大于 0");
+    for id in 7101..=7106 {
+        let request = Request::new(id, "/api/stop", Duration::from_millis(200));
+        lb.submit_request(request).await.unwrap();
+    }
+    
+    sleep(Duration::from_millis(300)).await;
+    let dropped = lb.stop().await;
+    let handled = lb.stats().total as usize;
+    
+    println!("   📊 已处理 {} 个，丢弃 {} 个，剩余工作者 {} 个", handled, dropped, lb.worker_count());
+    if handled + dropped == 6 && dropped > 0 && lb.worker_count() == 0 {
+        println!("   ✅ 正在处理的请求完成后工作者才退出，其余请求留在队列中被丢弃");
+    } else {
+        println!("   ❌ 处理数与丢弃数之和应为 6，且工作者应全部退出");
+    }
+}
+
+/// 演示优先级通道：低优先级请求先大量排队，之后到达的高优先级请求仍然先被处理
+async fn priority_demo() {
+    println!("\n\n🚨 请求优先级演示");
+    println!("📝 1 个工作者；先提交 1 个占住工作者的请求，再交替提交低/高优先级请求各 4 个\n");
+    
+    let config = ServerConfig { max_concurrent: 1, num_workers: 1, ..ServerConfig::default() };
+    let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+    let request = |id, priority| {
+        let path = if priority > 0 { "/api/urgent" } else { "/api/endpoint0" };
+        Request::new(id, path, Duration::from_millis(30)).with_priority(priority)
+    };
+    
+    lb.submit_request(Request::new(9000, "/api/endpoint0", Duration::from_millis(100))).await.unwrap();
+    sleep(Duration::from_millis(20)).await;
+    for i in 1..=4 {
+        lb.submit_prioritized(request(9000 + i, 0)).await.unwrap();
+        lb.submit_prioritized(request(9100 + i, 1)).await.unwrap();
+    }
+    
+    let mut order = Vec::new();
+    for _ in 0..9 {
+        if let Some(response) = lb.get_response().await {
+            order.push(response.request_id);
+        }
+    }
+    println!("\n   📋 处理顺序: {:?}", order);
+    let urgent_first = order.get(1..5).is_some_and(|s| s.iter().all(|id| *id > 9100));
+    println!("   {} 排队期间到达的高优先级请求全部排在低优先级请求之前", if urgent_first { "✅" } else { "❌" });
+}
+
+/// 演示动态扩缩容：扩容立即生效，缩容等工作者处理完手上的请求再退出
+async fn scaling_demo() {
+    println!("\n\n📐 工作者池动态扩缩容演示");
+    println!("📝 2 个工作者起步，提交 10 个 200ms 的请求后扩到 5 个，再缩到 1 个\n");
     
-    // 创建服务器组件
     let stats = Arc::new(ServerStats::new());
-    let load_balancer = Arc::new(LoadBalancer::new(3, stats.clone()));
+    let config = ServerConfig { max_concurrent: 8, num_workers: 2, ..ServerConfig::default() };
+    let lb = LoadBalancer::with_config(&config, stats.clone());
+    for id in 1..=10 {
+        let request = Request::new(8000 + id, "/api/elastic", Duration::from_millis(200));
+        lb.submit_request(request).await.unwrap();
+    }
     
-    println!("⚙️  服务器配置:");
-    println!("   • 最大并发: 3");
-    println!("   • 工作者数量: 4");
-    println!("   • 请求队列大小: 100\n");
+    lb.scale_to(5);
+    println!("   👷 扩容后工作者数量: {}", lb.worker_count());
+    sleep(Duration::from_millis(100)).await;
     
-    let num_requests = 20;
+    lb.scale_to(1);
+    println!("   👷 刚发出缩容时: {}（忙碌的工作者先处理完当前请求）", lb.worker_count());
+    for _ in 0..10 {
+        lb.get_response().await;
+    }
+    sleep(Duration::from_millis(50)).await;
+    println!("   👷 所有请求完成后: {}", lb.worker_count());
     
-    // 启动各个组件
-    let lb_clone1 = load_balancer.clone();
-    let generator = tokio::spawn(async move {
-        request_generator(lb_clone1, num_requests).await;
+    let total = stats.snapshot().total;
+    println!("   {} 缩容过程中没有请求丢失（处理 {} / 10 个）", if total == 10 { "✅" } else { "❌" }, total);
+}
+
+/// 演示关闭负载均衡器：shutdown 返回时所有已提交的请求都已处理完
+async fn lb_shutdown_demo() {
+    println!("\n\n🔚 LoadBalancer::shutdown 演示");
+    println!("📝 提交 10 个请求后立即关闭，shutdown 等待它们全部处理完\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let lb = LoadBalancer::new(3, stats.clone());
+    for id in 1..=10 {
+        let request = Request::new(7000 + id, "/api/flush", Duration::from_millis(50));
+        lb.submit_request(request).await.unwrap();
+    }
+    
+    lb.shutdown().await;
+    let total = stats.snapshot().total;
+    println!("   {} shutdown 返回时已处理 {} 个请求（提交 10 个）", if total == 10 { "✅" } else { "❌" }, total);
+}
+
+/// 自定义处理器：统计一段文本的单词数
+struct WordCounter;
+
+#[async_trait]
+impl Handler for WordCounter {
+    type Req = String;
+    type Resp = (String, usize);
+    
+    async fn handle(&self, text: String) -> (String, usize) {
+        sleep(Duration::from_millis(30)).await;
+        let words = text.split_whitespace().count();
+        (text, words)
+    }
+}
+
+/// 演示复用工作者池处理自定义消息类型
+async fn custom_handler_demo() {
+    println!("\n\n🧩 自定义 Handler 演示");
+    println!("📝 同一套工作者池处理 String -> (String, usize) 的单词计数\n");
+    
+    let config = ServerConfig { num_workers: 2, ..ServerConfig::default() };
+    let lb = LoadBalancer::with_handler(&config, Arc::new(ServerStats::new()), Arc::new(|_| WordCounter));
+    for text in ["hello async world", "tokio", "one two three four"] {
+        match lb.call(text.to_string()).await {
+            Ok((text, words)) => println!("   📝 {:?}: {} 个单词", text, words),
+            Err(e) => println!("   ❌ {}", e),
+        }
+    }
+}
+
+/// 演示请求/响应调用：并发的 call 各自拿到自己的响应，即使完成顺序与提交顺序相反
+async fn request_reply_demo() {
+    println!("\n\n📮 请求/响应调用演示");
+    println!("📝 5 个并发 call，越早提交的处理越久\n");
+    
+    let lb = Arc::new(LoadBalancer::new(5, Arc::new(ServerStats::new())));
+    let calls: Vec<_> = (1..=5u64)
+        .map(|id| {
+            let lb = lb.clone();
+            tokio::spawn(async move {
+                let request = Request::new(100 + id, format!("/api/call{}", id), Duration::from_millis(300 - id * 50));
+                (request.id, lb.call(request).await)
+            })
+        })
+        .collect();
+    
+    for call in calls {
+        match call.await.unwrap() {
+            (id, Ok(response)) => println!("   {} 请求 #{} 收到响应 #{}: {}",
+                                           if response.request_id == id { "✅" } else { "❌" },
+                                           id, response.request_id, response.body),
+            (id, Err(e)) => println!("   ❌ 请求 #{} 失败: {}", id, e),
+        }
+    }
+}
+
+/// 演示对冲请求：慢的首发请求被快的对冲请求抢先，首发结果被丢弃
+async fn hedging_demo() {
+    println!("\n\n⏩ 对冲请求演示");
+    println!("📝 首发请求需要 500ms，100ms 后发出只需 50ms 的对冲请求\n");
+    
+    let lb = LoadBalancer::new(4, Arc::new(ServerStats::new()));
+    let request = |ms| Request::new(8002, "/api/hedged", Duration::from_millis(ms));
+    
+    match lb.call_hedged(request(500), request(50), Duration::from_millis(100)).await {
+        Ok((response, hedged)) => println!("   🏁 得到请求 #{} 的响应（状态 {}），来自{}",
+                                           response.request_id, response.status,
+                                           if hedged { "对冲请求" } else { "首发请求" }),
+        Err(e) => println!("   ❌ {}", e),
+    }
+    
+    // 首发请求随即被中止，不会再占用工作者到 500ms
+    sleep(Duration::from_millis(50)).await;
+    println!("   📊 成功完成的请求数: {}（落败的首发请求没有完成）", lb.stats().success);
+}
+
+/// 演示从环境变量解析配置（用 HashMap 模拟环境，不影响真实进程环境）
+async fn config_demo() {
+    println!("\n\n⚙️  环境变量配置演示");
+    
+    let env = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    };
+    let cases = [
+        ("未设置", env(&[]), Ok(ServerConfig::default())),
+        (
+            "自定义",
+            env(&[("MAX_CONCURRENT", "8"), ("NUM_WORKERS", "6"), ("QUEUE_CAP", "256")]),
+            Ok(ServerConfig { max_concurrent: 8, num_workers: 6, queue_cap: 256, ..ServerConfig::default() }),
+        ),
+        ("格式错误", env(&[("QUEUE_CAP", "lots")]), Err(ConfigError::Invalid { var: "QUEUE_CAP", value: "lots".to_string() })),
+        ("值为 0", env(&[("NUM_WORKERS", "0")]), Err(ConfigError::Zero { var: "NUM_WORKERS" })),
+    ];
+    
+    for (label, vars, expected) in cases {
+        let result = ServerConfig::from_lookup(|var| vars.get(var).cloned());
+        let verdict = if result == expected { "✅" } else { "❌" };
+        match result {
+            Ok(config) => println!("   {} {}: {:?}", verdict, label, config),
+            Err(e) => println!("   {} {}: {}", verdict, label, e),
+        }
+    }
+}
+
+/// 演示用构建器调整工作者池参数，非法参数返回错误而不是构建出一个卡死的池
+async fn builder_demo() {
+    println!("\n\n🏗️  LoadBalancerBuilder 演示");
+    
+    match LoadBalancerBuilder::new().workers(2).queue_capacity(10).max_concurrent(2).build(Arc::new(ServerStats::new())) {
+        Ok(lb) => {
+            let workers = lb.worker_count();
+            println!("   {} workers(2) + queue_capacity(10) + max_concurrent(2): 启动了 {} 个工作者，并发上限 {}",
+                     if workers == 2 { "✅" } else { "❌" }, workers, lb.available_slots());
+            lb.shutdown().await;
+        }
+        Err(e) => println!("   ❌ 意外的错误: {}", e),
+    }
+    
+    let bad = [
+        ("workers(0)", LoadBalancerBuilder::new().workers(0)),
+        ("max_concurrent(0)", LoadBalancerBuilder::new().max_concurrent(0)),
+    ];
+    for (label, builder) in bad {
+        match builder.build(Arc::new(ServerStats::new())) {
+            Ok(_) => println!("   ❌ {}: 不应构建成功", label),
+            Err(e) => println!("   ✅ {}: 被拒绝 ({})", label, e),
+        }
+    }
+}
+
+/// 演示延迟过载保护：慢请求把 p99 推高后开始丢弃，延迟恢复后停止丢弃
+async fn load_shedding_demo() {
+    println!("\n\n🚦 延迟过载保护演示");
+    println!("📝 阈值 100ms，滚动窗口 5 个请求：先来 12 个 300ms 的慢请求，再来 30 个 20ms 的快请求\n");
+    
+    let shedder = Arc::new(LoadShedder::new(5, Duration::from_millis(100)));
+    let lb = LoadBalancer::new_shedding(4, shedder.clone(), Arc::new(ServerStats::new()));
+    
+    let phases = [("慢请求阶段", 12, 300), ("快请求阶段 1", 10, 20), ("快请求阶段 2", 10, 20), ("快请求阶段 3", 10, 20)];
+    let mut next_id = 6000;
+    for (label, count, ms) in phases {
+        for _ in 0..count {
+            next_id += 1;
+            let request = Request::new(next_id, "/api/shed", Duration::from_millis(ms));
+            lb.submit_request(request).await.unwrap();
+        }
+        
+        let mut shed = 0;
+        for _ in 0..count {
+            if lb.get_response().await.is_some_and(|r| r.status == 503) {
+                shed += 1;
+            }
+        }
+        println!("   📊 {}: {} 个请求中 {} 个被丢弃（当前丢弃比例 {:.0}%）",
+                 label, count, shed, shedder.shed_fraction() * 100.0);
+    }
+}
+
+/// 演示有界重排缓冲：#3 的响应永远不会到达，收集器记录缺口后继续按序交付
+async fn reorder_buffer_demo() {
+    println!("\n\n🔀 有界重排缓冲演示");
+    println!("📝 响应乱序到达，且 #3 丢失；分别用等待超时和缓冲上限跳过缺口\n");
+    
+    let response = |id| Response { request_id: id, status: 200, body: String::new() };
+    let cases = [
+        ("等待超时 100ms", ReorderBuffer::new(1, 16, Duration::from_millis(100))),
+        ("缓冲上限 2 个", ReorderBuffer::new(1, 2, Duration::from_secs(60))),
+    ];
+    for (label, buffer) in cases {
+        println!("   ▶ {}", label);
+        let (tx, rx) = mpsc::channel(16);
+        let collector = tokio::spawn(collect_ordered(rx, buffer));
+        for id in [2, 1, 5, 4, 6] {
+            tx.send(response(id)).await.unwrap();
+        }
+        sleep(Duration::from_millis(200)).await;
+        for id in [8, 7] {
+            tx.send(response(id)).await.unwrap();
+        }
+        drop(tx);
+        
+        let (delivered, gaps) = collector.await.unwrap();
+        let in_order = delivered.windows(2).all(|w| w[0] < w[1]);
+        println!("   {} 交付 {:?}，缺口 {:?}\n",
+                 if in_order && gaps == [3] { "✅" } else { "❌" }, delivered, gaps);
+    }
+}
+
+/// 演示工作者预热：wait_ready 在所有工作者初始化完成后才返回
+async fn warmup_demo() {
+    println!("\n\n🔥 工作者预热演示");
+    println!("📝 每个工作者需要 300ms 初始化，完成前不接收请求\n");
+    
+    let config = ServerConfig {
+        max_concurrent: 2,
+        warmup: Duration::from_millis(300),
+        ..ServerConfig::default()
+    };
+    let start = tokio::time::Instant::now();
+    let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+    lb.wait_ready().await;
+    let elapsed = start.elapsed();
+    if elapsed >= config.warmup {
+        println!("   ✅ 所有工作者就绪，用时 {}ms（不早于预热时间 {}ms）",
+                 elapsed.as_millis(), config.warmup.as_millis());
+    } else {
+        println!("   ❌ wait_ready 在 {}ms 就返回了，早于预热时间 {}ms",
+                 elapsed.as_millis(), config.warmup.as_millis());
+    }
+}
+
+/// seqlock 压力测试：writers 个写线程各记录 per_writer 个请求，2 个读线程持续读取快照
+///
+/// 这里用的是真正的 OS 线程，所以写成同步函数，不在异步任务里阻塞执行器。
+/// 返回 (最终快照, 读取次数, 不一致的快照数)。
+fn seqlock_stress(writers: u64, per_writer: u64) -> (StatsSnapshot, u64, u64) {
+    let stats = Stats::new();
+    let writers_done = AtomicBool::new(false);
+    
+    let (reads, torn) = std::thread::scope(|scope| {
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                scope.spawn(|| {
+                    let (mut reads, mut torn) = (0u64, 0u64);
+                    // 先读再检查，保证写线程结束得再快也至少读过一次
+                    loop {
+                        let snap = stats.snapshot();
+                        reads += 1;
+                        if snap.success + snap.failed > snap.total {
+                            torn += 1;
+                        }
+                        if writers_done.load(Ordering::Acquire) {
+                            break;
+                        }
+                    }
+                    (reads, torn)
+                })
+            })
+            .collect();
+        
+        let handles: Vec<_> = (0..writers)
+            .map(|w| {
+                let stats = &stats;
+                scope.spawn(move || {
+                    for i in 0..per_writer {
+                        stats.record_request();
+                        stats.record_outcome((i + w) % 7 != 0);
+                    }
+                })
+            })
+            .collect();
+        for writer in handles {
+            writer.join().unwrap();
+        }
+        writers_done.store(true, Ordering::Release);
+        
+        readers
+            .into_iter()
+            .map(|r| r.join().unwrap())
+            .fold((0, 0), |acc, (reads, torn)| (acc.0 + reads, acc.1 + torn))
     });
     
-    let lb_clone2 = load_balancer.clone();
-    let collector = tokio::spawn(async move {
-        response_collector(lb_clone2, num_requests).await;
+    (stats.snapshot(), reads, torn)
+}
+
+/// 演示 seqlock：多个线程并发写、并发读，每个读到的快照都自洽
+fn seqlock_stats_demo() {
+    println!("\n\n🔒 Seqlock 计数器压力测试");
+    println!("📝 4 个写线程各记录 50000 个请求，2 个读线程持续读取快照\n");
+    
+    let (last, reads, torn) = seqlock_stress(4, 50_000);
+    println!("   📸 最终快照: {:?}", last);
+    println!("   👀 读取 {} 次快照，不一致的快照: {} 个", reads, torn);
+    if torn == 0 && last.total == 200_000 && last.success + last.failed == last.total {
+        println!("   ✅ 所有快照都满足 success + failed <= total，最终计数准确");
+    } else {
+        println!("   ❌ 出现不一致的快照或最终计数错误");
+    }
+}
+
+/// 演示结构化监控指标：monitor_task 返回 MetricsSnapshot 列表，可以直接做程序化检查
+async fn metrics_snapshot_demo() {
+    println!("\n\n📈 结构化监控指标演示");
+    println!("📝 并发上限 2，提交 6 个 100ms 的请求，监控任务运行 1 秒\n");
+    
+    let lb = Arc::new(LoadBalancer::new(2, Arc::new(ServerStats::new())));
+    let monitor = tokio::spawn(monitor_task(lb.clone(), Duration::from_secs(1)));
+    
+    for id in 7201..=7206 {
+        let request = Request::new(id, "/api/metrics", Duration::from_millis(100));
+        lb.submit_request(request).await.unwrap();
+    }
+    for _ in 0..6 {
+        lb.get_response().await;
+    }
+    
+    let metrics = monitor.await.unwrap();
+    let peak = metrics.iter().map(|m| m.in_flight).max().unwrap_or(0);
+    let ordered = metrics.windows(2).all(|w| w[0].timestamp <= w[1].timestamp && w[0].total <= w[1].total);
+    println!("   📋 采集 {} 条指标，处理中请求数峰值 {}，结束后 gauge 为 {}",
+             metrics.len(), peak, lb.stats.in_flight());
+    if peak == 2 && ordered && metrics.last().map(|m| m.total) == Some(6) && lb.stats.in_flight() == 0 {
+        println!("   ✅ 峰值等于并发上限，指标按时间有序，请求全部完成后 gauge 归零");
+    } else {
+        println!("   ❌ 指标不符合预期: {:?}", metrics.last());
+    }
+}
+
+/// 演示统计变化推送：监控方 await watch 变化，只在数值真正变化时被唤醒
+async fn stats_watch_demo() {
+    println!("\n\n📡 统计变化推送演示");
+    println!("📝 提交 4 个请求，监控任务不轮询，每次被唤醒都打印一次快照\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let lb = LoadBalancer::new(2, stats.clone());
+    let mut changes = lb.watch_stats();
+    
+    let monitor = tokio::spawn(async move {
+        let mut seen = Vec::new();
+        while changes.changed().await.is_ok() {
+            let snap = *changes.borrow_and_update();
+            println!("   📊 总数 {} / 成功 {} / 失败 {} / 处理中 {}",
+                     snap.total, snap.success, snap.failed, snap.in_flight);
+            seen.push(snap);
+            if snap.total == 4 && snap.in_flight == 0 {
+                break;
+            }
+        }
+        seen
+    });
+    
+    // id 7 的请求会失败
+    for id in [5, 6, 7, 8] {
+        let request = Request::new(id, "/api/watched", Duration::from_millis(40));
+        lb.submit_request(request).await.unwrap();
+    }
+    
+    let seen = timeout(Duration::from_secs(2), monitor).await.unwrap().unwrap();
+    lb.shutdown().await;
+    
+    let distinct = seen.windows(2).all(|w| w[0] != w[1]);
+    let last = seen.last().copied().unwrap_or(StatsSnapshot::new(0, 0, 0));
+    let ok = distinct && last == StatsSnapshot::new(4, 3, 1);
+    println!("   {} 共收到 {} 次变化通知，相邻快照互不相同，最终 {:?}",
+             if ok { "✅" } else { "❌" }, seen.len(), last);
+}
+
+/// 演示各工作者的负载分布：4 个工作者争抢同一个接收端，谁先拿到锁谁处理
+async fn worker_fairness_demo() {
+    println!("\n\n⚖️  工作者负载分布演示");
+    println!("📝 一次提交 40 个 10ms 的请求，看 4 个工作者各处理了多少\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let lb = LoadBalancer::new(4, stats.clone());
+    for id in 1..=40 {
+        let request = Request::new(8000 + id, "/api/fair", Duration::from_millis(10));
+        lb.submit_request(request).await.unwrap();
+    }
+    lb.shutdown().await;
+    
+    stats.print_per_worker();
+    let handled: u64 = stats.per_worker_counts().values().sum();
+    println!("   {} 各工作者合计处理 {} 个请求（提交 40 个）", if handled == 40 { "✅" } else { "❌" }, handled);
+}
+
+/// 演示按路径统计平均耗时，其中一个路径的请求全部失败
+async fn endpoint_averages_demo() {
+    println!("\n\n🗺️  各路径平均耗时演示");
+    println!("📝 /api/slow 120ms、/api/fast 30ms 各两个请求，/api/broken 的请求全部失败\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let handler = RequestHandler {
+        id: 88,
+        stats: stats.clone(),
+        max_processing_time: Duration::from_secs(5),
+        breakers: Arc::new(BreakerRegistry::new(5, Duration::from_secs(1))),
+    };
+    // id 是 7 的倍数的请求会失败
+    let cases = [(1, "/api/fast", 30), (2, "/api/slow", 120), (3, "/api/fast", 30), (4, "/api/slow", 120), (7, "/api/broken", 10), (14, "/api/broken", 10)];
+    for (id, path, ms) in cases {
+        let request = Request::new(id, path, Duration::from_millis(ms));
+        handler.handle_request(request).await;
+    }
+    
+    stats.print_endpoint_averages();
+    let averages = stats.endpoint_averages();
+    let order: Vec<&str> = averages.iter().map(|(path, _)| path.as_str()).collect();
+    let ok = order == ["/api/slow", "/api/fast", "/api/broken"] && averages[2].1.is_none();
+    println!("   {} 按平均耗时从高到低排列，没有成功请求的路径不参与除法", if ok { "✅" } else { "❌" });
+}
+
+/// 演示 AIMD 自适应并发：延迟低时上限缓慢上升，延迟飙升时迅速减半
+async fn adaptive_limit_demo() {
+    println!("\n\n📈 自适应并发限制（AIMD）演示");
+    println!("📝 延迟阈值 150ms：先来 8 个 50ms 的快请求，再来 6 个 300ms 的慢请求\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let lb = LoadBalancer::new_adaptive(8, 2, 8, Duration::from_millis(150), stats);
+    
+    let fast = (1..=8).map(|i| (i, 50));
+    let slow = (9..=14).map(|i| (i, 300));
+    for (id, ms) in fast.chain(slow) {
+        let request = Request::new(500 + id, "/api/adaptive", Duration::from_millis(ms));
+        lb.submit_request(request).await.unwrap();
+    }
+    
+    let mut limits = Vec::new();
+    for _ in 0..14 {
+        lb.get_response().await;
+        limits.push(lb.concurrency_limit().unwrap_or(0));
+    }
+    println!("\n📊 每个响应后的并发上限: {:?}", limits);
+    let peak = limits.iter().copied().max().unwrap_or(0);
+    let last = limits.last().copied().unwrap_or(0);
+    if peak > 2 && last < peak {
+        println!("   ✅ 快请求阶段上限从 2 升到 {}，慢请求阶段降到 {}", peak, last);
+    } else {
+        println!("   ❌ 预期上限先升过初始值 2 再下降（峰值 {}，最终 {}）", peak, last);
+    }
+}
+
+/// 演示单个请求的处理超时：超过上限返回 504，按时完成的请求照常模拟失败
+async fn processing_timeout_demo() {
+    println!("\n\n⌛ 请求处理超时演示");
+    println!("📝 处理上限 100ms\n");
+    
+    let handler = RequestHandler {
+        id: 55,
+        stats: Arc::new(ServerStats::new()),
+        max_processing_time: Duration::from_millis(100),
+        breakers: Arc::new(BreakerRegistry::new(5, Duration::from_secs(1))),
+    };
+    for (id, ms) in [(5001, 30), (5005, 30), (5002, 300)] {
+        let request = Request::new(id, "/api/bounded", Duration::from_millis(ms));
+        let start = tokio::time::Instant::now();
+        let response = handler.handle_request(request).await;
+        println!("   📨 请求 #{}（需要 {}ms）: 状态 {}，用时 {}ms",
+                 id, ms, response.status, start.elapsed().as_millis());
+    }
+}
+
+/// 演示令牌桶限速：先突发 capacity 个，之后按补充速率提交
+async fn rate_limiter_demo() {
+    println!("\n\n🪣 令牌桶限速演示");
+    println!("📝 capacity = 10，每秒补充 10 个：提交 100 个请求，前 10 个立即发出，其余 90 个约需 9 秒\n");
+    
+    let limiter = RateLimiter::new(10, 10.0);
+    let (tx, mut rx) = mpsc::channel(100);
+    let start = tokio::time::Instant::now();
+    
+    for id in 1..=100u64 {
+        limiter.acquire().await;
+        let request = Request::new(id, "/api/limited", Duration::ZERO);
+        tx.send(request).await.unwrap();
+        if id == 10 || id % 25 == 0 {
+            println!("   📤 第 {:>3} 个请求 @ {:>5}ms", id, start.elapsed().as_millis());
+        }
+    }
+    drop(tx);
+    
+    let mut submitted = 0;
+    while rx.recv().await.is_some() {
+        submitted += 1;
+    }
+    let elapsed = start.elapsed();
+    let ok = submitted == 100 && elapsed >= Duration::from_millis(8900);
+    println!("   {} 提交 {} 个请求用时 {:.2} 秒（应不少于约 9 秒）",
+             if ok { "✅" } else { "❌" }, submitted, elapsed.as_secs_f64());
+}
+
+/// 演示处理器内的熔断：连续失败后快速返回 503，冷却后只放行一个试探请求
+async fn handler_breaker_demo() {
+    println!("\n\n🔌 处理器熔断演示");
+    println!("📝 /api/flaky 连续失败 3 次后熔断 200ms，冷却后同时来两个请求，只有一个被放行试探\n");
+    
+    let stats = Arc::new(ServerStats::new());
+    let handler = Arc::new(RequestHandler {
+        id: 66,
+        stats: stats.clone(),
+        max_processing_time: Duration::from_secs(5),
+        breakers: Arc::new(BreakerRegistry::new(3, Duration::from_millis(200)).observed_by(stats.clone())),
+    });
+    let request = |id: u64| Request::new(id, "/api/flaky", Duration::from_millis(50));
+    
+    // id 是 7 的倍数的请求会失败
+    for id in [7, 14, 21] {
+        handler.handle_request(request(id)).await;
+    }
+    
+    let start = tokio::time::Instant::now();
+    let rejected = handler.handle_request(request(22)).await;
+    println!("   📨 熔断期间的请求 #22: 状态 {}，用时 {}ms", rejected.status, start.elapsed().as_millis());
+    
+    sleep(Duration::from_millis(250)).await;
+    let (a, b) = tokio::join!(handler.handle_request(request(23)), handler.handle_request(request(24)));
+    println!("   📨 冷却后的两个并发请求: #23 状态 {}，#24 状态 {}", a.status, b.status);
+    
+    let after = handler.handle_request(request(25)).await;
+    println!("   📨 试探成功后的请求 #25: 状态 {}", after.status);
+    
+    let transitions = stats.breaker_transitions.load(Ordering::Relaxed);
+    let ok = rejected.status == 503
+        && [a.status, b.status].contains(&200)
+        && [a.status, b.status].contains(&503)
+        && after.status == 200
+        && transitions == 3;
+    println!("   {} 状态变化 {} 次（Closed -> Open -> HalfOpen -> Closed）", if ok { "✅" } else { "❌" }, transitions);
+}
+
+/// 演示请求级日志：被取消的请求同样会留下带耗时的事件
+async fn request_span_demo() {
+    println!("\n\n🔍 请求级 tracing 演示");
+    println!("📝 一个 500ms 的请求被 100ms 超时取消，Drop 守卫记录 cancelled\n");
+    
+    let handler = RequestHandler {
+        id: 99,
+        stats: Arc::new(ServerStats::new()),
+        max_processing_time: Duration::from_secs(5),
+        breakers: Arc::new(BreakerRegistry::new(5, Duration::from_secs(1))),
+    };
+    let request = Request::new(1000, "/api/slow", Duration::from_millis(500));
+    
+    if timeout(Duration::from_millis(100), handler.handle_request(request)).await.is_err() {
+        println!("⏱️  请求超时被取消");
+    }
+}
+
+/// 演示 span 跨 channel 传递：工作者的日志带上提交者 span 里的字段
+async fn span_propagation_demo() {
+    use tracing::Instrument;
+    
+    println!("\n\n🧵 跨 channel 的 tracing 上下文传递");
+    println!("📝 在 client{{user=alice}} span 中提交请求，工作者日志应带有 user=alice\n");
+    
+    let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+    let request = Request::new(3000, "/api/traced", Duration::from_millis(20));
+    
+    async {
+        lb.submit_request(request).await.unwrap();
+    }
+    .instrument(tracing::info_span!("client", user = "alice"))
+    .await;
+    
+    if let Some(response) = lb.get_response().await {
+        println!("   ✅ 收到请求 #{} 的响应，上面的 request completed 日志以 client{{user=\"alice\"}} 开头", response.request_id);
+    }
+}
+
+/// 演示 task-local：并发处理的两个请求各自看到自己的请求 id
+async fn task_local_demo() {
+    println!("\n\n🏷️  task-local 请求 id 演示");
+    println!("📝 两个请求并发处理，响应体里的 id 由 render_body 从 task-local 读出\n");
+    
+    let handler = Arc::new(RequestHandler {
+        id: 77,
+        stats: Arc::new(ServerStats::new()),
+        max_processing_time: Duration::from_secs(5),
+        breakers: Arc::new(BreakerRegistry::new(5, Duration::from_secs(1))),
     });
+    let handles: Vec<_> = [4001, 4002]
+        .into_iter()
+        .map(|id| {
+            let handler = handler.clone();
+            let request = Request::new(id, "/api/local", Duration::from_millis(50));
+            tokio::spawn(REQUEST_ID.scope(id, async move { handler.handle_request(request).await }))
+        })
+        .collect();
+    
+    for handle in handles {
+        let response = handle.await.unwrap();
+        println!("   📦 请求 #{} 的响应体: {}", response.request_id, response.body);
+    }
+    println!("   📭 作用域外读取: {:?}", current_request_id());
+}
+
+/// 演示关闭钩子按注册的相反顺序执行
+async fn shutdown_hooks_demo() {
+    println!("\n\n🧹 关闭钩子演示");
+    println!("📝 按 数据库 → 缓存 → 日志 的顺序注册，关闭时反向执行\n");
+    
+    let hooks = ShutdownHooks::new();
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    
+    for name in ["关闭数据库连接", "刷新缓存", "刷新日志"] {
+        let order = order.clone();
+        hooks.register(name, move || async move {
+            sleep(Duration::from_millis(50)).await;
+            order.lock().unwrap().push(name);
+        });
+    }
+    
+    hooks.run_all().await;
+    let order = order.lock().unwrap();
+    if *order == ["刷新日志", "刷新缓存", "关闭数据库连接"] {
+        println!("\n✅ 按注册的相反顺序执行: {:?}", order);
+    } else {
+        println!("\n❌ 执行顺序不是注册的相反顺序: {:?}", order);
+    }
+}
+
+/// 演示关闭期限：一个永远不结束的组件会在期限到达时被强制中止
+async fn shutdown_timeout_demo() {
+    println!("\n\n⏰ 关闭期限演示");
+    println!("📝 总期限 500ms，其中一个组件永远不会结束\n");
     
-    let lb_clone3 = load_balancer.clone();
-    let monitor = tokio::spawn(async move {
-        monitor_task(lb_clone3, Duration::from_secs(15)).await;
+    let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+    let quick = tokio::spawn(async {
+        sleep(Duration::from_millis(100)).await;
+        println!("   ✅ 快速组件正常结束");
+    });
+    let stuck = tokio::spawn(async {
+        loop {
+            sleep(Duration::from_secs(3600)).await;
+        }
     });
     
-    // 等待所有任务完成
-    let _ = tokio::join!(generator, collector, monitor);
-    
-    println!("\n{}", "=".repeat(50));
-    println!("{}", "=".repeat(50));
-    stats.print_stats();
-    println!("{}", "=".repeat(50));
+    let start = tokio::time::Instant::now();
+    let outcome = supervise(&lb, vec![("快速组件", quick), ("卡死组件", stuck)], Duration::from_millis(500)).await;
+    match outcome {
+        ServerOutcome::Clean => println!("   📋 正常结束"),
+        ServerOutcome::Forced { aborted } => {
+            println!("   📋 强制结束，被中止的组件: {:?}（耗时 {}ms）", aborted, start.elapsed().as_millis());
+        }
+        ServerOutcome::Interrupted { dropped } => println!("   📋 被中断，丢弃 {} 个请求", dropped),
+    }
     
-    println!("\n🎉 服务器模拟完成！");
+    let request = Request::new(9000, "/api/late", Duration::from_millis(10));
+    if let Err(e) = lb.submit_request(request).await {
+        println!("   🚫 排空后提交请求: {}", e);
+    }
 }
 
-/// 演示优雅关闭
-async fn graceful_shutdown_demo() {
-    use tokio::sync::broadcast;
+/// 演示 WFQ 调度：两个租户权重相同，各自突发提交一批请求
+async fn wfq_demo() {
+    println!("\n\n⚖️  加权公平队列（WFQ）演示");
+    println!("📝 tenant-a 先突发提交 12 个请求，tenant-b 紧随其后提交 12 个\n");
     
-    println!("\n\n🛑 优雅关闭演示");
-    println!("📝 按 Ctrl+C 不会立即终止，而是等待任务完成\n");
+    let stats = Arc::new(ServerStats::new());
+    let weights = HashMap::from([
+        ("tenant-a".to_string(), 1),
+        ("tenant-b".to_string(), 1),
+    ]);
+    let config = ServerConfig { max_concurrent: 2, num_workers: 2, ..ServerConfig::default() };
+    let lb = LoadBalancer::new_wfq(&config, weights, stats);
     
-    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+    for (tenant, offset) in [("tenant-a", 100), ("tenant-b", 200)] {
+        for i in 1..=12 {
+            let request = Request::new(offset + i, format!("/{}/orders", tenant), Duration::from_millis(100));
+            lb.submit_request(request).await.unwrap();
+        }
+    }
     
-    // 模拟一些长时间运行的任务
-    let mut tasks = vec![];
+    // 统计最先完成的 12 个请求各属于哪个租户（tenant-a 的 id 在 200 以下）
+    let (mut a, mut b) = (0u32, 0u32);
+    for _ in 0..12 {
+        match lb.get_response().await {
+            Some(response) if response.request_id < 200 => a += 1,
+            Some(_) => b += 1,
+            None => break,
+        }
+    }
+    println!("\n📊 最先完成的 12 个请求中各租户的个数:");
+    println!("   tenant-a: {}", a);
+    println!("   tenant-b: {}", b);
+    // 容差：两个租户完成的请求数相差不超过 2
+    if a.abs_diff(b) <= 2 {
+        println!("   ✅ 尽管 tenant-a 先到，两个租户仍大致平分工作者时间（{} vs {}）", a, b);
+    } else {
+        println!("   ❌ 两个租户的服务次数相差过大（{} vs {}）", a, b);
+    }
     
-    for i in 1..=3 {
-        let mut shutdown_rx = shutdown_tx.subscribe();
-        let task = tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    _ = shutdown_rx.recv() => {
-                        println!("   🛑 任务 {} 收到关闭信号", i);
-                        break;
-                    }
-                    _ = sleep(Duration::from_millis(500)) => {
-                        println!("   🔄 任务 {} 运行中...", i);
-                    }
-                }
-            }
-            
-            println!("   ✅ 任务 {} 清理完成", i);
-        });
-        tasks.push(task);
+    // 收完剩余响应，避免后台工作者的输出混入后面的演示
+    for _ in 0..12 {
+        lb.get_response().await;
     }
+}
+
+/// 演示按权重分配：3 个工作者权重 1:2:3，各自处理的请求数应接近权重占比
+async fn weighted_demo() {
+    println!("\n\n🏋️  加权负载均衡演示");
+    println!("📝 3 个工作者权重 1 / 2 / 3，提交 60 个 10ms 的请求\n");
     
-    // 模拟接收关闭信号
-    sleep(Duration::from_secs(2)).await;
-    println!("\n📢 发送关闭信号...\n");
-    let _ = shutdown_tx.send(());
+    let weights = vec![1, 2, 3];
+    let stats = Arc::new(ServerStats::new());
+    let config = ServerConfig { max_concurrent: 3, ..ServerConfig::default() };
+    let lb = LoadBalancer::new_weighted(weights.clone(), &config, stats.clone());
     
-    // 等待所有任务完成
-    for task in tasks {
-        let _ = task.await;
+    // 工作者与权重一一对应，扩缩容请求会被忽略
+    lb.scale_to(5);
+    lb.scale_to(1);
+    let workers = lb.worker_count();
+    println!("   {} scale_to 之后仍是 {} 个工作者", if workers == weights.len() { "✅" } else { "❌" }, workers);
+    
+    let total = 60;
+    for id in 1..=total {
+        let request = Request::new(7300 + id, "/api/weighted", Duration::from_millis(10));
+        lb.submit_request(request).await.unwrap();
+    }
+    for _ in 0..total {
+        lb.get_response().await;
     }
     
-    println!("\n✅ 所有任务已优雅关闭");
+    // 容差：每个工作者与期望值的偏差不超过总请求数的 10%
+    let counts = stats.per_worker_counts();
+    let weight_sum: u32 = weights.iter().sum();
+    let tolerance = total / 10;
+    let mut within = true;
+    println!("\n📊 各工作者处理的请求数:");
+    for (worker_id, &weight) in weights.iter().enumerate() {
+        let expected = total * weight as u64 / weight_sum as u64;
+        let actual = counts.get(&worker_id).copied().unwrap_or(0);
+        within &= actual.abs_diff(expected) <= tolerance;
+        println!("   工作者 {} (权重 {}): {:>2} 个，期望 {}", worker_id, weight, actual, expected);
+    }
+    if within {
+        println!("   ✅ 每个工作者的处理数都在期望值 ±{} 以内", tolerance);
+    } else {
+        println!("   ❌ 有工作者的处理数偏离权重占比超过 ±{}", tolerance);
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    tracing_subscriber::fmt().with_target(false).init();
+    
     // 运行主服务器模拟
-    run_server().await;
+    let report = run_server().await;
+    println!("\n📄 服务器报告:");
+    println!("   • 请求: {} 个（成功 {}，失败 {}）", report.total, report.success, report.failed);
+    println!("   • p50 延迟: {:?}", report.p50_latency);
+    println!("   • 运行时长: {:.1} 秒", report.duration.as_secs_f64());
+    println!("   • 结束方式: {:?}", report.outcome);
+    let expected = ServerConfig::from_env().unwrap_or_default().num_requests;
+    if report.total == expected && report.success + report.failed == report.total {
+        println!("   ✅ 报告中的请求总数与提交数一致（{}）", expected);
+    } else {
+        println!("   ⚠️  报告中的请求总数 ({}) 与提交数 ({}) 不一致", report.total, expected);
+    }
+    
+    // Ctrl+C 已被 run_server 接管，后面的演示无法再被中断，直接以 SIGINT 的惯例退出码结束
+    if matches!(report.outcome, ServerOutcome::Interrupted { .. }) {
+        println!("\n🛑 已被 Ctrl+C 中断，跳过其余演示");
+        std::process::exit(130);
+    }
+    
+    // 演示关闭期限
+    shutdown_timeout_demo().await;
+    
+    // 演示 WFQ 公平调度
+    wfq_demo().await;
+    
+    // 演示加权负载均衡
+    weighted_demo().await;
+    
+    // 演示自适应并发限制
+    adaptive_limit_demo().await;
+    
+    // 演示延迟过载保护
+    load_shedding_demo().await;
+    
+    // 演示工作者预热
+    warmup_demo().await;
+    
+    // 演示有界重排缓冲
+    reorder_buffer_demo().await;
+    
+    // 演示 seqlock 计数器
+    seqlock_stats_demo();
+    
+    // 演示统计变化推送
+    stats_watch_demo().await;
+    
+    // 演示结构化监控指标
+    metrics_snapshot_demo().await;
+    
+    // 演示各工作者的负载分布
+    worker_fairness_demo().await;
+    
+    // 演示各路径平均耗时
+    endpoint_averages_demo().await;
+    
+    // 演示请求处理超时
+    processing_timeout_demo().await;
+    
+    // 演示处理器内的熔断
+    handler_breaker_demo().await;
+    
+    // 演示令牌桶限速
+    rate_limiter_demo().await;
+    
+    // 演示请求级 tracing
+    request_span_demo().await;
+    
+    // 演示 tracing 上下文跨 channel 传递
+    span_propagation_demo().await;
+    
+    // 演示 task-local 请求 id
+    task_local_demo().await;
+    
+    // 演示关闭钩子
+    shutdown_hooks_demo().await;
     
     // 演示优雅关闭
     graceful_shutdown_demo().await;
     
+    // 演示分阶段有序关闭
+    ordered_shutdown_demo().await;
+    
+    // 演示弹性调用
+    resilient_call_demo().await;
+    
+    // 演示提交重试
+    submit_retry_demo().await;
+    
+    // 演示背压感知提交
+    try_submit_demo().await;
+    
+    // 演示立即停止
+    stop_demo().await;
+    
+    // 演示请求/响应调用
+    request_reply_demo().await;
+    
+    // 演示自定义 Handler
+    custom_handler_demo().await;
+    
+    // 演示关闭负载均衡器
+    lb_shutdown_demo().await;
+    
+    // 演示动态扩缩容
+    scaling_demo().await;
+    
+    // 演示请求优先级
+    priority_demo().await;
+    
+    // 演示对冲请求
+    hedging_demo().await;
+    
+    // 演示环境变量配置
+    config_demo().await;
+    
+    // 演示构建器
+    builder_demo().await;
+    
     println!("\n💡 本示例展示了：");
     println!("   ✓ 任务生成和管理 (tokio::spawn)");
     println!("   ✓ Channel 通信 (mpsc)");
     println!("   ✓ 并发限制 (Semaphore)");
+    println!("   ✓ 加权公平调度 (WFQ + Notify)");
+    println!("   ✓ 加权负载均衡 (平滑加权轮询 + 每个工作者一个通道)");
+    println!("   ✓ 自适应并发限制 (AIMD)");
+    println!("   ✓ 延迟过载保护 (滚动 p99 + 按比例丢弃)");
     println!("   ✓ 原子操作 (AtomicU64)");
-    println!("   ✓ 超时处理 (timeout)");
-    println!("   ✓ 优雅关闭 (broadcast + select!)");
+    println!("   ✓ 无锁一致快照 (seqlock)");
+    println!("   ✓ 统计变化推送 (watch + send_if_modified，取代定时轮询)");
+    println!("   ✓ 结构化监控指标 (MetricsSnapshot + 处理中请求 gauge)");
+    println!("   ✓ 按工作者统计 (观察共享接收端分发的公平性)");
+    println!("   ✓ 按路径统计平均耗时 (Mutex<HashMap>，零成功请求不做除法)");
+    println!("   ✓ 超时处理 (timeout，慢请求返回 504)");
+    println!("   ✓ 按路径熔断 (打开时直接 503，半开只放行一个试探请求)");
+    println!("   ✓ 令牌桶限速 (容量允许突发 + 按速率补充)");
+    println!("   ✓ 弹性调用 (重试 + 退避 + 熔断)");
+    println!("   ✓ 提交重试 (try_send 区分已满与已关闭 + 指数退避)");
+    println!("   ✓ 背压感知提交 (try_submit 返回 Busy，由调用者决定退避或丢弃)");
+    println!("   ✓ 请求/响应调用 (oneshot 把响应送回调用者)");
+    println!("   ✓ 泛型工作者池 (Handler trait + 关联类型)");
+    println!("   ✓ 动态扩缩容 (退出令牌 + 请求处理完再退出)");
+    println!("   ✓ 请求优先级 (两个通道 + biased select!)");
+    println!("   ✓ 对冲请求 (oneshot 关联响应 + select!)");
+    println!("   ✓ 优雅关闭 (broadcast 关闭原因 + select!)");
+    println!("   ✓ 关闭负载均衡器 (关闭请求通道 + 等待工作者 JoinHandle)");
+    println!("   ✓ Ctrl+C 立即停止 (broadcast 停止信号 + 统计被丢弃的排队请求)");
+    println!("   ✓ 分阶段有序关闭 (watch)");
+    println!("   ✓ 工作者预热与就绪信号 (watch 倒计数)");
+    println!("   ✓ 有界重排缓冲 (BTreeMap + 超时跳过缺口)");
+    println!("   ✓ 关闭钩子 (LIFO 清理)");
     println!("   ✓ 错误处理和统计");
+    println!("   ✓ 环境变量配置 (Result + 自定义错误)");
+    println!("   ✓ 构建器 (链式设置 + build 时校验)");
+    println!("   ✓ 请求级 tracing (instrument + Drop 守卫)");
+    println!("   ✓ tracing 上下文跨 channel 传递 (Span 随请求携带)");
+    println!("   ✓ 隐式传递请求 id (task_local!)");
     println!("\n🎓 恭喜完成所有教程！你已经掌握了 Rust 异步编程的核心概念！");
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test(start_paused = true)]
+    async fn wfq_shares_workers_evenly_between_equal_tenants() {
+        let weights = HashMap::from([("tenant-a".to_string(), 1), ("tenant-b".to_string(), 1)]);
+        let config = ServerConfig { max_concurrent: 2, num_workers: 2, ..ServerConfig::default() };
+        let lb = LoadBalancer::new_wfq(&config, weights, Arc::new(ServerStats::new()));
+        
+        // tenant-a 的整批请求先到，tenant-b 随后
+        for (tenant, offset) in [("tenant-a", 100), ("tenant-b", 200)] {
+            for i in 1..=12 {
+                let request = Request::new(offset + i, format!("/{}/orders", tenant), Duration::from_millis(100));
+                lb.submit_request(request).await.unwrap();
+            }
+        }
+        
+        // 按完成顺序统计，而不是按出队顺序
+        let mut completed = Vec::new();
+        for _ in 0..24 {
+            completed.push(lb.get_response().await.unwrap().request_id);
+        }
+        for prefix in [4, 8, 12, 24] {
+            let a = completed[..prefix].iter().filter(|&&id| id < 200).count();
+            let b = prefix - a;
+            assert!(a.abs_diff(b) <= 2, "前 {} 个完成的请求: tenant-a {} / tenant-b {}", prefix, a, b);
+        }
+    }
+    
+    #[tokio::test]
+    async fn shutdown_hooks_run_in_reverse_registration_order() {
+        let hooks = ShutdownHooks::new();
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for name in ["first", "second", "third"] {
+            let order = order.clone();
+            hooks.register(name, move || async move {
+                order.lock().unwrap().push(name);
+            });
+        }
+        
+        hooks.run_all().await;
+        assert_eq!(*order.lock().unwrap(), ["third", "second", "first"]);
+        
+        // 每个钩子只执行一次
+        hooks.run_all().await;
+        assert_eq!(order.lock().unwrap().len(), 3);
+    }
+    
+    /// 测试用的处理器：熔断阈值足够高，不会干扰结果
+    fn test_handler(stats: Arc<ServerStats>) -> RequestHandler {
+        RequestHandler {
+            id: 1,
+            stats,
+            max_processing_time: Duration::from_secs(5),
+            breakers: Arc::new(BreakerRegistry::new(100, Duration::from_secs(1))),
+        }
+    }
+    
+    /// 从日志行中取出 key=value 形式的数值字段
+    fn field(line: &str, key: &str) -> Option<u64> {
+        let start = line.find(&format!("{}=", key))? + key.len() + 1;
+        line[start..].split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()
+    }
+    
+    #[tokio::test(start_paused = true)]
+    #[tracing_test::traced_test]
+    async fn completed_request_logs_its_id_and_elapsed_time() {
+        let handler = test_handler(Arc::new(ServerStats::new()));
+        // prepare 记下当前（测试）span，请求的日志挂在它下面才能被 logs_assert 看到
+        let mut request = Request::new(42, "/api/traced", Duration::from_millis(120));
+        RequestHandler::prepare(&mut request);
+        handler.handle_request(request).await;
+        
+        logs_assert(|lines: &[&str]| {
+            let line = lines
+                .iter()
+                .find(|line| line.contains("request completed"))
+                .ok_or("没有 request completed 事件")?;
+            match (field(line, "request_id"), field(line, "elapsed_ms")) {
+                (Some(42), Some(elapsed)) if elapsed > 0 => Ok(()),
+                _ => Err(format!("字段不符合预期: {}", line)),
+            }
+        });
+    }
+    
+    #[tokio::test(start_paused = true)]
+    #[tracing_test::traced_test]
+    async fn dropped_request_logs_cancelled() {
+        let handler = test_handler(Arc::new(ServerStats::new()));
+        let mut request = Request::new(43, "/api/slow", Duration::from_millis(500));
+        RequestHandler::prepare(&mut request);
+        assert!(timeout(Duration::from_millis(100), handler.handle_request(request)).await.is_err());
+        
+        logs_assert(|lines: &[&str]| {
+            let line = lines.iter().find(|line| line.contains("cancelled")).ok_or("没有 cancelled 事件")?;
+            match (field(line, "request_id"), field(line, "elapsed_ms")) {
+                (Some(43), Some(100)) => Ok(()),
+                _ => Err(format!("字段不符合预期: {}", line)),
+            }
+        });
+        assert!(!logs_contain("request completed"));
+    }
+    
+    #[tokio::test]
+    async fn adaptive_limit_drops_when_latency_rises() {
+        let semaphore = Arc::new(Semaphore::new(4));
+        let limiter = AdaptiveLimiter::new(semaphore.clone(), 4, 1, 8, Duration::from_millis(100));
+        
+        for _ in 0..3 {
+            limiter.on_latency(Duration::from_millis(20));
+        }
+        assert_eq!(limiter.limit(), 7);
+        assert_eq!(semaphore.available_permits(), 7);
+        
+        // 延迟越过阈值：每次减半，直到下限
+        let mut limits = Vec::new();
+        for _ in 0..4 {
+            limiter.on_latency(Duration::from_millis(300));
+            limits.push(limiter.limit());
+        }
+        assert_eq!(limits, [3, 1, 1, 1]);
+        
+        // 没收多余许可的后台任务运行之后，信号量的许可数也跟着收缩
+        tokio::task::yield_now().await;
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn adaptive_load_balancer_shrinks_under_slow_requests() {
+        let lb = LoadBalancer::new_adaptive(8, 2, 8, Duration::from_millis(150), Arc::new(ServerStats::new()));
+        let fast = (1..=8).map(|id| (id, 50));
+        let slow = (9..=14).map(|id| (id, 300));
+        for (id, ms) in fast.chain(slow) {
+            lb.submit_request(Request::new(id, "/api/adaptive", Duration::from_millis(ms))).await.unwrap();
+        }
+        
+        let mut limits = Vec::new();
+        for _ in 0..14 {
+            lb.get_response().await.unwrap();
+            limits.push(lb.concurrency_limit().unwrap());
+        }
+        let peak = limits.iter().copied().max().unwrap();
+        assert!(peak > 2, "快请求阶段上限应升过初始值: {:?}", limits);
+        assert!(*limits.last().unwrap() < peak, "慢请求阶段上限应下降: {:?}", limits);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn supervise_force_stops_a_hung_component_at_the_deadline() {
+        let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+        let quick = tokio::spawn(sleep(Duration::from_millis(100)));
+        let hung = tokio::spawn(std::future::pending::<()>());
+        let hung_abort = hung.abort_handle();
+        
+        let start = tokio::time::Instant::now();
+        let outcome = supervise(&lb, vec![("quick", quick), ("hung", hung)], Duration::from_secs(2)).await;
+        
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+        assert!(matches!(outcome, ServerOutcome::Forced { ref aborted } if aborted == &["hung"]), "{:?}", outcome);
+        tokio::task::yield_now().await;
+        assert!(hung_abort.is_finished());
+        // 超时后负载均衡器开始排空，不再接受新请求
+        assert!(lb.submit_request(Request::new(1, "/late", Duration::ZERO)).await.is_err());
+        
+        let done = tokio::spawn(sleep(Duration::from_millis(100)));
+        let outcome = supervise(&lb, vec![("done", done)], Duration::from_secs(2)).await;
+        assert!(matches!(outcome, ServerOutcome::Clean), "{:?}", outcome);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn stats_snapshot_matches_completed_requests() {
+        let lb = LoadBalancer::new(4, Arc::new(ServerStats::new()));
+        assert_eq!(lb.stats(), StatsSnapshot::new(0, 0, 0));
+        
+        for id in 1..=10 {
+            lb.submit_request(Request::new(id, "/api/stats", Duration::from_millis(50))).await.unwrap();
+        }
+        for _ in 0..10 {
+            lb.get_response().await.unwrap();
+        }
+        
+        // id 为 7 的倍数的请求模拟失败
+        let snapshot = lb.stats();
+        assert_eq!(snapshot, StatsSnapshot::new(10, 9, 1));
+        assert_eq!(snapshot.in_flight, 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn ordered_shutdown_drains_queue_before_workers_stop() {
+        let report = run_ordered_shutdown(Duration::from_millis(300)).await;
+        assert!(report.submitted > 0);
+        assert_eq!(report.submitted, report.submitted_at_drain, "排空开始后不应再提交请求");
+        assert_eq!(report.left_on_exit, 0, "工作者退出时队列应已为空");
+        assert_eq!(report.collected, report.submitted);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn resilient_call_retries_flaky_request_until_it_succeeds() {
+        let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+        let policy = ResiliencePolicy {
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(50),
+            breakers: Arc::new(BreakerRegistry::new(5, Duration::from_secs(5))),
+        };
+        
+        // id 7 失败，重试时的 id 8 成功
+        let start = tokio::time::Instant::now();
+        let result = resilient_call(&lb, Request::new(7, "/api/flaky", Duration::from_millis(20)), &policy).await;
+        assert_eq!(result.attempts, 2);
+        assert!(!result.breaker_open);
+        assert!(matches!(result.outcome, CallOutcome::Success(ref r) if r.request_id == 8), "{:?}", result.outcome);
+        // 两次处理加一次 50ms 的退避
+        assert_eq!(start.elapsed(), Duration::from_millis(90));
+        
+        // 重试预算只有一次时，失败直接返回最后的状态码
+        let once = ResiliencePolicy { max_attempts: 1, ..policy };
+        let result = resilient_call(&lb, Request::new(14, "/api/flaky", Duration::from_millis(20)), &once).await;
+        assert_eq!(result.attempts, 1);
+        assert!(matches!(result.outcome, CallOutcome::Failed(500)), "{:?}", result.outcome);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn hedged_call_returns_fast_hedge_and_cancels_slow_primary() {
+        let lb = LoadBalancer::new(4, Arc::new(ServerStats::new()));
+        let request = |ms| Request::new(41, "/api/hedged", Duration::from_millis(ms));
+        
+        let start = tokio::time::Instant::now();
+        let (response, hedged) = lb.call_hedged(request(500), request(50), Duration::from_millis(100)).await.unwrap();
+        assert!(hedged);
+        assert_eq!(response.request_id, 41, "响应应该保留调用者的请求 id");
+        assert_eq!(start.elapsed(), Duration::from_millis(150));
+        
+        // 首发请求被中止：许可立即归还，之后也不会完成
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(lb.available_slots(), 4);
+        sleep(Duration::from_secs(1)).await;
+        // 被中止的首发请求计为失败，不会在快照里留下一个永远处理中的请求
+        let stats = lb.stats();
+        assert_eq!((stats.total, stats.success, stats.failed), (2, 1, 1));
+        assert_eq!(stats.in_flight, 0);
+        assert!(timeout(Duration::from_millis(100), lb.get_response()).await.is_err(), "落败的结果不应进入响应通道");
+    }
+    
+    /// 用一组键值对模拟环境变量，不影响真实的进程环境
+    fn lookup(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let env: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |var| env.get(var).cloned()
+    }
+    
+    #[test]
+    fn config_reads_set_vars_and_defaults_the_rest() {
+        let config = ServerConfig::from_lookup(lookup(&[("MAX_CONCURRENT", "8"), ("QUEUE_CAP", " 16 ")])).unwrap();
+        assert_eq!(
+            config,
+            ServerConfig { max_concurrent: 8, queue_cap: 16, ..ServerConfig::default() }
+        );
+        assert_eq!(ServerConfig::from_lookup(lookup(&[])).unwrap(), ServerConfig::default());
+    }
+    
+    #[test]
+    fn config_rejects_malformed_and_zero_values() {
+        let err = ServerConfig::from_lookup(lookup(&[("NUM_WORKERS", "four")])).unwrap_err();
+        assert_eq!(err, ConfigError::Invalid { var: "NUM_WORKERS", value: "four".to_string() });
+        assert!(err.to_string().contains("NUM_WORKERS"));
+        
+        let err = ServerConfig::from_lookup(lookup(&[("QUEUE_CAP", "0")])).unwrap_err();
+        assert_eq!(err, ConfigError::Zero { var: "QUEUE_CAP" });
+    }
+    
+    #[tokio::test(start_paused = true)]
+    #[tracing_test::traced_test]
+    async fn worker_events_carry_fields_from_the_submitters_span() {
+        use tracing::Instrument;
+        
+        let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+        async {
+            lb.submit_request(Request::new(3000, "/api/traced", Duration::from_millis(20))).await.unwrap();
+        }
+        .instrument(tracing::info_span!("client", user = "alice"))
+        .await;
+        assert_eq!(lb.get_response().await.unwrap().request_id, 3000);
+        
+        // 事件由工作者任务发出，但仍挂在提交者的 client span 之下
+        logs_assert(|lines: &[&str]| {
+            let line = lines
+                .iter()
+                .find(|line| line.contains("request completed"))
+                .ok_or("没有 request completed 事件")?;
+            if line.contains("client{user=\"alice\"}") && field(line, "request_id") == Some(3000) {
+                Ok(())
+            } else {
+                Err(format!("工作者的日志没有带上提交者的 span: {}", line))
+            }
+        });
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn breaker_for_one_path_does_not_trip_others() {
+        let handler = RequestHandler {
+            breakers: Arc::new(BreakerRegistry::new(3, Duration::from_secs(5))),
+            ..test_handler(Arc::new(ServerStats::new()))
+        };
+        let status = |id, path| {
+            let handler = &handler;
+            async move { handler.handle_request(Request::new(id, path, Duration::from_millis(10))).await.status }
+        };
+        
+        // id 为 7 的倍数的请求失败：/a 连续失败 3 次后熔断
+        for id in [7, 14, 21] {
+            assert_eq!(status(id, "/a").await, 500);
+        }
+        assert_eq!(status(1, "/a").await, 503);
+        assert!(handler.breakers.for_path("/a").is_open());
+        
+        // /b 有自己的计数，不受 /a 影响；偶尔一次失败也不会熔断
+        for id in [1, 2, 28, 3, 4] {
+            assert_ne!(status(id, "/b").await, 503);
+        }
+        assert!(!handler.breakers.for_path("/b").is_open());
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn server_report_accounts_for_every_submitted_request() {
+        let config = ServerConfig { num_requests: 12, ..ServerConfig::default() };
+        let report = run_server_with(config, std::future::pending()).await;
+        
+        assert!(matches!(report.outcome, ServerOutcome::Clean), "{:?}", report.outcome);
+        assert_eq!(report.total, 12);
+        // 只有 id 为 7 的请求失败
+        assert_eq!((report.success, report.failed), (11, 1));
+        assert!(report.p50_latency >= Duration::from_millis(100));
+        assert!(report.duration > Duration::ZERO);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_requests_each_see_their_own_task_local_id() {
+        let lb = LoadBalancer::new(2, Arc::new(ServerStats::new()));
+        let (a, b) = tokio::join!(
+            lb.call(Request::new(4001, "/api/local", Duration::from_millis(50))),
+            lb.call(Request::new(4002, "/api/local", Duration::from_millis(50))),
+        );
+        assert_eq!(a.unwrap().body, "Response for /api/local (request #4001)");
+        assert_eq!(b.unwrap().body, "Response for /api/local (request #4002)");
+        
+        // 作用域之外读不到 id
+        assert_eq!(current_request_id(), None);
+    }
+    
+    #[test]
+    fn shedder_sheds_under_high_latency_and_stops_after_recovery() {
+        let shedder = LoadShedder::new(10, Duration::from_millis(100));
+        let shed_count = |n| (0..n).filter(|_| shedder.should_shed()).count();
+        assert_eq!(shed_count(20), 0);
+        
+        // p99 为 400ms：拒绝比例 1 - 100/400 = 75%
+        for _ in 0..10 {
+            shedder.record(Duration::from_millis(400));
+        }
+        assert!((shedder.shed_fraction() - 0.75).abs() < 1e-9);
+        assert_eq!(shed_count(20), 15);
+        
+        // 延迟回落后窗口里的慢样本被挤出，不再丢弃
+        for _ in 0..10 {
+            shedder.record(Duration::from_millis(20));
+        }
+        assert_eq!(shedder.shed_fraction(), 0.0);
+        assert_eq!(shed_count(20), 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn wait_ready_resolves_only_after_warmup() {
+        let config = ServerConfig { warmup: Duration::from_millis(300), ..ServerConfig::default() };
+        let start = tokio::time::Instant::now();
+        let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+        
+        assert!(timeout(Duration::from_millis(299), lb.wait_ready()).await.is_err());
+        lb.wait_ready().await;
+        assert_eq!(start.elapsed(), Duration::from_millis(300));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn wait_ready_resolves_when_a_warming_worker_is_aborted() {
+        let config = ServerConfig { warmup: Duration::from_secs(10), ..ServerConfig::default() };
+        let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+        
+        // 预热到一半时中止所有工作者，它们永远不会走到预热结束那一步
+        sleep(Duration::from_millis(10)).await;
+        for worker in lb.workers.lock().unwrap().iter() {
+            worker.abort();
+        }
+        assert!(timeout(Duration::from_millis(10), lb.wait_ready()).await.is_ok());
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn scale_down_lets_busy_workers_finish_their_request_first() {
+        let lb = LoadBalancer::new(4, Arc::new(ServerStats::new()));
+        for id in 1..=4 {
+            lb.submit_request(Request::new(id, "/api/slow", Duration::from_secs(1))).await.unwrap();
+        }
+        sleep(Duration::from_millis(10)).await;
+        
+        // 4 个工作者都在处理请求，没有谁能立刻领取退出令牌
+        lb.scale_to(1);
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(lb.worker_count(), 4);
+        
+        for _ in 1..=4 {
+            assert_eq!(lb.get_response().await.unwrap().status, 200);
+        }
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(lb.worker_count(), 1);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn scale_to_zero_keeps_one_worker() {
+        let lb = LoadBalancer::new(4, Arc::new(ServerStats::new()));
+        lb.scale_to(0);
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(lb.worker_count(), 1);
+        
+        lb.submit_request(Request::new(1, "/api/after-scale", Duration::from_millis(10))).await.unwrap();
+        assert_eq!(lb.get_response().await.unwrap().request_id, 1);
+    }
+    
+    #[test]
+    fn seqlock_snapshots_are_never_torn_under_concurrent_writes() {
+        let (last, reads, torn) = seqlock_stress(4, 20_000);
+        assert!(reads > 0);
+        assert_eq!(torn, 0, "{} 次读取中出现了不一致的快照", reads);
+        assert_eq!(last.total, 80_000);
+        assert_eq!(last.success + last.failed, last.total);
+        assert_eq!(last.in_flight, 0);
+    }
+    
+    /// 按 (id, 发送前等待的毫秒数) 依次发出响应，发完后关闭 channel
+    fn send_responses(plan: Vec<(u64, u64)>) -> mpsc::Receiver<Response> {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(async move {
+            for (id, gap) in plan {
+                sleep(Duration::from_millis(gap)).await;
+                let _ = tx.send(Response { request_id: id, status: 200, body: String::new() }).await;
+            }
+        });
+        rx
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn reorder_buffer_skips_a_missing_response_after_max_wait() {
+        // #2 永远不到；#6 在跳过缺口之后才到，应照常按序交付
+        let rx = send_responses(vec![(3, 10), (1, 10), (4, 10), (5, 10), (6, 500)]);
+        let start = tokio::time::Instant::now();
+        let (delivered, gaps) = collect_ordered(rx, ReorderBuffer::new(1, 10, Duration::from_millis(200))).await;
+        assert_eq!(delivered, [1, 3, 4, 5, 6]);
+        assert_eq!(gaps, [2]);
+        assert_eq!(start.elapsed(), Duration::from_millis(540));
+        
+        // 缓冲超过上限时不等超时，立即跳过；迟到的 #1 被丢弃
+        let rx = send_responses(vec![(2, 0), (3, 0), (4, 0), (1, 0), (5, 0)]);
+        let (delivered, gaps) = collect_ordered(rx, ReorderBuffer::new(1, 2, Duration::from_secs(60))).await;
+        assert_eq!(delivered, [2, 3, 4, 5]);
+        assert_eq!(gaps, [1]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn tasks_see_shutdown_reason_and_skip_flush_on_fatal_error() {
+        let reason = ShutdownReason::FatalError("磁盘损坏".to_string());
+        let exits = run_until_shutdown(reason.clone(), Duration::from_millis(700)).await;
+        assert_eq!(exits.len(), 3);
+        assert!(exits.iter().all(|exit| exit.reason == reason && !exit.flushed));
+        
+        let exits = run_until_shutdown(ShutdownReason::UserRequested, Duration::from_millis(700)).await;
+        assert!(exits.iter().all(|exit| exit.reason == ShutdownReason::UserRequested && exit.flushed));
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_waits_for_every_submitted_request() {
+        let stats = Arc::new(ServerStats::new());
+        let lb = LoadBalancer::new(4, stats.clone());
+        
+        // 不读取任何响应，shutdown 也必须等所有请求处理完
+        const N: u64 = 25;
+        for id in 1..=N {
+            lb.submit_request(Request::new(id, "/api/shutdown", Duration::from_millis(50))).await.unwrap();
+        }
+        lb.shutdown().await;
+        
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.total, N);
+        assert_eq!(snapshot.success + snapshot.failed, N);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn high_priority_requests_jump_the_queue_under_contention() {
+        let config = ServerConfig { max_concurrent: 1, num_workers: 1, ..ServerConfig::default() };
+        let lb = LoadBalancer::with_config(&config, Arc::new(ServerStats::new()));
+        
+        // 先占住唯一的工作者，之后的请求只能排队
+        lb.submit_request(Request::new(1, "/api/busy", Duration::from_millis(100))).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+        for i in 1..=4 {
+            lb.submit_prioritized(Request::new(10 + i, "/api/low", Duration::from_millis(30))).await.unwrap();
+            lb.submit_prioritized(Request::new(20 + i, "/api/urgent", Duration::from_millis(30)).with_priority(1)).await.unwrap();
+        }
+        
+        let mut order = Vec::new();
+        for _ in 0..9 {
+            order.push(lb.get_response().await.unwrap().request_id);
+        }
+        assert_eq!(order, [1, 21, 22, 23, 24, 11, 12, 13, 14]);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn abandoned_half_open_trial_expires_after_cooldown() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(1));
+        breaker.record(false);
+        assert!(!breaker.allow(), "冷却期内应拒绝");
+        
+        sleep(Duration::from_secs(1)).await;
+        assert!(breaker.allow(), "冷却期过后放行一个试探");
+        assert!(!breaker.allow(), "试探进行中不再放行");
+        
+        // 试探请求被丢弃，永远不会 record；一个冷却期后应允许重新试探
+        sleep(Duration::from_millis(999)).await;
+        assert!(!breaker.allow());
+        sleep(Duration::from_millis(1)).await;
+        assert!(breaker.allow());
+        
+        breaker.record(true);
+        assert!(!breaker.is_open());
+        assert!(breaker.allow() && breaker.allow(), "关闭后正常放行");
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter_paces_a_hundred_requests_at_ten_per_second() {
+        let limiter = RateLimiter::new(10, 10.0);
+        let start = tokio::time::Instant::now();
+        for _ in 0..100 {
+            limiter.acquire().await;
+        }
+        // 前 10 个用掉满桶的突发额度，剩下 90 个按每秒 10 个放行
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_secs(9), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(9100), "{:?}", elapsed);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn rate_limiter_clamps_invalid_refill_rates() {
+        for rate in [0.0, -5.0, f64::NAN] {
+            let limiter = RateLimiter::new(1, rate);
+            assert_eq!(limiter.refill_rate, RateLimiter::MIN_REFILL_RATE);
+            limiter.acquire().await;
+            
+            // 第二个令牌要按最低速率等 1000 秒，而不是 panic
+            let start = tokio::time::Instant::now();
+            limiter.acquire().await;
+            assert_eq!(start.elapsed(), Duration::from_secs(1000));
+        }
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn queued_counts_requests_buffered_by_wfq_and_weighted_dispatch() {
+        let stats = Arc::new(ServerStats::new());
+        let single = ServerConfig { max_concurrent: 1, num_workers: 1, ..ServerConfig::default() };
+        let wfq = LoadBalancer::new_wfq(&single, HashMap::new(), stats.clone());
+        let weighted = LoadBalancer::new_weighted(vec![1], &single, stats);
+        
+        for lb in [&wfq, &weighted] {
+            for id in 1..=5 {
+                lb.submit_request(Request::new(id, "/tenant-a/orders", Duration::from_millis(100))).await.unwrap();
+            }
+            // 让入队/分发任务把请求从请求通道转走，唯一的工作者正在处理第一个
+            sleep(Duration::from_millis(10)).await;
+            assert_eq!(lb.queued(), 4);
+            
+            for _ in 0..5 {
+                lb.get_response().await.unwrap();
+            }
+            assert_eq!(lb.queued(), 0);
+        }
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn in_flight_gauge_is_released_when_a_worker_is_aborted() {
+        let stats = Arc::new(ServerStats::new());
+        let lb = LoadBalancer::new(4, stats.clone());
+        lb.submit_request(Request::new(1, "/api/slow", Duration::from_secs(3))).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(stats.in_flight(), 1);
+        
+        // 处理到一半时中止所有工作者，正在处理的 future 被直接丢弃
+        for worker in lb.workers.lock().unwrap().iter() {
+            worker.abort();
+        }
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(stats.in_flight(), 0);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn weighted_workers_handle_their_share_of_requests() {
+        let weights = vec![1, 2, 3];
+        let stats = Arc::new(ServerStats::new());
+        let config = ServerConfig { max_concurrent: 3, ..ServerConfig::default() };
+        let lb = LoadBalancer::new_weighted(weights.clone(), &config, stats.clone());
+        
+        let total = 60;
+        for id in 1..=total {
+            lb.submit_request(Request::new(id, "/api/weighted", Duration::from_millis(10))).await.unwrap();
+        }
+        for _ in 0..total {
+            lb.get_response().await.unwrap();
+        }
+        
+        let counts = stats.per_worker_counts();
+        let weight_sum: u32 = weights.iter().sum();
+        for (worker_id, &weight) in weights.iter().enumerate() {
+            let expected = total * weight as u64 / weight_sum as u64;
+            let actual = counts.get(&worker_id).copied().unwrap_or(0);
+            assert!(actual.abs_diff(expected) <= total / 10, "工作者 {}：{} 个，期望 {}", worker_id, actual, expected);
+        }
+    }
+}
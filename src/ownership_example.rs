@@ -5,14 +5,39 @@
 // 2. 引用（Reference）
 // 3. 借用（Borrowing）
 
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use futures::stream::{self, Stream, StreamExt};
+use tokio::sync::{broadcast, mpsc, oneshot, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
 /// 自定义结构体用于演示
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Book {
     title: String,
     author: String,
     pages: u32,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    revisions: Vec<Revision>,
+}
+
+/// 一条修改记录：哪个字段、从什么改成什么、什么时候改的
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Revision {
+    field: String,
+    old: String,
+    new: String,
+    at: SystemTime,
 }
 
 impl Book {
@@ -21,8 +46,32 @@ impl Book {
             title: title.to_string(),
             author: author.to_string(),
             pages,
+            tags: Vec::new(),
+            revisions: Vec::new(),
         }
     }
+    
+    // 可变借用：修改页数并追加一条修改记录
+    fn set_pages(&mut self, pages: u32) {
+        self.revisions.push(Revision {
+            field: "pages".to_string(),
+            old: self.pages.to_string(),
+            new: pages.to_string(),
+            at: SystemTime::now(),
+        });
+        self.pages = pages;
+    }
+    
+    // 借出修改历史的只读切片
+    fn history(&self) -> &[Revision] {
+        &self.revisions
+    }
+    
+    // 获取 self 的所有权，修改后再返回，便于链式调用
+    fn with_tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|t| t.to_string()).collect();
+        self
+    }
 }
 
 impl fmt::Display for Book {
@@ -31,6 +80,32 @@ impl fmt::Display for Book {
     }
 }
 
+/// 可以放进图书馆的馆藏：按标题查找，能打印
+///
+/// Library<T> 只通过这个 trait 访问馆藏，所有权和借用的规则与具体类型无关。
+trait Catalogable: fmt::Display {
+    fn title(&self) -> &str;
+    
+    // 归还时转交给下一位预约者，默认什么也不记录
+    fn record_handover(&mut self, _from: &str, _to: &str) {}
+}
+
+impl Catalogable for Book {
+    fn title(&self) -> &str {
+        &self.title
+    }
+    
+    // 书会在修改历史中记下这次交接
+    fn record_handover(&mut self, from: &str, to: &str) {
+        self.revisions.push(Revision {
+            field: "holder".to_string(),
+            old: from.to_string(),
+            new: to.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+}
+
 /// ============================================
 /// 第一部分：所有权基础
 /// ============================================
@@ -85,9 +160,9 @@ fn demo_ownership_basics() {
     }
 }
 
-/// ============================================
-/// 第二部分：函数与所有权
-/// ============================================
+// ============================================
+// 第二部分：函数与所有权
+// ============================================
 
 // 这个函数会获取所有权
 fn take_ownership(book: Book) {
@@ -96,6 +171,7 @@ fn take_ownership(book: Book) {
 }
 
 // 这个函数会返回所有权
+#[allow(clippy::let_and_return)] // 保留 let 绑定，突出“返回所有权”这一步
 fn give_ownership() -> Book {
     let book = Book::new("Rust 编程", "Steve Klabnik", 500);
     book // 返回所有权给调用者
@@ -144,9 +220,9 @@ fn demo_ownership_functions() {
     }
 }
 
-/// ============================================
-/// 第三部分：引用和借用
-/// ============================================
+// ============================================
+// 第三部分：引用和借用
+// ============================================
 
 // 不可变引用 - 只读借用
 fn read_book(book: &Book) {
@@ -238,9 +314,9 @@ fn demo_references_borrowing() {
     }
 }
 
-/// ============================================
-/// 第四部分：常见陷阱和解决方案
-/// ============================================
+// ============================================
+// 第四部分：常见陷阱和解决方案
+// ============================================
 
 fn demo_common_pitfalls() {
     println!("\n📚 第四部分：常见陷阱和解决方案");
@@ -316,43 +392,149 @@ fn demo_common_pitfalls() {
     }
 }
 
-/// ============================================
-/// 第五部分：实战示例 - 图书管理系统
-/// ============================================
+// ============================================
+// 第五部分：实战示例 - 图书管理系统
+// ============================================
 
-struct Library {
-    books: Vec<Book>,
+#[derive(Debug, Serialize, Deserialize)]
+struct Library<T = Book> {
+    books: Vec<T>,
     name: String,
+    // 书名 -> 当前借阅人
+    #[serde(default)]
+    loans: HashMap<String, String>,
+    // 书名 -> 预约排队的读者（先到先得）
+    #[serde(default)]
+    holds: HashMap<String, VecDeque<String>>,
+    // 自上次保存以来是否被修改过（不参与序列化和比较）
+    #[serde(skip)]
+    dirty: bool,
+    // 藏书变更的广播发送端（不参与序列化，加载后重新创建）
+    #[serde(skip, default = "new_event_channel")]
+    events: broadcast::Sender<LibraryEvent>,
+}
+
+/// 藏书变更事件，携带受影响的书名
+#[derive(Debug, Clone, PartialEq)]
+enum LibraryEvent {
+    Added(String),
+    Removed(String),
+    Updated(String),
 }
 
-impl Library {
+fn new_event_channel() -> broadcast::Sender<LibraryEvent> {
+    broadcast::channel(64).0
+}
+
+/// 图书馆某一时刻的藏书、借阅和预约副本（克隆得到，和原图书馆互不影响）
+#[derive(Debug, Clone, PartialEq)]
+struct LibrarySnapshot<T = Book> {
+    books: Vec<T>,
+    loans: HashMap<String, String>,
+    holds: HashMap<String, VecDeque<String>>,
+}
+
+impl<T: PartialEq> PartialEq for Library<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.books == other.books
+    }
+}
+
+impl<T: Catalogable> Library<T> {
     fn new(name: &str) -> Self {
         Library {
             books: Vec::new(),
             name: name.to_string(),
+            loans: HashMap::new(),
+            holds: HashMap::new(),
+            dirty: false,
+            events: new_event_channel(),
         }
     }
     
     // 获取所有权并添加书籍
-    fn add_book(&mut self, book: Book) {
-        println!("   ➕ 添加书籍: {}", book.title);
+    fn add_book(&mut self, book: T) {
+        println!("   ➕ 添加书籍: {}", book.title());
+        self.publish(LibraryEvent::Added(book.title().to_string()));
         self.books.push(book);
+        self.dirty = true;
+    }
+    
+    // 订阅藏书变更：add_book、remove_book、retain、restore 和 update_book_pages 会推送事件
+    //
+    // 通过 find_book_mut 或 &mut Library 迭代拿到 &mut T 后的原地修改无法被观察到，
+    // 不会产生 Updated 事件（只会设置 dirty）；需要通知订阅者时请走上面这些方法。
+    // 返回的 Stream 拥有自己的 broadcast 接收端，不借用 self；
+    // 订阅者跟不上而错过的事件会被跳过，Library 被 drop 后 Stream 结束。
+    fn subscribe_changes(&self) -> impl Stream<Item = LibraryEvent> {
+        stream::unfold(self.events.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+    
+    // 没有订阅者时发送会失败，直接忽略即可
+    fn publish(&self, event: LibraryEvent) {
+        let _ = self.events.send(event);
+    }
+    
+    // 读取并清除修改标记
+    fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+    
+    // 借用：克隆当前藏书及借阅、预约状态作为快照，用于批量修改前“开启事务”
+    fn snapshot(&self) -> LibrarySnapshot<T>
+    where
+        T: Clone,
+    {
+        LibrarySnapshot {
+            books: self.books.clone(),
+            loans: self.loans.clone(),
+            holds: self.holds.clone(),
+        }
+    }
+    
+    // 获取快照的所有权，整体替换当前藏书、借阅和预约，实现“回滚”
+    // 按书名对比前后藏书推送事件：消失的是 Removed，新出现的是 Added，两边都有的保守地视为 Updated
+    fn restore(&mut self, snap: LibrarySnapshot<T>) {
+        let old = std::mem::replace(&mut self.books, snap.books);
+        self.loans = snap.loans;
+        self.holds = snap.holds;
+        for book in &old {
+            if !self.books.iter().any(|b| b.title() == book.title()) {
+                self.publish(LibraryEvent::Removed(book.title().to_string()));
+            }
+        }
+        for book in &self.books {
+            let event = if old.iter().any(|b| b.title() == book.title()) {
+                LibraryEvent::Updated(book.title().to_string())
+            } else {
+                LibraryEvent::Added(book.title().to_string())
+            };
+            self.publish(event);
+        }
+        self.dirty = true;
     }
     
     // 借用：不可变引用查找书籍
-    fn find_book(&self, title: &str) -> Option<&Book> {
-        self.books.iter().find(|book| book.title == title)
+    fn find_book(&self, title: &str) -> Option<&T> {
+        self.books.iter().find(|book| book.title() == title)
     }
     
-    // 借用：可变引用更新书籍
-    fn update_book_pages(&mut self, title: &str, new_pages: u32) -> bool {
-        if let Some(book) = self.books.iter_mut().find(|book| book.title == title) {
-            println!("   ✏️  更新 '{}' 的页数: {} -> {}", book.title, book.pages, new_pages);
-            book.pages = new_pages;
-            true
-        } else {
-            false
+    // 借用：返回书籍的可变引用，调用者可以直接修改
+    fn find_book_mut(&mut self, title: &str) -> Option<&mut T> {
+        let book = self.books.iter_mut().find(|book| book.title() == title);
+        // 借出了可变引用，就保守地认为图书馆可能被修改
+        if book.is_some() {
+            self.dirty = true;
         }
+        book
     }
     
     // 借用：不可变引用列出所有书籍
@@ -363,10 +545,215 @@ impl Library {
         }
     }
     
+    // 可变借用：只保留满足条件的书籍，返回被移除的数量
+    fn retain<F: Fn(&T) -> bool>(&mut self, pred: F) -> usize {
+        let (kept, removed): (Vec<T>, Vec<T>) =
+            std::mem::take(&mut self.books).into_iter().partition(|book| pred(book));
+        self.books = kept;
+        for book in &removed {
+            self.publish(LibraryEvent::Removed(book.title().to_string()));
+        }
+        if !removed.is_empty() {
+            self.dirty = true;
+        }
+        removed.len()
+    }
+    
+    // 移除一本书，把它的所有权交还给调用者；借阅和预约记录一并清除
+    fn remove_book(&mut self, title: &str) -> Option<T> {
+        let index = self.books.iter().position(|book| book.title() == title)?;
+        let book = self.books.remove(index);
+        self.loans.remove(title);
+        self.holds.remove(title);
+        self.publish(LibraryEvent::Removed(title.to_string()));
+        self.dirty = true;
+        Some(book)
+    }
+    
+    // 借出一本书：书必须存在且未被借出
+    fn borrow_book(&mut self, title: &str, patron: &str) -> Result<(), &'static str> {
+        if self.find_book(title).is_none() {
+            return Err("没有这本书");
+        }
+        if self.loans.contains_key(title) {
+            return Err("这本书已被借出，请预约");
+        }
+        self.loans.insert(title.to_string(), patron.to_string());
+        self.dirty = true;
+        Ok(())
+    }
+    
+    // 预约一本已借出的书，返回在队列中的位置（从 1 开始）
+    fn reserve(&mut self, title: &str, patron: &str) -> Result<usize, &'static str> {
+        if !self.loans.contains_key(title) {
+            return Err("这本书没有被借出，可以直接借阅");
+        }
+        let queue = self.holds.entry(title.to_string()).or_default();
+        queue.push_back(patron.to_string());
+        self.dirty = true;
+        Ok(queue.len())
+    }
+    
+    // 归还一本书：有人预约时自动转交给队首的读者，并让馆藏记录交接，返回新的借阅人
+    fn return_book(&mut self, title: &str) -> Option<String> {
+        let previous = self.loans.remove(title)?;
+        self.dirty = true;
+        let next = self.holds.get_mut(title)?.pop_front()?;
+        
+        self.loans.insert(title.to_string(), next.clone());
+        if let Some(book) = self.books.iter_mut().find(|b| b.title() == title) {
+            book.record_handover(&previous, &next);
+        }
+        Some(next)
+    }
+    
+    // 借用：当前借阅人
+    fn holder(&self, title: &str) -> Option<&str> {
+        self.loans.get(title).map(String::as_str)
+    }
+    
+    // 借用：仍在排队的预约者
+    fn waitlist(&self, title: &str) -> Vec<&str> {
+        self.holds
+            .get(title)
+            .map(|q| q.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+    
     // 返回书籍数量（不需要借用self）
     fn book_count(&self) -> usize {
         self.books.len()
     }
+    
+    // 借用：把整个图书馆序列化为紧凑的二进制格式
+    fn to_bincode(&self) -> io::Result<Vec<u8>>
+    where
+        T: Serialize,
+    {
+        bincode::serialize(self).map_err(io::Error::other)
+    }
+    
+    // 借用：序列化后写入文件
+    fn save_bincode(&self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        fs::write(path, self.to_bincode()?)
+    }
+    
+    // 从文件读取并反序列化，返回一个全新的、拥有所有权的 Library
+    fn load_bincode(path: impl AsRef<Path>) -> io::Result<Self>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let bytes = fs::read(path)?;
+        bincode::deserialize(&bytes).map_err(io::Error::other)
+    }
+    
+    // 借用：序列化为便于阅读的 JSON 写入文件，体积比二进制格式大
+    fn save_json(&self, path: impl AsRef<Path>) -> io::Result<()>
+    where
+        T: Serialize,
+    {
+        fs::write(path, serde_json::to_vec(self)?)
+    }
+    
+    // 从 JSON 文件加载，同样得到一个全新的 Library
+    fn load_json(path: impl AsRef<Path>) -> io::Result<Self>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        let bytes = fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+// 借用的种类决定迭代器元素的类型：&Library 产出 &T，&mut Library 产出 &mut T
+impl<'a, T> IntoIterator for &'a Library<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        self.books.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut Library<T> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        // 和 find_book_mut 一样，借出了可变引用就认为可能被修改
+        self.dirty = true;
+        self.books.iter_mut()
+    }
+}
+
+// 只有书才有页数和作者，这些方法只对 Library<Book> 提供
+impl Library<Book> {
+    // 借用：可变引用更新书籍
+    fn update_book_pages(&mut self, title: &str, new_pages: u32) -> bool {
+        if let Some(book) = self.find_book_mut(title) {
+            println!("   ✏️  更新 '{}' 的页数: {} -> {}", book.title, book.pages, new_pages);
+            book.set_pages(new_pages);
+            self.publish(LibraryEvent::Updated(title.to_string()));
+            true
+        } else {
+            false
+        }
+    }
+    
+    // 借用：统计每位作者的书籍数，返回书最多的作者（并列时取名字靠前的）
+    fn most_prolific_author(&self) -> Option<(String, usize)> {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for book in &self.books {
+            *counts.entry(book.author.as_str()).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(author, count)| (author.to_string(), count))
+    }
+}
+
+/// 组合查询构建器：每个条件都是可选的，设置的条件之间是“且”的关系
+#[derive(Debug, Default)]
+struct Query {
+    author: Option<String>,
+    min_pages: Option<u32>,
+    tag: Option<String>,
+}
+
+impl Query {
+    fn new() -> Self {
+        Query::default()
+    }
+    
+    fn author(mut self, author: &str) -> Self {
+        self.author = Some(author.to_string());
+        self
+    }
+    
+    fn min_pages(mut self, pages: u32) -> Self {
+        self.min_pages = Some(pages);
+        self
+    }
+    
+    fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_string());
+        self
+    }
+    
+    fn matches(&self, book: &Book) -> bool {
+        self.author.as_ref().is_none_or(|a| &book.author == a)
+            && self.min_pages.is_none_or(|p| book.pages >= p)
+            && self.tag.as_ref().is_none_or(|t| book.tags.contains(t))
+    }
+    
+    // 返回的引用借用自 lib，生命周期 'a 与 Query 本身无关
+    fn execute<'a>(&self, lib: &'a Library) -> Vec<&'a Book> {
+        lib.into_iter().filter(|book| self.matches(book)).collect()
+    }
 }
 
 fn demo_practical_example() {
@@ -376,9 +763,9 @@ fn demo_practical_example() {
     let mut library = Library::new("清华大学图书馆");
     
     println!("\n1️⃣  添加书籍（转移所有权）：");
-    let book1 = Book::new("算法导论", "Thomas H. Cormen", 1200);
-    let book2 = Book::new("深入理解计算机系统", "Randal E. Bryant", 1000);
-    let book3 = Book::new("代码大全", "Steve McConnell", 960);
+    let book1 = Book::new("算法导论", "Thomas H. Cormen", 1200).with_tags(&["算法"]);
+    let book2 = Book::new("深入理解计算机系统", "Randal E. Bryant", 1000).with_tags(&["系统"]);
+    let book3 = Book::new("代码大全", "Steve McConnell", 960).with_tags(&["工程"]);
     
     library.add_book(book1); // book1 所有权转移到 library
     library.add_book(book2);
@@ -402,12 +789,403 @@ fn demo_practical_example() {
     println!("\n6️⃣  统计信息（不可变借用）：");
     println!("   📊 图书总数: {}", library.book_count());
     
+    println!("\n7️⃣  组合查询（结果借用自 library）：");
+    let queries = [
+        ("页数 ≥ 1000", Query::new().min_pages(1000)),
+        ("Cormen 的算法书", Query::new().author("Thomas H. Cormen").tag("算法")),
+        ("页数 ≥ 1000 的工程书", Query::new().min_pages(1000).tag("工程")),
+    ];
+    for (label, query) in &queries {
+        let titles: Vec<_> = query.execute(&library).iter().map(|b| b.title.as_str()).collect();
+        println!("   🔎 {}: {:?}", label, titles);
+    }
+    
+    println!("\n8️⃣  修改历史（通过可变引用修改，再借出历史切片）：");
+    if let Some(book) = library.find_book_mut("算法导论") {
+        book.set_pages(1312);
+    }
+    if let Some(book) = library.find_book("算法导论") {
+        for rev in book.history() {
+            println!("   📝 {}: {} -> {}", rev.field, rev.old, rev.new);
+        }
+    }
+    
+    println!("\n9️⃣  快照与回滚（克隆实现事务）：");
+    let snap = library.snapshot();
+    let before = snap.clone();
+    library.add_book(Book::new("临时书籍", "无名氏", 10));
+    library.update_book_pages("代码大全", 1);
+    println!("   ⚠️  批量修改出错，回滚到快照");
+    library.restore(snap); // snap 的所有权转移给 library
+    println!("   🔙 回滚后与修改前{}（{} 本书）",
+             if library.snapshot() == before { "完全一致" } else { "不一致" },
+             library.book_count());
+    
+    println!("\n🔟 作者统计（HashMap 计数后取最大值）：");
+    library.add_book(Book::new("算法（第四版）", "Thomas H. Cormen", 900));
+    if let Some((author, count)) = library.most_prolific_author() {
+        println!("   🏆 藏书最多的作者: {}（{} 本）", author, count);
+    }
+    println!("   📭 空图书馆: {:?}", Library::new("新馆").most_prolific_author());
+    
+    println!("\n1️⃣1️⃣ 批量删除（retain 只保留满足条件的书）：");
+    let removed = library.retain(|book| book.pages > 950);
+    let titles: Vec<_> = library.books.iter().map(|b| b.title.as_str()).collect();
+    println!("   🗑️  移除了 {} 本页数 ≤ 950 的书，剩余: {:?}", removed, titles);
+    
+    println!("\n1️⃣2️⃣ 预约排队（归还时自动转交）：");
+    library.borrow_book("代码大全", "张三").unwrap();
+    for patron in ["李四", "王五"] {
+        if let Ok(position) = library.reserve("代码大全", patron) {
+            println!("   📝 {} 预约《代码大全》，排在第 {} 位", patron, position);
+        }
+    }
+    if let Some(next) = library.return_book("代码大全") {
+        println!("   🔁 张三归还，自动转交给 {}", next);
+    }
+    println!("   📖 当前借阅人: {:?}，仍在排队: {:?}",
+             library.holder("代码大全"), library.waitlist("代码大全"));
+    
+    println!("\n1️⃣3️⃣ 移除书籍（所有权从 Vec 中转移回调用者）：");
+    let before = library.book_count();
+    if let Some(mut book) = library.remove_book("算法导论") {
+        // book 不再属于 library，而是归这里的变量所有，可以随意修改
+        book.set_pages(1320);
+        println!("   📤 取回: {}（修改记录 {} 条）", book, book.history().len());
+        let gift = book; // 还可以继续转移所有权
+        println!("   🎁 转赠: {}", gift);
+    }
+    println!("   🔍 再次移除: {:?}", library.remove_book("算法导论").map(|b| b.title));
+    println!("   {} 图书数 {} -> {}，library 中已找不到《算法导论》",
+             if library.find_book("算法导论").is_none() && library.book_count() == before - 1 { "✅" } else { "❌" },
+             before, library.book_count());
+    
+    println!("\n1️⃣4️⃣ 直接迭代图书馆（&mut library 产出 &mut Book）：");
+    let total_before: u32 = library.into_iter().map(|b| b.pages).sum();
+    for book in &mut library {
+        book.set_pages(book.pages + 10);
+    }
+    for book in &library {
+        println!("   📖 {}", book);
+    }
+    let total_after: u32 = library.into_iter().map(|b| b.pages).sum();
+    println!("   {} 每本书都加了 10 页，总页数 {} -> {}",
+             if total_after == total_before + 10 * library.book_count() as u32 { "✅" } else { "❌" },
+             total_before, total_after);
+    
     println!("\n✅ 图书管理系统演示完成！");
 }
 
-/// ============================================
-/// 第六部分：关键概念总结
-/// ============================================
+// ============================================
+// 第六部分：持久化 - 序列化与所有权
+// ============================================
+
+/// 在系统临时目录下生成一个不会与其他进程或其他调用冲突的文件路径
+fn unique_temp_path(stem: &str, ext: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("{}-{}-{}.{}", stem, std::process::id(), n, ext))
+}
+
+fn demo_persistence() {
+    println!("\n📚 第六部分：持久化 - 序列化与所有权");
+    println!("{}", "=".repeat(60));
+    
+    let mut library = Library::new("城市图书馆");
+    library.add_book(Book::new("算法导论", "Thomas H. Cormen", 1200));
+    library.add_book(Book::new("代码大全", "Steve McConnell", 960));
+    
+    let path = unique_temp_path("library", "bin");
+    let json_path = unique_temp_path("library", "json");
+    
+    println!("\n1️⃣  保存为二进制和 JSON（只需要不可变借用）：");
+    if let Err(e) = library.save_bincode(&path).and_then(|()| library.save_json(&json_path)) {
+        println!("   ❌ 保存失败: {}", e);
+        return;
+    }
+    let size_of = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    println!("   💾 二进制: {}（{} 字节）", path.display(), size_of(&path));
+    println!("   💾 JSON:   {}（{} 字节）", json_path.display(), size_of(&json_path));
+    
+    println!("\n2️⃣  从文件加载（得到一个全新的所有者）：");
+    match (Library::load_bincode(&path), Library::load_json(&json_path)) {
+        (Ok(from_bin), Ok(from_json)) => {
+            from_bin.list_books();
+            println!("   {} 两种格式加载的图书馆都与原图书馆相同",
+                     if from_bin == library && from_json == library { "✅" } else { "❌" });
+        }
+        (Err(e), _) | (_, Err(e)) => println!("   ❌ 加载失败: {}", e),
+    }
+    
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(&json_path);
+}
+
+// ============================================
+// 第七部分：异步防抖自动保存 - 共享所有权
+// ============================================
+
+/// 防抖自动保存器
+///
+/// 图书馆放在 Arc<Mutex<_>> 中由多方共享。每次通过 edit() 修改后通知后台任务；
+/// 后台任务在最后一次修改之后再安静 quiet 时长才保存一次，
+/// 把一连串快速的修改合并成一次写盘。
+///
+/// flush() 立即保存尚未写盘的修改；shutdown() 保存后停止后台任务并等待它退出。
+/// 直接 drop 时后台任务同样会先保存再退出，只是没有人等待它完成。
+struct AutoSaver {
+    library: Arc<Mutex<Library>>,
+    notify: Arc<Notify>,
+    saves: Arc<AtomicUsize>,
+    // 发送 flush 请求，保存完成后通过 oneshot 回复；发送端关闭即表示停止
+    flushes: mpsc::Sender<oneshot::Sender<()>>,
+    task: JoinHandle<()>,
+}
+
+impl AutoSaver {
+    fn spawn(library: Arc<Mutex<Library>>, path: PathBuf, quiet: Duration) -> Self {
+        let notify = Arc::new(Notify::new());
+        let saves = Arc::new(AtomicUsize::new(0));
+        let (flushes, mut flush_rx) = mpsc::channel::<oneshot::Sender<()>>(1);
+        
+        let (lib, changed, count) = (library.clone(), notify.clone(), saves.clone());
+        let task = tokio::spawn(async move {
+            // 防抖截止时间：None 表示没有等待保存的修改
+            let mut deadline: Option<Instant> = None;
+            loop {
+                tokio::select! {
+                    // 安静期内又有修改就重新计时
+                    _ = changed.notified() => deadline = Some(Instant::now() + quiet),
+                    _ = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        deadline = None;
+                        Self::save_if_dirty(&lib, &path, &count).await;
+                    }
+                    request = flush_rx.recv() => {
+                        deadline = None;
+                        Self::save_if_dirty(&lib, &path, &count).await;
+                        match request {
+                            Some(done) => {
+                                let _ = done.send(());
+                            }
+                            // AutoSaver 被关闭或 drop：最后一次保存已完成，退出
+                            None => break,
+                        }
+                    }
+                }
+            }
+        });
+        
+        AutoSaver { library, notify, saves, flushes, task }
+    }
+    
+    // 只在锁内序列化出快照，写盘时不持有锁，edit() 不会被磁盘 IO 阻塞
+    async fn save_if_dirty(lib: &Mutex<Library>, path: &Path, count: &AtomicUsize) {
+        let (bytes, books) = {
+            let mut library = lib.lock().unwrap();
+            if !library.take_dirty() {
+                return;
+            }
+            match library.to_bincode() {
+                Ok(bytes) => (bytes, library.book_count()),
+                Err(e) => {
+                    library.dirty = true;
+                    println!("   ❌ 自动保存失败: {}", e);
+                    return;
+                }
+            }
+        };
+        
+        match tokio::fs::write(path, bytes).await {
+            Ok(()) => {
+                count.fetch_add(1, Ordering::SeqCst);
+                println!("   💾 自动保存完成（{} 本书）", books);
+            }
+            Err(e) => {
+                // 保存失败时恢复修改标记，下一次修改后会连同这次的内容一起重试
+                lib.lock().unwrap().dirty = true;
+                println!("   ❌ 自动保存失败: {}", e);
+            }
+        }
+    }
+    
+    /// 不等安静期结束，立即保存尚未写盘的修改
+    async fn flush(&self) {
+        let (done_tx, done_rx) = oneshot::channel();
+        if self.flushes.send(done_tx).await.is_ok() {
+            let _ = done_rx.await;
+        }
+    }
+    
+    /// 保存尚未写盘的修改，然后停止后台任务
+    async fn shutdown(self) {
+        let AutoSaver { flushes, task, .. } = self;
+        drop(flushes);
+        let _ = task.await;
+    }
+    
+    // 在锁内借出 &mut Library 给闭包，修改完成后通知保存任务
+    fn edit<R>(&self, f: impl FnOnce(&mut Library) -> R) -> R {
+        let result = f(&mut self.library.lock().unwrap());
+        self.notify.notify_one();
+        result
+    }
+    
+    fn save_count(&self) -> usize {
+        self.saves.load(Ordering::SeqCst)
+    }
+}
+
+fn demo_auto_save() {
+    println!("\n📚 第七部分：异步防抖自动保存 - 共享所有权");
+    println!("{}", "=".repeat(60));
+    
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let path = unique_temp_path("library_autosave", "bin");
+        let library = Arc::new(Mutex::new(Library::new("社区图书馆")));
+        let saver = AutoSaver::spawn(library, path.clone(), Duration::from_millis(300));
+        
+        println!("\n1️⃣  连续快速修改 5 次（每次间隔 50ms）：");
+        for i in 1..=5 {
+            saver.edit(|lib| lib.add_book(Book::new(&format!("新书 {}", i), "匿名", 100 * i)));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        
+        println!("\n2️⃣  等待安静期结束：");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        if saver.save_count() == 1 {
+            println!("   ✅ 5 次修改只触发了 1 次保存");
+        } else {
+            println!("   ❌ 预期 1 次保存，实际 {} 次", saver.save_count());
+        }
+        
+        println!("\n3️⃣  修改后立即 flush，不等安静期：");
+        saver.edit(|lib| lib.add_book(Book::new("新书 6", "匿名", 600)));
+        saver.flush().await;
+        
+        println!("\n4️⃣  再修改一次，直接关闭：");
+        saver.edit(|lib| lib.add_book(Book::new("新书 7", "匿名", 700)));
+        saver.shutdown().await;
+        match Library::<Book>::load_bincode(&path) {
+            Ok(saved) if saved.book_count() == 7 => println!("   ✅ 关闭前保存了最后一次修改（7 本书）"),
+            Ok(saved) => println!("   ❌ 文件中只有 {} 本书，最后的修改丢失了", saved.book_count()),
+            Err(e) => println!("   ❌ 加载失败: {}", e),
+        }
+        
+        let _ = fs::remove_file(&path);
+    });
+}
+
+// ============================================
+// 第八部分：变更通知流 - 借出 Stream 而不借出 self
+// ============================================
+
+fn demo_change_stream() {
+    println!("\n📚 第八部分：变更通知流 - 借出 Stream 而不借出 self");
+    println!("{}", "=".repeat(60));
+    
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut library = Library::new("街区图书馆");
+        // Stream 不借用 library，所以之后仍然可以可变借用 library
+        let changes = library.subscribe_changes();
+        
+        println!("\n1️⃣  订阅后添加、更新、删除，再回滚：");
+        library.add_book(Book::new("人月神话", "Frederick Brooks", 320));
+        library.update_book_pages("人月神话", 336);
+        let snap = library.snapshot();
+        library.retain(|book| book.title != "人月神话");
+        library.restore(snap); // 回滚让删掉的书重新出现，推送 Added
+        drop(library); // 发送端随 library 一起释放，Stream 随之结束
+        
+        println!("\n2️⃣  按顺序收到的事件：");
+        let events: Vec<LibraryEvent> = changes.collect().await;
+        for event in &events {
+            println!("   🔔 {:?}", event);
+        }
+        let expected = [
+            LibraryEvent::Added("人月神话".to_string()),
+            LibraryEvent::Updated("人月神话".to_string()),
+            LibraryEvent::Removed("人月神话".to_string()),
+            LibraryEvent::Added("人月神话".to_string()),
+        ];
+        println!("   {} 事件顺序与修改顺序一致", if events == expected { "✅" } else { "❌" });
+    });
+}
+
+// ============================================
+// 第九部分：泛型图书馆 - 同样的借用规则，不同的馆藏
+// ============================================
+
+/// 另一种馆藏：只要实现 Catalogable，就能放进 Library<T>
+#[derive(Debug, Clone, PartialEq)]
+struct Dvd {
+    title: String,
+    director: String,
+    minutes: u32,
+}
+
+impl Dvd {
+    fn new(title: &str, director: &str, minutes: u32) -> Self {
+        Dvd {
+            title: title.to_string(),
+            director: director.to_string(),
+            minutes,
+        }
+    }
+}
+
+impl fmt::Display for Dvd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "《{}》导演: {}, {}分钟", self.title, self.director, self.minutes)
+    }
+}
+
+impl Catalogable for Dvd {
+    fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+fn demo_generic_library() {
+    println!("\n📚 第九部分：泛型图书馆 - 同样的借用规则，不同的馆藏");
+    println!("{}", "=".repeat(60));
+    
+    let mut library: Library<Dvd> = Library::new("影音资料室");
+    
+    println!("\n1️⃣  添加 DVD（所有权同样转移进 library）：");
+    let dvd = Dvd::new("千与千寻", "宫崎骏", 125);
+    library.add_book(dvd);
+    // ❌ dvd 在这里已经失效
+    library.add_book(Dvd::new("星际穿越", "克里斯托弗·诺兰", 169));
+    library.list_books();
+    
+    println!("\n2️⃣  查找与可变借用（find_book / find_book_mut 与书籍完全相同）：");
+    if let Some(dvd) = library.find_book_mut("星际穿越") {
+        dvd.minutes = 170;
+    }
+    if let Some(dvd) = library.find_book("星际穿越") {
+        println!("   🔍 找到: {}", dvd);
+    }
+    
+    println!("\n3️⃣  借阅与预约（Dvd 不记录交接历史，使用 trait 的默认实现）：");
+    library.borrow_book("千与千寻", "张三").unwrap();
+    library.reserve("千与千寻", "李四").unwrap();
+    let next = library.return_book("千与千寻");
+    println!("   🔁 张三归还，转交给 {:?}", next);
+    
+    // update_book_pages 只为 Library<Book> 实现，下面这行会编译错误
+    // library.update_book_pages("千与千寻", 100);
+    
+    let ok = library.book_count() == 2
+        && library.holder("千与千寻") == Some("李四")
+        && library.find_book("星际穿越").map(|d| d.minutes) == Some(170);
+    println!("   {} Library<Dvd> 的增删查借与 Library<Book> 行为一致", if ok { "✅" } else { "❌" });
+}
+
+// ============================================
+// 第十部分：关键概念总结
+// ============================================
 
 fn print_summary() {
     println!("\n📚 关键概念总结");
@@ -429,6 +1207,9 @@ fn print_summary() {
     println!("   • 不可变引用：只需要读取数据");
     println!("   • 可变引用：需要修改数据");
     println!("   • Clone：需要保留原数据又需要新副本");
+    println!("   • 从集合中移除：所有权随返回值交还调用者");
+    println!("   • for x in &c 得到 &T，for x in &mut c 得到 &mut T");
+    println!("   • 泛型 + trait：同一套借用规则适用于任何馆藏类型");
     
     println!("\n🎯 记忆口诀：");
     println!("   📦 所有权：一个值一个主人");
@@ -437,9 +1218,9 @@ fn print_summary() {
     println!("   ⏰ 生命周期：引用不能活过主人");
 }
 
-/// ============================================
-/// 主函数
-/// ============================================
+// ============================================
+// 主函数
+// ============================================
 
 fn main() {
     println!("🎓 Rust 所有权、引用、借用完整教程");
@@ -451,6 +1232,10 @@ fn main() {
     demo_references_borrowing();
     demo_common_pitfalls();
     demo_practical_example();
+    demo_persistence();
+    demo_auto_save();
+    demo_change_stream();
+    demo_generic_library();
     print_summary();
     
     println!("\n{}", "=".repeat(60));
@@ -458,3 +1243,169 @@ fn main() {
     println!("💡 建议：多运行几次，尝试取消注释那些会报错的代码，");
     println!("   观察编译器的错误信息，这有助于加深理解。");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn sample_library() -> Library {
+        let mut library = Library::new("城市图书馆");
+        library.add_book(Book::new("算法导论", "Thomas H. Cormen", 1200).with_tags(&["算法", "经典"]));
+        library.add_book(Book::new("代码大全", "Steve McConnell", 960).with_tags(&["工程", "经典"]));
+        library.update_book_pages("代码大全", 914);
+        library.add_book(Book::new("算法概论", "Sanjoy Dasgupta", 336).with_tags(&["算法"]));
+        library.add_book(Book::new("重构", "Martin Fowler", 448).with_tags(&["工程"]));
+        library
+    }
+    
+    #[test]
+    fn bincode_round_trip_is_lossless_and_smaller_than_json() {
+        let library = sample_library();
+        let (bin, json) = (unique_temp_path("library", "bin"), unique_temp_path("library", "json"));
+        library.save_bincode(&bin).unwrap();
+        library.save_json(&json).unwrap();
+        
+        let from_bin = Library::load_bincode(&bin).unwrap();
+        let from_json = Library::load_json(&json).unwrap();
+        let sizes = (fs::metadata(&bin).unwrap().len(), fs::metadata(&json).unwrap().len());
+        let _ = fs::remove_file(&bin);
+        let _ = fs::remove_file(&json);
+        
+        assert_eq!(from_bin, library);
+        assert_eq!(from_bin.books[1].history(), library.books[1].history());
+        assert_eq!(from_json, library);
+        assert!(sizes.0 < sizes.1, "bincode {} 字节，JSON {} 字节", sizes.0, sizes.1);
+    }
+    
+    fn titles(books: Vec<&Book>) -> Vec<&str> {
+        books.into_iter().map(|book| book.title.as_str()).collect()
+    }
+    
+    #[test]
+    fn query_combines_filters_with_and() {
+        let library = sample_library();
+        assert_eq!(titles(Query::new().execute(&library)).len(), 4);
+        assert_eq!(titles(Query::new().tag("算法").min_pages(500).execute(&library)), ["算法导论"]);
+        assert_eq!(titles(Query::new().tag("经典").min_pages(900).execute(&library)), ["算法导论", "代码大全"]);
+        assert_eq!(titles(Query::new().author("Martin Fowler").tag("工程").execute(&library)), ["重构"]);
+    }
+    
+    #[test]
+    fn query_with_no_matches_is_empty() {
+        let library = sample_library();
+        assert!(Query::new().author("Martin Fowler").tag("算法").execute(&library).is_empty());
+        assert!(Query::new().min_pages(5000).execute(&library).is_empty());
+    }
+    
+    #[test]
+    fn each_page_update_records_a_revision() {
+        let mut library = sample_library();
+        assert!(library.update_book_pages("重构", 460));
+        assert!(library.update_book_pages("重构", 480));
+        assert!(!library.update_book_pages("不存在的书", 1));
+        
+        let history = library.find_book("重构").unwrap().history();
+        let changes: Vec<_> = history.iter().map(|r| (r.field.as_str(), r.old.as_str(), r.new.as_str())).collect();
+        assert_eq!(changes, [("pages", "448", "460"), ("pages", "460", "480")]);
+        assert!(history[0].at <= history[1].at);
+    }
+    
+    #[tokio::test(start_paused = true)]
+    async fn auto_saver_coalesces_rapid_edits_and_saves_on_shutdown() {
+        let path = unique_temp_path("library_autosave_test", "bin");
+        let saver = AutoSaver::spawn(Arc::new(Mutex::new(Library::new("测试"))), path.clone(), Duration::from_millis(300));
+        
+        for i in 1..=5 {
+            saver.edit(|lib| lib.add_book(Book::new(&format!("书 {}", i), "匿名", 100)));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(saver.save_count(), 0, "安静期内不应保存");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(saver.save_count(), 1);
+        
+        // flush 不等安静期
+        saver.edit(|lib| lib.add_book(Book::new("书 6", "匿名", 100)));
+        saver.flush().await;
+        assert_eq!(saver.save_count(), 2);
+        
+        // 没有修改时关闭不会多写一次；有修改时关闭前一定写盘
+        saver.edit(|lib| lib.add_book(Book::new("书 7", "匿名", 100)));
+        saver.shutdown().await;
+        assert_eq!(Library::<Book>::load_bincode(&path).unwrap().book_count(), 7);
+        let _ = fs::remove_file(&path);
+    }
+    
+    #[test]
+    fn restore_rolls_back_every_edit_since_snapshot() {
+        let mut library = sample_library();
+        library.borrow_book("代码大全", "小明").unwrap();
+        library.reserve("代码大全", "小红").unwrap();
+        // 快照本身可比较，修订记录里的时间戳也会原样保留
+        let before = library.snapshot();
+        let snap = library.snapshot();
+        
+        library.add_book(Book::new("人月神话", "Frederick Brooks", 320));
+        library.update_book_pages("算法导论", 1300);
+        library.remove_book("代码大全");
+        assert_eq!(library.holder("代码大全"), None);
+        assert_ne!(library.snapshot(), before);
+        
+        library.restore(snap);
+        assert_eq!(library.snapshot(), before);
+        // 删除书时清掉的借阅人和预约队列也一起回来了
+        assert_eq!(library.holder("代码大全"), Some("小明"));
+        assert_eq!(library.waitlist("代码大全"), ["小红"]);
+    }
+    
+    #[test]
+    fn most_prolific_author_counts_books_per_author() {
+        let mut library = Library::new("作者统计");
+        assert_eq!(library.most_prolific_author(), None);
+        
+        for (title, author) in [("一", "A"), ("二", "B"), ("三", "A"), ("四", "A")] {
+            library.add_book(Book::new(title, author, 100));
+        }
+        assert_eq!(library.most_prolific_author(), Some(("A".to_string(), 3)));
+    }
+    
+    #[test]
+    fn retain_removes_short_books_and_reports_count() {
+        let mut library = sample_library();
+        library.add_book(Book::new("小王子", "Antoine de Saint-Exupéry", 96));
+        library.add_book(Book::new("人月神话", "Frederick Brooks", 320));
+        
+        assert_eq!(library.retain(|book| book.pages > 300), 1);
+        let surviving: Vec<&str> = library.books.iter().map(|book| book.title.as_str()).collect();
+        assert_eq!(surviving, ["算法导论", "代码大全", "算法概论", "重构", "人月神话"]);
+        assert_eq!(library.retain(|book| book.pages > 300), 0);
+    }
+    
+    #[test]
+    fn returned_book_goes_to_first_reserver_and_queue_advances() {
+        let mut library = sample_library();
+        library.borrow_book("算法导论", "小明").unwrap();
+        assert!(library.borrow_book("算法导论", "小红").is_err());
+        assert_eq!(library.reserve("算法导论", "小红"), Ok(1));
+        assert_eq!(library.reserve("算法导论", "小刚"), Ok(2));
+        
+        assert_eq!(library.return_book("算法导论"), Some("小红".to_string()));
+        assert_eq!(library.holder("算法导论"), Some("小红"));
+        assert_eq!(library.waitlist("算法导论"), ["小刚"]);
+        
+        let handover = library.find_book("算法导论").unwrap().revisions.last().unwrap();
+        assert_eq!((handover.field.as_str(), handover.old.as_str(), handover.new.as_str()), ("holder", "小明", "小红"));
+    }
+    
+    #[tokio::test]
+    async fn change_stream_delivers_added_then_removed_in_order() {
+        let mut library: Library = Library::new("测试图书馆");
+        let changes = library.subscribe_changes();
+        
+        library.add_book(Book::new("人月神话", "Brooks", 320));
+        assert!(library.remove_book("人月神话").is_some());
+        drop(library);
+        
+        let events: Vec<LibraryEvent> = changes.collect().await;
+        assert_eq!(events, [LibraryEvent::Added("人月神话".to_string()), LibraryEvent::Removed("人月神话".to_string())]);
+    }
+}
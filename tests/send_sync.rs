@@ -0,0 +1,8 @@
+// 05_send_sync.rs 中被注释掉的错误示例：用 trybuild 确认它确实无法通过编译
+
+#[test]
+fn rc_cannot_cross_spawn() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/rc_across_spawn.rs");
+    t.pass("tests/ui/arc_across_spawn.rs");
+}
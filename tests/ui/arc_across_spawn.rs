@@ -0,0 +1,11 @@
+// 换成 Arc 后引用计数是原子的，同样的代码可以通过编译
+use std::sync::Arc;
+
+#[tokio::main]
+async fn main() {
+    let arc = Arc::new(42);
+    let handle = tokio::spawn(async move {
+        println!("{}", arc);
+    });
+    handle.await.unwrap();
+}
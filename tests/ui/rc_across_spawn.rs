@@ -0,0 +1,11 @@
+// Rc 的引用计数不是原子的，不能被 move 进可能在其他线程上运行的任务
+use std::rc::Rc;
+
+#[tokio::main]
+async fn main() {
+    let rc = Rc::new(42);
+    let handle = tokio::spawn(async move {
+        println!("{}", rc);
+    });
+    handle.await.unwrap();
+}